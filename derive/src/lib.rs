@@ -0,0 +1,291 @@
+//! `#[derive(EventStoreEvent)]` implementing `eventstore_tcp::serialization::Event` for structs
+//! and enums. The `serialization` feature of `eventstore-tcp` needs to be enabled.
+//!
+//! Structs are written as a single event type, serialized as JSON. The event type defaults to
+//! the name of the struct and can be changed with `#[event(name = "...")]`. Adding
+//! `#[event(version = 2)]` appends the version to the name as in `OrderPlaced.v2`, so that the
+//! old and new shapes of an event can be told apart when reading.
+//!
+//! A named field marked with `#[event(metadata)]` is written as the JSON metadata of the event
+//! instead of as part of the data. It should also be marked `#[serde(skip)]` so that it is not
+//! duplicated in the data; when reading an event without metadata the field keeps its default
+//! value.
+//!
+//! Enums combine several events: newtype variants delegate to the `Event` implementation of the
+//! type they contain, so they use its event type and metadata, and decoding tries every newtype
+//! variant in order until one knows the event type. Unit variants are written as an empty JSON
+//! object with the event type given by the same `name` and `version` attributes as on structs.
+//!
+//! # Example
+//!
+//! ```rust
+//! extern crate eventstore_tcp;
+//! #[macro_use]
+//! extern crate eventstore_tcp_derive;
+//! #[macro_use]
+//! extern crate serde_derive;
+//!
+//! use eventstore_tcp::serialization::Event;
+//!
+//! #[derive(Serialize, Deserialize, EventStoreEvent)]
+//! #[event(version = 2)]
+//! struct OrderPlaced {
+//!     order: u32,
+//! }
+//!
+//! #[derive(EventStoreEvent)]
+//! enum OrderEvent {
+//!     Placed(OrderPlaced),
+//!     #[event(name = "order-cancelled")]
+//!     Cancelled,
+//! }
+//!
+//! fn main() {
+//!     assert_eq!(OrderPlaced { order: 1 }.event_type(), "OrderPlaced.v2");
+//!     assert_eq!(OrderEvent::Cancelled.event_type(), "order-cancelled");
+//! }
+//! ```
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as Tokens;
+use syn::{Attribute, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta};
+
+/// Implements `eventstore_tcp::serialization::Event`, see the crate documentation.
+#[proc_macro_derive(EventStoreEvent, attributes(event))]
+pub fn derive_event(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = syn::parse_macro_input!(input as DeriveInput);
+
+    let expanded = match expand(&input) {
+        Ok(tokens) => tokens,
+        Err(e) => e.to_compile_error(),
+    };
+
+    expanded.into()
+}
+
+/// The contents of the `#[event(...)]` attributes of an item.
+#[derive(Default)]
+struct Options {
+    name: Option<String>,
+    version: Option<u64>,
+    metadata: bool,
+}
+
+impl Options {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut options = Options::default();
+
+        for attr in attrs {
+            if attr.path.segments.len() != 1 || attr.path.segments[0].ident != "event" {
+                continue;
+            }
+
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                other => return Err(syn::Error::new_spanned(other, "expected #[event(...)]")),
+            };
+
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.ident == "name" => match nv.lit {
+                        Lit::Str(ref s) => options.name = Some(s.value()),
+                        ref lit => return Err(syn::Error::new_spanned(lit, "expected a string")),
+                    },
+                    NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.ident == "version" => match nv.lit {
+                        Lit::Int(ref i) => options.version = Some(i.value()),
+                        ref lit => return Err(syn::Error::new_spanned(lit, "expected an integer")),
+                    },
+                    NestedMeta::Meta(Meta::Word(ref word)) if word == "metadata" => options.metadata = true,
+                    other => return Err(syn::Error::new_spanned(other, "unknown event attribute, expected name, version or metadata")),
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// The event type for an item of the given name.
+    fn event_type(&self, ident: &Ident) -> String {
+        let name = self.name.clone().unwrap_or_else(|| ident.to_string());
+        match self.version {
+            Some(version) => format!("{}.v{}", name, version),
+            None => name,
+        }
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<Tokens> {
+    let options = Options::parse(&input.attrs)?;
+
+    let (event_type, append_to, decode) = match input.data {
+        Data::Struct(ref data) => expand_struct(input, &options, &data.fields)?,
+        Data::Enum(ref data) => {
+            if options.name.is_some() || options.version.is_some() || options.metadata {
+                return Err(syn::Error::new_spanned(&input.ident, "event attributes of enums go on the variants"));
+            }
+            expand_enum(&input.ident, data)?
+        },
+        Data::Union(_) => return Err(syn::Error::new_spanned(&input.ident, "EventStoreEvent cannot be derived for unions")),
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::eventstore_tcp::serialization::Event for #ident #ty_generics #where_clause {
+            fn event_type(&self) -> &'static str {
+                #event_type
+            }
+
+            fn append_to(&self, events: &mut ::eventstore_tcp::builder::WriteEventsBuilder) -> ::std::result::Result<(), ::eventstore_tcp::serialization::EncodeError> {
+                #append_to
+            }
+
+            fn decode(record: &::eventstore_tcp::EventRecord) -> ::std::result::Result<Self, ::eventstore_tcp::serialization::DecodeError> {
+                #decode
+            }
+        }
+    })
+}
+
+fn expand_struct(input: &DeriveInput, options: &Options, fields: &Fields) -> syn::Result<(Tokens, Tokens, Tokens)> {
+    if options.metadata {
+        return Err(syn::Error::new_spanned(&input.ident, "#[event(metadata)] goes on a field"));
+    }
+
+    let event_type = options.event_type(&input.ident);
+
+    let mut metadata = None;
+    for field in fields.iter() {
+        let field_options = Options::parse(&field.attrs)?;
+        if field_options.name.is_some() || field_options.version.is_some() {
+            return Err(syn::Error::new_spanned(field, "only #[event(metadata)] is allowed on fields"));
+        }
+        if !field_options.metadata {
+            continue;
+        }
+        if metadata.is_some() {
+            return Err(syn::Error::new_spanned(field, "only one field can be the metadata"));
+        }
+        match field.ident {
+            Some(ref ident) => metadata = Some(ident.clone()),
+            None => return Err(syn::Error::new_spanned(field, "the metadata field needs to be named")),
+        }
+    }
+
+    let (write_metadata, read_metadata) = match metadata {
+        Some(ident) => (
+            quote! { let builder = builder.json_metadata(&self.#ident)?; },
+            quote! {
+                if record.metadata.as_ref().map(|m| !m.is_empty()).unwrap_or(false) {
+                    value.#ident = record.decode_metadata()?;
+                }
+            },
+        ),
+        None => (quote!(), quote!()),
+    };
+
+    let append_to = quote! {
+        let builder = events.new_event()
+            .event_type(#event_type)
+            .json_data(self)?;
+        #write_metadata
+        builder.done();
+        Ok(())
+    };
+
+    let decode = quote! {
+        if record.event_type != #event_type {
+            return Err(::eventstore_tcp::serialization::DecodeError::unknown_event_type(record));
+        }
+        #[allow(unused_mut)]
+        let mut value: Self = record.decode_data()?;
+        #read_metadata
+        Ok(value)
+    };
+
+    Ok((quote!(#event_type), append_to, decode))
+}
+
+fn expand_enum(name: &Ident, data: &syn::DataEnum) -> syn::Result<(Tokens, Tokens, Tokens)> {
+    let mut event_types = Vec::new();
+    let mut appends = Vec::new();
+    let mut decodes = Vec::new();
+
+    for variant in &data.variants {
+        let options = Options::parse(&variant.attrs)?;
+        if options.metadata {
+            return Err(syn::Error::new_spanned(variant, "#[event(metadata)] is only supported on struct fields"));
+        }
+
+        let ident = &variant.ident;
+
+        match variant.fields {
+            Fields::Unnamed(ref fields) if fields.unnamed.len() == 1 => {
+                if options.name.is_some() || options.version.is_some() {
+                    return Err(syn::Error::new_spanned(variant, "the event type of a newtype variant is set on the type it contains"));
+                }
+
+                let ty = &fields.unnamed[0].ty;
+                event_types.push(quote! {
+                    #name::#ident(ref inner) => ::eventstore_tcp::serialization::Event::event_type(inner)
+                });
+                appends.push(quote! {
+                    #name::#ident(ref inner) => ::eventstore_tcp::serialization::Event::append_to(inner, events)?,
+                });
+                decodes.push(quote! {
+                    match <#ty as ::eventstore_tcp::serialization::Event>::decode(record) {
+                        Ok(inner) => return Ok(#name::#ident(inner)),
+                        Err(ref e) if e.is_unknown_event_type() => {},
+                        Err(e) => return Err(e),
+                    }
+                });
+            },
+            Fields::Unit => {
+                let event_type = options.event_type(ident);
+                event_types.push(quote! { #name::#ident => #event_type });
+                appends.push(quote! {
+                    #name::#ident => {
+                        events.new_event()
+                            .event_type(#event_type)
+                            .data(&b"{}"[..])
+                            .data_content_type(::eventstore_tcp::ContentType::Json)
+                            .done();
+                    }
+                });
+                decodes.push(quote! {
+                    if record.event_type == #event_type {
+                        return Ok(#name::#ident);
+                    }
+                });
+            },
+            _ => return Err(syn::Error::new_spanned(variant, "only newtype and unit variants are supported")),
+        }
+    }
+
+    let event_type = quote! {
+        match *self {
+            #(#event_types,)*
+        }
+    };
+
+    let append_to = quote! {
+        match *self {
+            #(#appends)*
+        }
+        Ok(())
+    };
+
+    let decode = quote! {
+        #(#decodes)*
+        Err(::eventstore_tcp::serialization::DecodeError::unknown_event_type(record))
+    };
+
+    Ok((event_type, append_to, decode))
+}
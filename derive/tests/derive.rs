@@ -0,0 +1,86 @@
+extern crate eventstore_tcp;
+#[macro_use]
+extern crate eventstore_tcp_derive;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+
+use eventstore_tcp::{Builder, EventRecord, ExpectedVersion};
+use eventstore_tcp::fixtures::recorded_from_write;
+use eventstore_tcp::serialization::Event;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, EventStoreEvent)]
+#[event(version = 2)]
+struct OrderPlaced {
+    order: u32,
+    items: Vec<String>,
+    #[serde(skip)]
+    #[event(metadata)]
+    correlation: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, EventStoreEvent)]
+#[event(name = "order-shipped")]
+struct OrderShipped(String);
+
+#[derive(Debug, PartialEq, EventStoreEvent)]
+enum OrderEvent {
+    Placed(OrderPlaced),
+    Shipped(OrderShipped),
+    #[event(name = "order-cancelled", version = 3)]
+    Cancelled,
+}
+
+/// Appends the values and returns the events as the server would return them.
+fn round_trip<E: Event>(values: &[E]) -> Vec<EventRecord<'static>> {
+    let mut builder = Builder::write_events();
+    builder.stream_id("orders-1").expected_version(ExpectedVersion::Any);
+    for value in values {
+        value.append_to(&mut builder).unwrap();
+    }
+
+    recorded_from_write(&mut builder)
+}
+
+#[test]
+fn structs_with_versions_and_metadata() {
+    let placed = OrderPlaced {
+        order: 42,
+        items: vec!["book".into()],
+        correlation: Some("abc".into()),
+    };
+
+    let records = round_trip(&[placed]);
+    assert_eq!(records[0].event_type, "OrderPlaced.v2");
+    assert_eq!(records[0].metadata.as_ref().map(|m| &m[..]), Some(&b"\"abc\""[..]));
+
+    let decoded = OrderPlaced::decode(&records[0]).unwrap();
+    assert_eq!(decoded.order, 42);
+    assert_eq!(decoded.correlation, Some("abc".into()));
+
+    // other types, including other versions, are not decoded
+    let shipped = round_trip(&[OrderShipped("today".into())]);
+    assert_eq!(shipped[0].event_type, "order-shipped");
+    assert_eq!(OrderShipped::decode(&shipped[0]).unwrap(), OrderShipped("today".into()));
+    assert!(OrderPlaced::decode(&shipped[0]).is_err());
+}
+
+#[test]
+fn enums_map_variants_to_types() {
+    let values = vec![
+        OrderEvent::Placed(OrderPlaced { order: 1, items: vec![], correlation: None }),
+        OrderEvent::Shipped(OrderShipped("today".into())),
+        OrderEvent::Cancelled,
+    ];
+
+    let records = round_trip(&values);
+    let types = records.iter().map(|r| r.event_type.clone().into_owned()).collect::<Vec<_>>();
+    assert_eq!(types, vec!["OrderPlaced.v2", "order-shipped", "order-cancelled.v3"]);
+
+    let decoded = records.iter().map(OrderEvent::decode).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(decoded, values);
+
+    let mut unknown = records[2].clone();
+    unknown.event_type = "OrderPlaced.v1".into();
+    assert_eq!(OrderEvent::decode(&unknown).unwrap_err().event_type, "OrderPlaced.v1");
+}
@@ -0,0 +1,62 @@
+extern crate tokio_core;
+
+#[macro_use]
+extern crate clap;
+extern crate eventstore_tcp;
+
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::process;
+
+use clap::{Arg, App};
+use tokio_core::reactor::Core;
+
+use eventstore_tcp::memory::MemoryStore;
+use eventstore_tcp::server::Server;
+
+fn main() {
+    let matches = App::new(crate_name!())
+        .version(crate_version!())
+        .about(crate_description!())
+        .author(crate_authors!("\n"))
+        .arg(Arg::with_name("hostname")
+                .short("h")
+                .long("host")
+                .value_name("HOST")
+                .takes_value(true)
+                .help("The address to listen on, default: 127.0.0.1"))
+        .arg(Arg::with_name("port")
+                .short("p")
+                .long("port")
+                .value_name("PORT")
+                .takes_value(true)
+                .help("The port to listen on, 0 for any free port, default: 1113"))
+        .get_matches();
+
+    let host = value_t!(matches, "hostname", IpAddr).unwrap_or_else(|e| {
+        if e.kind == clap::ErrorKind::ArgumentNotFound { "127.0.0.1".parse().unwrap() } else { e.exit() }
+    });
+    let port = value_t!(matches, "port", u16).unwrap_or_else(|e| {
+        if e.kind == clap::ErrorKind::ArgumentNotFound { 1113 } else { e.exit() }
+    });
+
+    let mut core = Core::new().unwrap();
+    let server = Server::new(MemoryStore::new(), &core.handle());
+
+    let listening = match server.listen(&SocketAddr::new(host, port)) {
+        Ok(listening) => listening,
+        Err(e) => {
+            eprintln!("Failed to listen on {}:{}: {}", host, port, e);
+            process::exit(1);
+        }
+    };
+
+    // the only line written to stdout, so that scripts can find the ephemeral port
+    println!("{}", listening.local_addr());
+    io::stdout().flush().unwrap();
+
+    if let Err(e) = core.run(listening) {
+        eprintln!("Failed to accept connections: {}", e);
+        process::exit(1);
+    }
+}
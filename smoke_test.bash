@@ -1,6 +1,7 @@
 #!/bin/bash
 #
-# simple build all and test testclient against inmemory ES script
+# simple build all and test testclient against the in-memory server of memory-server/, runs
+# fully offline
 
 set -eu
 set -o pipefail
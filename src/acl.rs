@@ -0,0 +1,583 @@
+//! Access control lists of streams and the default ACLs of the `$settings` stream.
+//!
+//! Every stream can have an `$acl` in its metadata (see `metadata::StreamMetadata`) listing the
+//! users and groups allowed to do each `Operation`. Roles missing from the stream ACL fall back
+//! to the defaults in the `$settings` stream: `$userStreamAcl` for normal streams and
+//! `$systemStreamAcl` for streams starting with `$`. Members of `$admins` are always allowed.
+//!
+//! When a request fails with `AccessDenied`, `diagnose_access_denied` reads the ACLs involved and
+//! explains which roles would have been allowed.
+
+use std::io;
+use std::fmt;
+use std::collections::BTreeMap;
+
+use futures::Future;
+use json::JsonValue;
+use tokio_service::Service;
+
+use adapted::{ReadEventError, ReadStreamError, ReadAllError, ReadAllFailure, WriteEventsCompleted, WriteEventsError, WriteEventsFailure};
+use metadata::{self, MetadataError, StreamMetadata};
+use package::Package;
+use {ExpectedVersion, StreamVersion, UsernamePassword};
+use errors::{Error, ErrorKind};
+
+/// The stream holding the system settings.
+pub const SETTINGS_STREAM: &'static str = "$settings";
+
+/// The event type of the system settings events.
+pub const SETTINGS_EVENT_TYPE: &'static str = "$settings";
+
+/// Role allowing everyone, including unauthenticated users.
+pub const ALL: &'static str = "$all";
+
+/// The group of administrators, which are always allowed.
+pub const ADMINS: &'static str = "$admins";
+
+/// The operations controlled by an ACL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Reading events, `$r`.
+    Read,
+    /// Writing events, `$w`.
+    Write,
+    /// Deleting the stream, `$d`.
+    Delete,
+    /// Reading the metadata, `$mr`.
+    MetadataRead,
+    /// Writing the metadata, `$mw`.
+    MetadataWrite,
+}
+
+impl Operation {
+    /// The key of the role in the JSON representation.
+    pub fn key(&self) -> &'static str {
+        match *self {
+            Operation::Read => "$r",
+            Operation::Write => "$w",
+            Operation::Delete => "$d",
+            Operation::MetadataRead => "$mr",
+            Operation::MetadataWrite => "$mw",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::Delete => "delete",
+            Operation::MetadataRead => "metadata read",
+            Operation::MetadataWrite => "metadata write",
+        })
+    }
+}
+
+/// Access control list of a stream. Every role is a list of users or groups, `None` when not set.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StreamAcl {
+    /// `$r`: who can read the stream.
+    pub read: Option<Vec<String>>,
+    /// `$w`: who can write to the stream.
+    pub write: Option<Vec<String>>,
+    /// `$d`: who can delete the stream.
+    pub delete: Option<Vec<String>>,
+    /// `$mr`: who can read the metadata of the stream.
+    pub metadata_read: Option<Vec<String>>,
+    /// `$mw`: who can write the metadata of the stream.
+    pub metadata_write: Option<Vec<String>>,
+}
+
+impl StreamAcl {
+    /// ACL with every role set to the same users or groups.
+    pub fn uniform<S: Into<String> + Clone>(roles: &[S]) -> Self {
+        let roles = roles.iter().cloned().map(Into::into).collect::<Vec<String>>();
+        StreamAcl {
+            read: Some(roles.clone()),
+            write: Some(roles.clone()),
+            delete: Some(roles.clone()),
+            metadata_read: Some(roles.clone()),
+            metadata_write: Some(roles),
+        }
+    }
+
+    /// The users or groups of the role for the operation.
+    pub fn role(&self, op: Operation) -> Option<&[String]> {
+        match op {
+            Operation::Read => self.read.as_ref(),
+            Operation::Write => self.write.as_ref(),
+            Operation::Delete => self.delete.as_ref(),
+            Operation::MetadataRead => self.metadata_read.as_ref(),
+            Operation::MetadataWrite => self.metadata_write.as_ref(),
+        }.map(Vec::as_slice)
+    }
+
+    /// Parses the ACL from the JSON object. Roles can be given as a single string or as an array
+    /// of strings.
+    pub fn from_json(value: &JsonValue) -> Result<Self, Error> {
+        if !value.is_object() {
+            bail!(invalid("$acl must be an object"));
+        }
+
+        let role = |key: &str| -> Result<Option<Vec<String>>, Error> {
+            let value = &value[key];
+
+            if value.is_null() {
+                return Ok(None);
+            }
+
+            if let Some(s) = value.as_str() {
+                return Ok(Some(vec![s.to_owned()]));
+            }
+
+            if value.is_array() {
+                return value.members()
+                    .map(|x| x.as_str()
+                        .map(str::to_owned)
+                        .ok_or_else(|| invalid(format!("{} must contain only strings", key)).into()))
+                    .collect::<Result<Vec<_>, Error>>()
+                    .map(Some);
+            }
+
+            bail!(invalid(format!("{} must be a string or an array of strings", key)))
+        };
+
+        Ok(StreamAcl {
+            read: role("$r")?,
+            write: role("$w")?,
+            delete: role("$d")?,
+            metadata_read: role("$mr")?,
+            metadata_write: role("$mw")?,
+        })
+    }
+
+    /// Serializes the ACL into a JSON object, leaving out the unset roles.
+    pub fn to_json(&self) -> JsonValue {
+        let mut obj = JsonValue::new_object();
+
+        for op in &[Operation::Read, Operation::Write, Operation::Delete, Operation::MetadataRead, Operation::MetadataWrite] {
+            if let Some(roles) = self.role(*op) {
+                obj[op.key()] = roles.iter().map(String::as_str).map(JsonValue::from).collect::<Vec<_>>().into();
+            }
+        }
+
+        obj
+    }
+}
+
+fn invalid<S: Into<String>>(reason: S) -> ErrorKind {
+    ErrorKind::InvalidStreamMetadata(reason.into())
+}
+
+/// Contents of the `$settings` stream: the default ACLs for streams without an `$acl` role.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SystemSettings {
+    /// `$userStreamAcl`: defaults for streams not starting with `$`.
+    pub user_stream_acl: Option<StreamAcl>,
+    /// `$systemStreamAcl`: defaults for streams starting with `$`.
+    pub system_stream_acl: Option<StreamAcl>,
+    /// Any other properties, written back as they were read.
+    pub custom: BTreeMap<String, JsonValue>,
+}
+
+impl SystemSettings {
+    /// Parses the settings from the JSON data of a `$settings` event.
+    pub fn from_json(data: &[u8]) -> Result<Self, Error> {
+        let s = ::std::str::from_utf8(data)?;
+        let parsed = ::json::parse(s).map_err(|e| invalid(format!("{}", e)))?;
+
+        if !parsed.is_object() {
+            bail!(invalid("expected a json object"));
+        }
+
+        let acl = |key: &str| -> Result<Option<StreamAcl>, Error> {
+            if parsed[key].is_null() {
+                Ok(None)
+            } else {
+                StreamAcl::from_json(&parsed[key]).map(Some)
+            }
+        };
+
+        let custom = parsed.entries()
+            .filter(|&(key, _)| key != "$userStreamAcl" && key != "$systemStreamAcl")
+            .map(|(key, value)| (key.to_owned(), value.clone()))
+            .collect();
+
+        Ok(SystemSettings {
+            user_stream_acl: acl("$userStreamAcl")?,
+            system_stream_acl: acl("$systemStreamAcl")?,
+            custom: custom,
+        })
+    }
+
+    /// Serializes the settings into JSON for a `$settings` event.
+    pub fn to_json(&self) -> String {
+        let mut obj = JsonValue::new_object();
+
+        for (key, value) in &self.custom {
+            obj[key.as_str()] = value.clone();
+        }
+
+        if let Some(ref acl) = self.user_stream_acl {
+            obj["$userStreamAcl"] = acl.to_json();
+        }
+
+        if let Some(ref acl) = self.system_stream_acl {
+            obj["$systemStreamAcl"] = acl.to_json();
+        }
+
+        obj.dump()
+    }
+}
+
+/// System settings together with the version of the `$settings` stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionedSettings {
+    /// The settings, or the default if none have been written.
+    pub settings: SystemSettings,
+    /// Event number of the settings event, `None` if the stream does not exist.
+    pub version: Option<StreamVersion>,
+}
+
+impl VersionedSettings {
+    /// Expected version for replacing exactly these settings with `set_system_settings`.
+    pub fn expected_version(&self) -> ExpectedVersion {
+        match self.version {
+            Some(version) => ExpectedVersion::Exact(version),
+            None => ExpectedVersion::NoStream,
+        }
+    }
+}
+
+/// Reads the latest system settings. Usually requires admin credentials.
+pub fn get_system_settings<S>(client: &S, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = VersionedSettings, Error = MetadataError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static
+{
+    Box::new(metadata::read_last_event(client, SETTINGS_STREAM.to_owned(), credentials).and_then(|found| -> Result<VersionedSettings, MetadataError> {
+        Ok(match found {
+            Some((version, data)) => VersionedSettings {
+                settings: SystemSettings::from_json(&data)?,
+                version: Some(version),
+            },
+            None => VersionedSettings {
+                settings: SystemSettings::default(),
+                version: None,
+            },
+        })
+    }))
+}
+
+/// Replaces the system settings. Requires admin credentials.
+pub fn set_system_settings<S>(client: &S, expected_version: ExpectedVersion, settings: &SystemSettings, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = WriteEventsCompleted, Error = MetadataError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static
+{
+    metadata::write_json_event(client, SETTINGS_STREAM.to_owned(), expected_version, SETTINGS_EVENT_TYPE, settings.to_json(), credentials)
+}
+
+/// Where the effective role of an ACL came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclSource {
+    /// The `$acl` of the stream metadata.
+    StreamMetadata,
+    /// `$userStreamAcl` of `$settings`.
+    UserStreamDefault,
+    /// `$systemStreamAcl` of `$settings`.
+    SystemStreamDefault,
+    /// The built-in default for normal streams: everyone is allowed.
+    BuiltinUserDefault,
+    /// The built-in default for system streams: only `$admins` are allowed.
+    BuiltinSystemDefault,
+}
+
+impl fmt::Display for AclSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            AclSource::StreamMetadata => "the stream metadata",
+            AclSource::UserStreamDefault => "$userStreamAcl of $settings",
+            AclSource::SystemStreamDefault => "$systemStreamAcl of $settings",
+            AclSource::BuiltinUserDefault => "the built-in default for user streams",
+            AclSource::BuiltinSystemDefault => "the built-in default for system streams",
+        })
+    }
+}
+
+/// Resolves the users and groups allowed to do the operation on the stream, and where they came
+/// from. Operations on a metastream `$$stream` are resolved as metadata operations of `stream`.
+pub fn effective_acl(stream: &str, op: Operation, metadata: &StreamMetadata, settings: &SystemSettings) -> (Vec<String>, AclSource) {
+    let (stream, op) = effective_target(stream, op);
+
+    if let Some(roles) = metadata.acl.as_ref().and_then(|acl| acl.role(op)) {
+        return (roles.to_vec(), AclSource::StreamMetadata);
+    }
+
+    let system = stream.starts_with('$');
+    let (default, source) = if system {
+        (&settings.system_stream_acl, AclSource::SystemStreamDefault)
+    } else {
+        (&settings.user_stream_acl, AclSource::UserStreamDefault)
+    };
+
+    if let Some(roles) = default.as_ref().and_then(|acl| acl.role(op)) {
+        return (roles.to_vec(), source);
+    }
+
+    if system {
+        (vec![ADMINS.to_owned()], AclSource::BuiltinSystemDefault)
+    } else {
+        (vec![ALL.to_owned()], AclSource::BuiltinUserDefault)
+    }
+}
+
+/// Maps operations on a metastream to the metadata operations of the stream.
+fn effective_target(stream: &str, op: Operation) -> (&str, Operation) {
+    if stream.starts_with("$$") {
+        let op = match op {
+            Operation::Read => Operation::MetadataRead,
+            Operation::Write | Operation::Delete => Operation::MetadataWrite,
+            other => other,
+        };
+        (&stream[2..], op)
+    } else {
+        (stream, op)
+    }
+}
+
+/// Errors which can be caused by an ACL.
+pub trait DeniedOperation {
+    /// The operation which was denied, `None` if the error is not `AccessDenied`.
+    fn denied_operation(&self) -> Option<Operation>;
+}
+
+impl<'a> DeniedOperation for ReadEventError<'a> {
+    fn denied_operation(&self) -> Option<Operation> {
+        match *self {
+            ReadEventError::AccessDenied => Some(Operation::Read),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> DeniedOperation for ReadStreamError<'a> {
+    fn denied_operation(&self) -> Option<Operation> {
+        match *self {
+            ReadStreamError::AccessDenied => Some(Operation::Read),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> DeniedOperation for ReadAllError<'a> {
+    fn denied_operation(&self) -> Option<Operation> {
+        match *self {
+            ReadAllError::AccessDenied => Some(Operation::Read),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> DeniedOperation for ReadAllFailure<'a> {
+    fn denied_operation(&self) -> Option<Operation> {
+        self.error.denied_operation()
+    }
+}
+
+impl DeniedOperation for WriteEventsFailure {
+    fn denied_operation(&self) -> Option<Operation> {
+        match *self {
+            WriteEventsFailure::AccessDenied => Some(Operation::Write),
+            _ => None,
+        }
+    }
+}
+
+impl DeniedOperation for WriteEventsError {
+    fn denied_operation(&self) -> Option<Operation> {
+        self.failure.denied_operation()
+    }
+}
+
+/// Explanation of a denied operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessDiagnosis {
+    /// The stream the operation was denied on.
+    pub stream: String,
+    /// The denied operation.
+    pub operation: Operation,
+    /// The user whose request was denied, `None` for unauthenticated requests.
+    pub user: Option<String>,
+    /// Users and groups allowed by the effective ACL.
+    pub allowed: Vec<String>,
+    /// Where the effective ACL came from.
+    pub source: AclSource,
+}
+
+impl AccessDiagnosis {
+    /// Explains the denial using the ACLs involved.
+    pub fn new(stream: &str, op: Operation, user: Option<&str>, metadata: &StreamMetadata, settings: &SystemSettings) -> Self {
+        let (allowed, source) = effective_acl(stream, op, metadata, settings);
+
+        AccessDiagnosis {
+            stream: stream.to_owned(),
+            operation: op,
+            user: user.map(str::to_owned),
+            allowed: allowed,
+            source: source,
+        }
+    }
+
+    /// `true` if the user is explicitly listed in the effective ACL.
+    pub fn user_listed(&self) -> bool {
+        match self.user {
+            Some(ref user) => self.allowed.iter().any(|x| x == user),
+            None => false,
+        }
+    }
+
+    /// `true` if the effective ACL allows everyone.
+    pub fn allows_everyone(&self) -> bool {
+        self.allowed.iter().any(|x| x == ALL)
+    }
+}
+
+impl fmt::Display for AccessDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (_, op) = effective_target(&self.stream, self.operation);
+
+        write!(f, "{} access to stream {:?} was denied for ", self.operation, self.stream)?;
+        match self.user {
+            Some(ref user) => write!(f, "user {:?}", user)?,
+            None => f.write_str("an unauthenticated request")?,
+        }
+
+        write!(f, ". The effective {} role from {} allows ", op.key(), self.source)?;
+        if self.allowed.is_empty() {
+            f.write_str("only $admins")?;
+        } else {
+            write!(f, "{:?}", self.allowed)?;
+        }
+        f.write_str(". ")?;
+
+        if self.allows_everyone() {
+            f.write_str("As the role allows everyone, the credentials were likely not accepted or the ACL has changed since.")
+        } else if self.user.is_none() {
+            f.write_str("Unauthenticated requests are only allowed by $all; send credentials with the request.")
+        } else if self.user_listed() {
+            f.write_str("The user is listed, so the ACL has likely changed since.")
+        } else {
+            f.write_str("The user must be listed, be a member of one of the listed groups or be a member of $admins.")
+        }
+    }
+}
+
+/// Reads the stream metadata and the system settings using `credentials`, which need to be
+/// allowed to read both (usually an admin), and explains why `user` was denied. Resolves to
+/// `None` if the error was not `AccessDenied`.
+pub fn diagnose_access_denied<S, E>(client: &S, stream: &str, error: &E, user: Option<&UsernamePassword>, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = Option<AccessDiagnosis>, Error = MetadataError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static,
+          E: DeniedOperation
+{
+    let op = match error.denied_operation() {
+        Some(op) => op,
+        None => return Box::new(::futures::future::ok(None)),
+    };
+
+    let (target, _) = effective_target(stream, op);
+    let stream = stream.to_owned();
+    let user = user.map(|x| x.0.clone().into_owned());
+
+    let metadata = metadata::get_stream_metadata(client, target, credentials.clone());
+    let settings = get_system_settings(client, credentials);
+
+    Box::new(metadata.join(settings).map(move |(metadata, settings)| {
+        Some(AccessDiagnosis::new(&stream, op, user.as_ref().map(String::as_str), &metadata.metadata, &settings.settings))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use json;
+
+    use super::*;
+    use metadata::StreamMetadata;
+    use StreamVersion;
+
+    #[test]
+    fn acl_json_round_trip() {
+        let parsed = json::parse(r#"{"$r":"$all","$w":["ops","alice"],"$mw":[]}"#).unwrap();
+        let acl = StreamAcl::from_json(&parsed).unwrap();
+
+        assert_eq!(acl.read, Some(vec!["$all".to_owned()]));
+        assert_eq!(acl.write, Some(vec!["ops".to_owned(), "alice".to_owned()]));
+        assert_eq!(acl.delete, None);
+        assert_eq!(acl.metadata_write, Some(vec![]));
+
+        assert_eq!(StreamAcl::from_json(&acl.to_json()).unwrap(), acl);
+        assert!(StreamAcl::from_json(&json::parse(r#"{"$r":1}"#).unwrap()).is_err());
+    }
+
+    #[test]
+    fn settings_json_round_trip() {
+        let settings = SystemSettings {
+            user_stream_acl: Some(StreamAcl::uniform(&["$all"])),
+            system_stream_acl: Some(StreamAcl::uniform(&["$admins", "ops"])),
+            custom: BTreeMap::new(),
+        };
+
+        let parsed = SystemSettings::from_json(settings.to_json().as_bytes()).unwrap();
+        assert_eq!(parsed, settings);
+
+        let unknown = r#"{"$userStreamAcl":{"$r":["$all"]},"$unknown":{"a":[1,2]},"app":"x"}"#;
+        let parsed = SystemSettings::from_json(unknown.as_bytes()).unwrap();
+        assert_eq!(parsed.custom.len(), 2);
+        assert_eq!(parsed.custom["app"], "x");
+
+        let written = json::parse(&parsed.to_json()).unwrap();
+        assert_eq!(written["$unknown"]["a"][1], 2);
+        assert_eq!(SystemSettings::from_json(written.dump().as_bytes()).unwrap(), parsed);
+
+        let versioned = VersionedSettings { settings: parsed, version: Some(StreamVersion::from(2u32)) };
+        assert_eq!(versioned.expected_version(), ExpectedVersion::Exact(StreamVersion::from(2u32)));
+    }
+
+    #[test]
+    fn effective_acl_fallbacks() {
+        let mut metadata = StreamMetadata::default();
+        let mut settings = SystemSettings::default();
+
+        assert_eq!(effective_acl("orders", Operation::Write, &metadata, &settings),
+                   (vec!["$all".to_owned()], AclSource::BuiltinUserDefault));
+        assert_eq!(effective_acl("$stats", Operation::Read, &metadata, &settings),
+                   (vec!["$admins".to_owned()], AclSource::BuiltinSystemDefault));
+
+        settings.user_stream_acl = Some(StreamAcl { write: Some(vec!["writers".into()]), ..Default::default() });
+        assert_eq!(effective_acl("orders", Operation::Write, &metadata, &settings),
+                   (vec!["writers".to_owned()], AclSource::UserStreamDefault));
+
+        metadata.acl = Some(StreamAcl { metadata_write: Some(vec!["ops".into()]), ..Default::default() });
+        assert_eq!(effective_acl("$$orders", Operation::Write, &metadata, &settings),
+                   (vec!["ops".to_owned()], AclSource::StreamMetadata));
+    }
+
+    #[test]
+    fn diagnosis_message() {
+        let metadata = StreamMetadata {
+            acl: Some(StreamAcl { write: Some(vec!["tenant-a".into()]), ..Default::default() }),
+            ..Default::default()
+        };
+
+        let diagnosis = AccessDiagnosis::new("tenant-b-orders", Operation::Write, Some("bob"), &metadata, &SystemSettings::default());
+        assert!(!diagnosis.user_listed());
+
+        let msg = diagnosis.to_string();
+        assert!(msg.contains("write access to stream \"tenant-b-orders\" was denied for user \"bob\""), msg);
+        assert!(msg.contains("$w role from the stream metadata allows [\"tenant-a\"]"), msg);
+        assert!(msg.contains("must be listed"), msg);
+
+        assert_eq!(WriteEventsFailure::AccessDenied.denied_operation(), Some(Operation::Write));
+        assert_eq!(ReadStreamError::NoStream.denied_operation(), None);
+    }
+}
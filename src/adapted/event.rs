@@ -0,0 +1,369 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use uuid::Uuid;
+
+use raw::client_messages::{self, EventRecord};
+use errors::{Error, ErrorKind};
+use {ContentType, LogPosition, StreamVersion};
+
+/// .NET ticks (100ns since 0001-01-01) at the unix epoch.
+const TICKS_AT_EPOCH: i64 = 621_355_968_000_000_000;
+const NANOS_PER_TICK: u32 = 100;
+const TICKS_PER_SECOND: i64 = 10_000_000;
+const TICKS_PER_MILLI: i64 = 10_000;
+
+/// An event read from a stream, decoded from the raw `EventRecord`. The contents are owned so
+/// that the events can be kept around after the response has been dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedEvent {
+    /// The stream the event was written to
+    pub stream_id: String,
+    /// The event number of the event in the stream
+    pub number: StreamVersion,
+    /// The id given to the event when writing, used for idempotent writes
+    pub event_id: Uuid,
+    /// The type of the event
+    pub event_type: String,
+    /// The content type of `data`
+    pub data_content_type: ContentType,
+    /// The content type of `metadata`
+    pub metadata_content_type: ContentType,
+    /// The payload of the event
+    pub data: Bytes,
+    /// The metadata of the event, if any was written
+    pub metadata: Option<Bytes>,
+    /// The time the event was written, when sent by the server
+    pub created: Option<SystemTime>,
+    /// The fields `created` was read from, and will be written to by `as_raw`
+    pub created_fields: CreatedFields,
+}
+
+/// The fields of the raw `EventRecord` carrying the creation time of an event: the server sends
+/// both, but either can be missing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CreatedFields {
+    /// Only `created`, .NET ticks
+    Ticks,
+    /// Only `created_epoch`, milliseconds since the unix epoch
+    EpochMillis,
+    /// Both of the fields, as sent by the server
+    Both,
+}
+
+impl Default for CreatedFields {
+    fn default() -> Self {
+        CreatedFields::Both
+    }
+}
+
+impl RecordedEvent {
+    /// Converts the event back to the raw representation, borrowing the payloads. The `created`
+    /// time is written to the fields given by `created_fields`, or left out if it cannot be
+    /// represented as .NET ticks.
+    pub fn as_raw(&self) -> EventRecord {
+        let ticks = self.created.and_then(ticks_from_time);
+        let millis = ticks.map(|ticks| (ticks - TICKS_AT_EPOCH) / TICKS_PER_MILLI);
+
+        let (created, created_epoch) = match self.created_fields {
+            CreatedFields::Ticks => (ticks, None),
+            CreatedFields::EpochMillis => (None, millis),
+            CreatedFields::Both => (ticks, millis),
+        };
+
+        EventRecord {
+            event_stream_id: Cow::Borrowed(&self.stream_id),
+            event_number: self.number.into(),
+            event_id: Cow::Borrowed(self.event_id.as_bytes()),
+            event_type: Cow::Borrowed(&self.event_type),
+            data_content_type: self.data_content_type.into(),
+            metadata_content_type: self.metadata_content_type.into(),
+            data: Cow::Borrowed(&self.data),
+            metadata: self.metadata.as_ref().map(|x| Cow::Borrowed(&x[..])),
+            created: created,
+            created_epoch: created_epoch,
+        }
+    }
+}
+
+impl<'a, 'b> TryFrom<&'a EventRecord<'b>> for RecordedEvent {
+    type Error = Error;
+
+    fn try_from(record: &'a EventRecord<'b>) -> Result<Self, Self::Error> {
+        let event_id = Uuid::from_bytes(&record.event_id)
+            .map_err(|_| ErrorKind::InvalidEventId(record.event_id.len()))?;
+
+        // the ticks are more precise, the milliseconds are only used if there are no ticks
+        let (ticks, created_fields) = match (record.created, record.created_epoch) {
+            (Some(ticks), Some(_)) => (Some(ticks), CreatedFields::Both),
+            (Some(ticks), None) => (Some(ticks), CreatedFields::Ticks),
+            (None, Some(millis)) => {
+                let ticks = millis.checked_mul(TICKS_PER_MILLI)
+                    .and_then(|x| x.checked_add(TICKS_AT_EPOCH))
+                    .ok_or(ErrorKind::InvalidCreationTime(millis))?;
+                (Some(ticks), CreatedFields::EpochMillis)
+            },
+            (None, None) => (None, CreatedFields::default()),
+        };
+
+        let created = match ticks {
+            Some(ticks) => Some(time_from_ticks(ticks).ok_or(ErrorKind::InvalidCreationTime(ticks))?),
+            None => None,
+        };
+
+        Ok(RecordedEvent {
+            stream_id: record.event_stream_id.clone().into_owned(),
+            number: StreamVersion::try_from(record.event_number)?,
+            event_id: event_id,
+            event_type: record.event_type.clone().into_owned(),
+            data_content_type: record.data_content_type.into(),
+            metadata_content_type: record.metadata_content_type.into(),
+            data: Bytes::from(&record.data[..]),
+            metadata: record.metadata.as_ref().map(|x| Bytes::from(&x[..])),
+            created: created,
+            created_fields: created_fields,
+        })
+    }
+}
+
+/// `None` if the ticks do not fit into a `SystemTime` on this platform.
+fn time_from_ticks(ticks: i64) -> Option<SystemTime> {
+    let since_epoch = ticks.checked_sub(TICKS_AT_EPOCH)?;
+    let abs = since_epoch.checked_abs()?;
+    let duration = Duration::new((abs / TICKS_PER_SECOND) as u64, (abs % TICKS_PER_SECOND) as u32 * NANOS_PER_TICK);
+
+    if since_epoch >= 0 {
+        UNIX_EPOCH.checked_add(duration)
+    } else {
+        UNIX_EPOCH.checked_sub(duration)
+    }
+}
+
+/// `None` if the time does not fit into an `i64` of ticks.
+fn ticks_from_time(time: SystemTime) -> Option<i64> {
+    let ticks = |d: Duration| i64::try_from(d.as_secs()).ok()
+        .and_then(|secs| secs.checked_mul(TICKS_PER_SECOND))
+        .and_then(|x| x.checked_add((d.subsec_nanos() / NANOS_PER_TICK) as i64));
+
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => ticks(d).and_then(|x| TICKS_AT_EPOCH.checked_add(x)),
+        Err(e) => ticks(e.duration()).and_then(|x| TICKS_AT_EPOCH.checked_sub(x)),
+    }
+}
+
+/// Position of an event in the `$all` stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Position {
+    /// Position where the transaction of the event was committed
+    pub commit: LogPosition,
+    /// Position where the event was stored
+    pub prepare: LogPosition,
+}
+
+/// An event as returned by reads, which can be a link to an event in another stream. When links
+/// are resolved (`resolve_link_tos`), `event` is the target of the link and `link` is the link
+/// event itself. Otherwise `event` is the event found in the stream, which may be a link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedEvent {
+    /// The resolved event, or the event itself if it was not a link
+    pub event: RecordedEvent,
+    /// The link event, if the read event was a resolved link
+    pub link: Option<RecordedEvent>,
+    /// The position of the event in `$all`, only available when reading `$all`
+    pub position: Option<Position>,
+}
+
+impl ResolvedEvent {
+    /// `true` if the read event was a link which was resolved
+    pub fn is_resolved(&self) -> bool {
+        self.link.is_some()
+    }
+
+    /// The event found in the stream which was read: the link if there was one, the event
+    /// otherwise
+    pub fn original_event(&self) -> &RecordedEvent {
+        self.link.as_ref().unwrap_or(&self.event)
+    }
+
+    /// The stream which was read
+    pub fn original_stream_id(&self) -> &str {
+        &self.original_event().stream_id
+    }
+
+    /// The event number in the stream which was read, for paging or checkpointing
+    pub fn original_event_number(&self) -> StreamVersion {
+        self.original_event().number
+    }
+
+    /// The position in `$all` of the event found in the stream, only available when reading `$all`
+    pub fn original_position(&self) -> Option<Position> {
+        self.position
+    }
+
+    /// Converts the event back to the raw representation used by stream reads, borrowing the
+    /// payloads
+    pub fn as_raw(&self) -> client_messages::ResolvedIndexedEvent {
+        client_messages::ResolvedIndexedEvent {
+            event: self.event.as_raw(),
+            link: self.link.as_ref().map(RecordedEvent::as_raw),
+        }
+    }
+
+    /// Converts the event back to the raw representation used by `$all` reads, borrowing the
+    /// payloads. A missing `position` is written as `LogPosition::Last`.
+    pub fn as_raw_positioned(&self) -> client_messages::ResolvedEvent {
+        let position = self.position.unwrap_or(Position { commit: LogPosition::Last, prepare: LogPosition::Last });
+
+        client_messages::ResolvedEvent {
+            event: self.event.as_raw(),
+            link: self.link.as_ref().map(RecordedEvent::as_raw),
+            commit_position: position.commit.into(),
+            prepare_position: position.prepare.into(),
+        }
+    }
+}
+
+impl<'a, 'b> TryFrom<&'a client_messages::ResolvedIndexedEvent<'b>> for ResolvedEvent {
+    type Error = Error;
+
+    fn try_from(e: &'a client_messages::ResolvedIndexedEvent<'b>) -> Result<Self, Self::Error> {
+        Ok(ResolvedEvent {
+            event: RecordedEvent::try_from(&e.event)?,
+            link: match e.link {
+                Some(ref link) => Some(RecordedEvent::try_from(link)?),
+                None => None,
+            },
+            position: None,
+        })
+    }
+}
+
+impl<'a, 'b> TryFrom<&'a client_messages::ResolvedEvent<'b>> for ResolvedEvent {
+    type Error = Error;
+
+    fn try_from(e: &'a client_messages::ResolvedEvent<'b>) -> Result<Self, Self::Error> {
+        let commit = LogPosition::try_from(e.commit_position).map_err(|(_, e)| e)?;
+        let prepare = LogPosition::try_from(e.prepare_position).map_err(|(_, e)| e)?;
+
+        Ok(ResolvedEvent {
+            event: RecordedEvent::try_from(&e.event)?,
+            link: match e.link {
+                Some(ref link) => Some(RecordedEvent::try_from(link)?),
+                None => None,
+            },
+            position: Some(Position { commit: commit, prepare: prepare }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::convert::TryFrom;
+    use std::time::{Duration, UNIX_EPOCH};
+    use uuid::Uuid;
+
+    use raw::client_messages::{EventRecord, ResolvedIndexedEvent};
+    use super::{CreatedFields, RecordedEvent, ResolvedEvent, TICKS_AT_EPOCH};
+    use {ContentType, StreamVersion};
+
+    fn record(stream: &str, number: i64, id: &Uuid) -> EventRecord<'static> {
+        // 2017-06-01T12:00:00.1234567Z
+        let ticks = 636_319_152_001_234_567;
+        EventRecord {
+            event_stream_id: Cow::Owned(stream.to_owned()),
+            event_number: number,
+            event_id: Cow::Owned(id.as_bytes().to_vec()),
+            event_type: Cow::Borrowed("OrderPlaced"),
+            data_content_type: 1,
+            metadata_content_type: 0,
+            data: Cow::Borrowed(&b"{\"id\":1}"[..]),
+            metadata: Some(Cow::Borrowed(&b"\x01\x02"[..])),
+            created: Some(ticks),
+            created_epoch: Some((ticks - TICKS_AT_EPOCH) / 10_000),
+        }
+    }
+
+    #[test]
+    fn recorded_event_round_trip() {
+        let id = Uuid::new_v4();
+        let raw = record("orders-1", 7, &id);
+        let event = RecordedEvent::try_from(&raw).unwrap();
+
+        assert_eq!(event.stream_id, "orders-1");
+        assert_eq!(event.number, StreamVersion::try_from(7).unwrap());
+        assert_eq!(event.event_id, id);
+        assert_eq!(event.data_content_type, ContentType::Json);
+        assert_eq!(event.created, Some(UNIX_EPOCH + Duration::new(1_496_318_400, 123_456_700)));
+
+        assert_eq!(event.as_raw(), raw);
+    }
+
+    #[test]
+    fn created_fields_round_trip() {
+        let mut raw = record("orders-1", 7, &Uuid::new_v4());
+        let millis = raw.created_epoch;
+
+        raw.created_epoch = None;
+        let event = RecordedEvent::try_from(&raw).unwrap();
+        assert_eq!(event.created_fields, CreatedFields::Ticks);
+        assert_eq!(event.as_raw(), raw);
+
+        raw.created = None;
+        raw.created_epoch = millis;
+        let event = RecordedEvent::try_from(&raw).unwrap();
+        assert_eq!(event.created_fields, CreatedFields::EpochMillis);
+        assert_eq!(event.created, Some(UNIX_EPOCH + Duration::new(1_496_318_400, 123_000_000)));
+        assert_eq!(event.as_raw(), raw);
+    }
+
+    #[test]
+    fn overflowing_creation_times() {
+        let mut raw = record("orders-1", 7, &Uuid::new_v4());
+        raw.created = Some(i64::min_value());
+        assert!(RecordedEvent::try_from(&raw).is_err());
+
+        raw.created = None;
+        raw.created_epoch = Some(i64::max_value());
+        assert!(RecordedEvent::try_from(&raw).is_err());
+
+        let mut event = RecordedEvent::try_from(&record("orders-1", 7, &Uuid::new_v4())).unwrap();
+        event.created = Some(UNIX_EPOCH + Duration::from_secs(1 << 40));
+        let raw = event.as_raw();
+        assert_eq!((raw.created, raw.created_epoch), (None, None));
+    }
+
+    #[test]
+    fn invalid_and_unknown_fields() {
+        let mut raw = record("orders-1", 7, &Uuid::new_v4());
+        raw.event_id = Cow::Borrowed(&[1, 2, 3]);
+        assert!(RecordedEvent::try_from(&raw).is_err());
+
+        let mut raw = record("orders-1", -1, &Uuid::new_v4());
+        assert!(RecordedEvent::try_from(&raw).is_err());
+
+        raw.event_number = 1;
+        raw.data_content_type = 5;
+        let event = RecordedEvent::try_from(&raw).unwrap();
+        assert_eq!(event.data_content_type, ContentType::Other(5));
+        assert_eq!(event.as_raw(), raw);
+    }
+
+    #[test]
+    fn resolved_link() {
+        let target = record("orders-1", 7, &Uuid::new_v4());
+        let link = record("$ce-orders", 100, &Uuid::new_v4());
+        let raw = ResolvedIndexedEvent { event: target, link: Some(link) };
+
+        let resolved = ResolvedEvent::try_from(&raw).unwrap();
+        assert!(resolved.is_resolved());
+        assert_eq!(resolved.event.stream_id, "orders-1");
+        assert_eq!(resolved.original_stream_id(), "$ce-orders");
+        assert_eq!(resolved.original_event_number(), StreamVersion::try_from(100).unwrap());
+        assert_eq!(resolved.original_position(), None);
+
+        assert_eq!(resolved.as_raw(), raw);
+    }
+}
@@ -1,16 +1,20 @@
 //! Adapted or refined types providing a much more oxidized API for handling the messages in the
 //! protocol.
 
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
 use std::borrow::Cow;
 use std::ops::Range;
 use errors::{Error, ErrorKind, ResultStatusKind};
 use crate::{ReadDirection, EventNumber, StreamVersion, LogPosition};
 use raw;
-use raw::client_messages::{WriteEvents, ResolvedIndexedEvent};
+use raw::client_messages::WriteEvents;
+use raw::client_messages::mod_NotHandled::NotHandledReason;
+
+mod event;
+pub use self::event::{CreatedFields, RecordedEvent, ResolvedEvent, Position};
 
 mod write_events;
-pub use self::write_events::{WriteEventsCompleted, WriteEventsFailure};
+pub use self::write_events::{WriteEventsCompleted, WriteEventsError, WriteEventsFailure};
 
 mod read_event;
 pub use self::read_event::{ReadEventError};
@@ -18,8 +22,8 @@ pub use self::read_event::{ReadEventError};
 mod read_stream;
 pub use self::read_stream::{ReadStreamCompleted, ReadStreamError};
 
-mod read_all;
-pub use self::read_all::{ReadAllCompleted, ReadAllError};
+pub mod read_all;
+pub use self::read_all::{ReadAllCompleted, ReadAllError, ReadAllFailure};
 
 /// Enumeration of converted messages for more oxidized API. Unlike the `RawMessage` variants,
 /// `AdaptedMessage` variants are validated and converted into nicer API. This validation comes at
@@ -39,22 +43,22 @@ pub enum AdaptedMessage<'a> {
     /// Append to stream request
     WriteEvents(WriteEvents<'a>),
     /// Append to stream response, which can fail for a number of reasons
-    WriteEventsCompleted(Result<WriteEventsCompleted, WriteEventsFailure>),
+    WriteEventsCompleted(Result<WriteEventsCompleted, WriteEventsError>),
 
     /// Request to read a single event from a stream
     ReadEvent(raw::client_messages::ReadEvent<'a>),
     /// Response to a single event read
-    ReadEventCompleted(Result<raw::client_messages::ResolvedIndexedEvent<'a>, ReadEventError<'a>>),
+    ReadEventCompleted(Result<ResolvedEvent, ReadEventError<'a>>),
 
     /// Request to read a stream from a point forward or backward
     ReadStreamEvents(ReadDirection, raw::client_messages::ReadStreamEvents<'a>),
     /// Response to a stream read in given direction
-    ReadStreamEventsCompleted(ReadDirection, Result<ReadStreamCompleted<'a>, ReadStreamError<'a>>),
+    ReadStreamEventsCompleted(ReadDirection, Result<ReadStreamCompleted, ReadStreamError<'a>>),
 
     /// Request to read a stream of all events from a position forward or backward
     ReadAllEvents(ReadDirection, raw::client_messages::ReadAllEvents),
     /// Response to a read all in given direction
-    ReadAllEventsCompleted(ReadDirection, Result<ReadAllCompleted<'a>, ReadAllError<'a>>),
+    ReadAllEventsCompleted(ReadDirection, Result<ReadAllCompleted, ReadAllFailure<'a>>),
 
     /// Request was not understood. Please open an issue!
     BadRequest(BadRequestMessage<'a>),
@@ -72,7 +76,14 @@ pub enum AdaptedMessage<'a> {
 
     /// Negative authentication response, or response to any sent request for which used
     /// authentication was not accepted.
-    NotAuthenticated(NotAuthenticatedMessage<'a>)
+    NotAuthenticated(NotAuthenticatedMessage<'a>),
+
+    /// Request to identify the client with a version and a connection name. Servers older than
+    /// EventStore 4 respond with `BadRequest`.
+    IdentifyClient(raw::client_messages::IdentifyClient<'a>),
+
+    /// Positive response to `IdentifyClient`.
+    ClientIdentified,
 }
 
 // NOTE: While I still think this was in general a good idea to layer more idiomatic layers above
@@ -129,6 +140,8 @@ impl<'a> TryFrom<raw::RawMessage<'a>> for AdaptedMessage<'a> {
             RawMessage::Authenticate                      => Ok(AdaptedMessage::Authenticate),
             RawMessage::Authenticated                     => Ok(AdaptedMessage::Authenticated),
             RawMessage::NotAuthenticated(reason)          => into_str_or_rebuild!(reason, NotAuthenticatedMessage::from),
+            RawMessage::IdentifyClient(e)                 => Ok(AdaptedMessage::IdentifyClient(e)),
+            RawMessage::ClientIdentified                  => Ok(AdaptedMessage::ClientIdentified),
             RawMessage::Unsupported(d, bytes)             => Err(((d, bytes).into(), ErrorKind::UnsupportedDiscriminator(d).into())),
             unimpl => Err((unimpl, ErrorKind::UnimplementedConversion.into())),
         }
@@ -151,7 +164,7 @@ impl<'a> AdaptedMessage<'a> {
             WriteEventsCompleted(Ok(ref body)) => RawMessage::WriteEventsCompleted(body.as_raw()),
             WriteEventsCompleted(Err(ref err)) => RawMessage::WriteEventsCompleted(err.as_raw()),
             ReadEvent(ref re) => RawMessage::ReadEvent(re.clone()),
-            ReadEventCompleted(Ok(ref event)) => RawMessage::ReadEventCompleted(event.as_raw()),
+            ReadEventCompleted(Ok(ref event)) => RawMessage::ReadEventCompleted(AsRawPayload::as_raw(event)),
             ReadEventCompleted(Err(ref err)) => RawMessage::ReadEventCompleted(err.as_raw()),
             ReadStreamEvents(ref dir, ref rse) => RawMessage::ReadStreamEvents(*dir, rse.clone()),
             ReadStreamEventsCompleted(ref dir, Ok(ref body)) => RawMessage::ReadStreamEventsCompleted(*dir, body.as_raw()),
@@ -162,6 +175,34 @@ impl<'a> AdaptedMessage<'a> {
             BadRequest(ref msg) => RawMessage::BadRequest(msg.as_raw()),
             NotHandled(ref info) => RawMessage::NotHandled(info.as_raw()),
             NotAuthenticated(ref msg) => RawMessage::NotAuthenticated(msg.as_raw()),
+            IdentifyClient(ref ic) => RawMessage::IdentifyClient(ic.clone()),
+            ClientIdentified => RawMessage::ClientIdentified,
+        }
+    }
+
+    /// Copies any borrowed contents so that the message no longer borrows the decoding buffer.
+    pub fn into_owned(self) -> AdaptedMessage<'static> {
+        use self::AdaptedMessage::*;
+        match self {
+            HeartbeatRequest => HeartbeatRequest,
+            HeartbeatResponse => HeartbeatResponse,
+            Ping => Ping,
+            Pong => Pong,
+            WriteEvents(we) => WriteEvents(we.into_owned()),
+            WriteEventsCompleted(res) => WriteEventsCompleted(res),
+            ReadEvent(re) => ReadEvent(re.into_owned()),
+            ReadEventCompleted(res) => ReadEventCompleted(res.map_err(ReadEventError::into_owned)),
+            ReadStreamEvents(dir, rse) => ReadStreamEvents(dir, rse.into_owned()),
+            ReadStreamEventsCompleted(dir, res) => ReadStreamEventsCompleted(dir, res.map_err(ReadStreamError::into_owned)),
+            ReadAllEvents(dir, rae) => ReadAllEvents(dir, rae),
+            ReadAllEventsCompleted(dir, res) => ReadAllEventsCompleted(dir, res.map_err(ReadAllFailure::into_owned)),
+            BadRequest(msg) => BadRequest(msg.into_owned()),
+            NotHandled(info) => NotHandled(info.into_owned()),
+            Authenticate => Authenticate,
+            Authenticated => Authenticated,
+            NotAuthenticated(msg) => NotAuthenticated(msg.into_owned()),
+            IdentifyClient(ic) => IdentifyClient(ic.into_owned()),
+            ClientIdentified => ClientIdentified,
         }
     }
 }
@@ -170,20 +211,26 @@ trait AsRawPayload<'a, 'b, P: 'b> {
     fn as_raw(&'b self) -> P;
 }
 
-/// Placeholder for NotHandledInfo, unused.
-#[derive(Debug, PartialEq, Clone)]
+/// Explanation for an `AdaptedMessage::NotHandled` response.
+#[derive(Debug, PartialEq, Clone, IntoOwned)]
 pub struct NotHandledInfo<'a> {
-    ph: &'a str
+    /// Why the request was not handled
+    pub reason: NotHandledReason,
+    /// For `NotHandledReason::NotMaster` this is the encoded `MasterInfo`
+    pub additional_info: Option<Cow<'a, [u8]>>,
 }
 
 impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::NotHandled<'b>> for NotHandledInfo<'a> {
     fn as_raw(&'b self) -> raw::client_messages::NotHandled<'b> {
-        unimplemented!()
+        raw::client_messages::NotHandled {
+            reason: Some(self.reason),
+            additional_info: self.additional_info.as_ref().map(|x| Cow::Borrowed(&x[..])),
+        }
     }
 }
 
 /// Newtype for wrapping a specific message, AdaptedMessage::BadRequest
-#[derive(Debug, PartialEq, Clone, From, Into)]
+#[derive(Debug, PartialEq, Clone, From, Into, IntoOwned)]
 pub struct BadRequestMessage<'a>(Cow<'a, str>);
 
 impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::BadRequestPayload<'b>> for BadRequestMessage<'a> {
@@ -208,7 +255,7 @@ impl<'a> From<BadRequestMessage<'a>> for AdaptedMessage<'a> {
 }
 
 /// Newtype for wrapping a specific message, AdaptedMessage::NotAuthenticated
-#[derive(Debug, PartialEq, Clone, From, Into)]
+#[derive(Debug, PartialEq, Clone, From, Into, IntoOwned)]
 pub struct NotAuthenticatedMessage<'a>(Cow<'a, str>);
 
 impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::NotAuthenticatedPayload<'b>> for NotAuthenticatedMessage<'a> {
@@ -236,7 +283,13 @@ impl<'a> TryFrom<raw::client_messages::NotHandled<'a>> for AdaptedMessage<'a> {
     type Error = (raw::client_messages::NotHandled<'a>, Error);
 
     fn try_from(msg: raw::client_messages::NotHandled<'a>) -> Result<AdaptedMessage<'a>, Self::Error> {
-        Err((msg, ErrorKind::UnimplementedConversion.into()))
+        match msg.reason {
+            Some(reason) => Ok(AdaptedMessage::NotHandled(NotHandledInfo {
+                reason: reason,
+                additional_info: msg.additional_info,
+            })),
+            None => Err((msg, ErrorKind::MissingResultField(ResultStatusKind::NotHandled).into())),
+        }
     }
 }
 
@@ -246,15 +299,16 @@ impl<'a> From<raw::client_messages::WriteEvents<'a>> for AdaptedMessage<'a> {
     }
 }
 
-fn range_from_parts(start: i32, end: i32) -> Result<Range<StreamVersion>, Error> {
+fn range_from_parts(start: i64, end: i64) -> Result<Range<StreamVersion>, Error> {
     let start = StreamVersion::try_from(start)?;
-    let end = StreamVersion::try_from(end + 1)?;
+    let end = end.checked_add(1).ok_or_else(|| Error::from(ErrorKind::InvalidStreamVersion(end)))?;
+    let end = StreamVersion::try_from(end)?;
     Ok(start..end)
 }
 
-fn range_to_parts(r: &Range<StreamVersion>) -> (i32, i32) {
-    let start: i32 = r.start.into();
-    let end: i32 = r.end.into();
+fn range_to_parts(r: &Range<StreamVersion>) -> (i64, i64) {
+    let start: i64 = r.start.into();
+    let end: i64 = r.end.into();
     (start, end - 1)
 }
 
@@ -276,14 +330,22 @@ impl<'a> TryFrom<raw::client_messages::WriteEventsCompleted<'a>> for AdaptedMess
                                 Ok(x) => x,
                                 Err(e) => return Err( (msg, e) ),
                             };
+                let positions = msg.prepare_position.map(LogPosition::try_from).transpose()
+                    .and_then(|prepare| msg.commit_position.map(LogPosition::try_from).transpose().map(|commit| (prepare, commit)));
+                let (prepare_position, commit_position) = match positions {
+                    Ok(x) => x,
+                    Err((_, e)) => return Err( (msg, e) ),
+                };
                 Ok(WriteEventsCompleted {
                     event_numbers: range,
                     // unsure if these should be:
                     //  * separate (instead of newtype for tuple)
                     // these must be options, as for idempotent writes the positions might not be
                     // returned. both seem to be returned always.
-                    prepare_position: msg.prepare_position.map(|x| x.try_into().unwrap()),
-                    commit_position: msg.commit_position.map(|x| x.try_into().unwrap()),
+                    prepare_position: prepare_position,
+                    commit_position: commit_position,
+                    message: msg.message.map(Cow::into_owned),
+                    current_version: msg.current_version,
                 })
             }
             InvalidTransaction => {
@@ -291,7 +353,11 @@ impl<'a> TryFrom<raw::client_messages::WriteEventsCompleted<'a>> for AdaptedMess
                 // using WriteEventsError::TryFrom
                 return Err((msg, ErrorKind::WriteEventsInvalidTransaction.into()));
             }
-            other => Err(other.into()),
+            other => Err(WriteEventsError {
+                failure: other.into(),
+                message: msg.message.map(Cow::into_owned),
+                current_version: msg.current_version,
+            }),
         };
 
         Ok(AdaptedMessage::WriteEventsCompleted(res))
@@ -303,24 +369,26 @@ impl<'b> AsRawPayload<'static, 'b, raw::client_messages::WriteEventsCompleted<'b
         let parts = range_to_parts(&self.event_numbers);
         raw::client_messages::WriteEventsCompleted {
             result: Some(raw::client_messages::OperationResult::Success),
-            message: None,
+            message: self.message.as_ref().map(|x| Cow::Borrowed(x.as_str())),
             first_event_number: parts.0,
             last_event_number: parts.1,
             prepare_position: self.prepare_position.map(|x| x.into()),
             commit_position: self.commit_position.map(|x| x.into()),
+            current_version: self.current_version,
         }
     }
 }
 
-impl<'b> AsRawPayload<'static, 'b, raw::client_messages::WriteEventsCompleted<'b>> for WriteEventsFailure {
+impl<'b> AsRawPayload<'static, 'b, raw::client_messages::WriteEventsCompleted<'b>> for WriteEventsError {
     fn as_raw(&'b self) -> raw::client_messages::WriteEventsCompleted<'b> {
         raw::client_messages::WriteEventsCompleted {
-            result: Some((*self).into()),
-            message: None,
+            result: Some(self.failure.into()),
+            message: self.message.as_ref().map(|x| Cow::Borrowed(x.as_str())),
             first_event_number: -1,
             last_event_number: -1,
             prepare_position: None,
             commit_position: None,
+            current_version: self.current_version,
         }
     }
 }
@@ -345,19 +413,22 @@ impl<'a> TryFrom<raw::client_messages::ReadEventCompleted<'a>> for AdaptedMessag
         }
 
         match msg.result.unwrap() {
-            // TODO: use own type here to have better ResolvedEvent
-            // TODO: own ResolvedEvent
-            ReadEventResult::Success => Ok(AdaptedMessage::ReadEventCompleted(Ok(msg.event))),
+            ReadEventResult::Success => {
+                match ResolvedEvent::try_from(&msg.event) {
+                    Ok(event) => Ok(AdaptedMessage::ReadEventCompleted(Ok(event))),
+                    Err(e) => Err((msg, e)),
+                }
+            },
             other => Ok(AdaptedMessage::ReadEventCompleted(Err((other, msg.error).into()))),
         }
     }
 }
 
-impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::ReadEventCompleted<'b>> for ResolvedIndexedEvent<'a> {
+impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::ReadEventCompleted<'b>> for ResolvedEvent {
     fn as_raw(&'b self) -> raw::client_messages::ReadEventCompleted<'b> {
         raw::client_messages::ReadEventCompleted {
             result: Some(raw::client_messages::mod_ReadEventCompleted::ReadEventResult::Success),
-            event: self.clone(),
+            event: ResolvedEvent::as_raw(self),
             error: None,
         }
     }
@@ -366,7 +437,7 @@ impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::ReadEventCompleted<'
 impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::ReadEventCompleted<'b>> for ReadEventError<'a> {
     fn as_raw(&'b self) -> raw::client_messages::ReadEventCompleted<'b> {
         use self::ReadEventError::*;
-        use raw::client_messages::EventRecord;
+        use raw::client_messages::{ResolvedIndexedEvent, EventRecord};
         use raw::client_messages::mod_ReadEventCompleted::ReadEventResult;
 
         let (res, msg): (ReadEventResult, Option<Cow<'b, str>>) = match self {
@@ -421,27 +492,34 @@ impl<'a> TryFrom<(ReadDirection, raw::client_messages::ReadStreamEventsCompleted
             return Err(((dir, msg), ErrorKind::MissingResultField(ResultStatusKind::ReadStream).into()));
         }
 
-        let next_page = if dir == ReadDirection::Backward && msg.next_event_number < 0 {
-            None
-        } else {
-            let stream_version = match StreamVersion::try_from(msg.next_event_number) {
-                                     Ok(x) => x,
-                                     Err(e) => return Err( ((dir, msg), e) ),
-                                 };
-            Some(EventNumber::from(stream_version))
-        };
-        let last_event_number = match StreamVersion::try_from(msg.last_event_number) {
-            Ok(x) => x,
-            Err(e) => return Err( ((dir, msg), e) ),
-        };
-
         // clone to avoid borrowing it
         let result = msg.result.as_ref().unwrap().clone();
 
         match result {
             ReadStreamResult::Success => {
+                // the event numbers are only meaningful on success, failures carry -1
+                let next_page = if dir == ReadDirection::Backward && msg.next_event_number < 0 {
+                    None
+                } else {
+                    let stream_version = match StreamVersion::try_from(msg.next_event_number) {
+                                             Ok(x) => x,
+                                             Err(e) => return Err( ((dir, msg), e) ),
+                                         };
+                    Some(EventNumber::from(stream_version))
+                };
+                let last_event_number = match StreamVersion::try_from(msg.last_event_number) {
+                    Ok(x) => x,
+                    Err(e) => return Err( ((dir, msg), e) ),
+                };
+
+                let events: Result<Vec<_>, _> = msg.events.iter().map(ResolvedEvent::try_from).collect();
+                let events = match events {
+                    Ok(x) => x,
+                    Err(e) => return Err( ((dir, msg), e) ),
+                };
+
                 Ok(AdaptedMessage::ReadStreamEventsCompleted(dir, Ok(ReadStreamCompleted {
-                    events: msg.events,
+                    events: events,
                     next_page: next_page,
                     last_event_number: last_event_number,
                     end_of_stream: msg.is_end_of_stream,
@@ -456,13 +534,12 @@ impl<'a> TryFrom<(ReadDirection, raw::client_messages::ReadStreamEventsCompleted
     }
 }
 
-impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::ReadStreamEventsCompleted<'b>> for ReadStreamCompleted<'a> {
+impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::ReadStreamEventsCompleted<'b>> for ReadStreamCompleted {
     fn as_raw(&'b self) -> raw::client_messages::ReadStreamEventsCompleted<'b> {
         use raw::client_messages::mod_ReadStreamEventsCompleted::ReadStreamResult;
 
         raw::client_messages::ReadStreamEventsCompleted {
-            // TODO: hopefully this clone just clones the slice reference
-            events: self.events.iter().map(|x| x.clone()).collect(),
+            events: self.events.iter().map(ResolvedEvent::as_raw).collect(),
             result: Some(ReadStreamResult::Success),
             next_event_number: self.next_page.map(|x| x.into()).unwrap_or(-1),
             last_event_number: self.last_event_number.into(),
@@ -514,38 +591,79 @@ impl<'a> TryFrom<(ReadDirection, raw::client_messages::ReadAllEventsCompleted<'a
     fn try_from((dir, msg): (ReadDirection, raw::client_messages::ReadAllEventsCompleted<'a>)) -> Result<AdaptedMessage<'a>, Self::Error> {
         use raw::client_messages::mod_ReadAllEventsCompleted::ReadAllResult;
 
-        let next_commit_position = LogPosition::from_i64_opt(msg.next_commit_position);
-        let next_prepare_position = LogPosition::from_i64_opt(msg.next_prepare_position);
-
         let res = match msg.result {
             ReadAllResult::Success => {
+                let positions = [msg.commit_position, msg.prepare_position, msg.next_commit_position, msg.next_prepare_position];
+                let positions: Result<Vec<LogPosition>, _> = positions.iter().map(|x| LogPosition::try_from(*x)).collect();
+                let positions = match positions {
+                    Ok(x) => x,
+                    Err((_, e)) => return Err( ((dir, msg), e) ),
+                };
+
+                let events: Result<Vec<_>, _> = msg.events.iter().map(ResolvedEvent::try_from).collect();
+                let events = match events {
+                    Ok(x) => x,
+                    Err(e) => return Err( ((dir, msg), e) ),
+                };
+
                 Ok(ReadAllCompleted {
-                    commit_position: msg.commit_position.try_into().unwrap(),
-                    prepare_position: msg.prepare_position.try_into().unwrap(),
-                    events: msg.events.into_iter().map(|x| {
-                        let re: self::read_all::ResolvedEvent<'a> = x.into();
-                        re
-                    }).collect(),
-                    next_commit_position: next_commit_position,
-                    next_prepare_position: next_prepare_position,
+                    commit_position: positions[0],
+                    prepare_position: positions[1],
+                    events: events,
+                    next_commit_position: positions[2],
+                    next_prepare_position: positions[3],
                 })
             },
-            fail => Err((fail, msg.error).into()),
+            fail => Err(ReadAllFailure {
+                error: (fail, msg.error).into(),
+                commit_position: msg.commit_position,
+                prepare_position: msg.prepare_position,
+                next_commit_position: msg.next_commit_position,
+                next_prepare_position: msg.next_prepare_position,
+            }),
         };
 
         Ok(AdaptedMessage::ReadAllEventsCompleted(dir, res))
     }
 }
 
-impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::ReadAllEventsCompleted<'b>> for ReadAllCompleted<'a> {
+impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::ReadAllEventsCompleted<'b>> for ReadAllCompleted {
     fn as_raw(&'b self) -> raw::client_messages::ReadAllEventsCompleted<'b> {
-        unimplemented!()
+        use raw::client_messages::mod_ReadAllEventsCompleted::ReadAllResult;
+
+        raw::client_messages::ReadAllEventsCompleted {
+            commit_position: self.commit_position.into(),
+            prepare_position: self.prepare_position.into(),
+            events: self.events.iter().map(ResolvedEvent::as_raw_positioned).collect(),
+            next_commit_position: self.next_commit_position.into(),
+            next_prepare_position: self.next_prepare_position.into(),
+            result: ReadAllResult::Success,
+            error: None,
+        }
     }
 }
 
-impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::ReadAllEventsCompleted<'b>> for ReadAllError<'a> {
+impl<'a, 'b: 'a> AsRawPayload<'a, 'b, raw::client_messages::ReadAllEventsCompleted<'b>> for ReadAllFailure<'a> {
     fn as_raw(&'b self) -> raw::client_messages::ReadAllEventsCompleted<'b> {
-        unimplemented!()
+        use raw::client_messages::mod_ReadAllEventsCompleted::ReadAllResult;
+        use self::ReadAllError::*;
+
+        let (result, error) = match &self.error {
+            &NotModified => (ReadAllResult::NotModified, None),
+            &Error(Some(ref msg)) => (ReadAllResult::Error, Some(Cow::Borrowed(&**msg))),
+            &Error(None) => (ReadAllResult::Error, None),
+            &AccessDenied => (ReadAllResult::AccessDenied, None),
+        };
+
+        raw::client_messages::ReadAllEventsCompleted {
+            commit_position: self.commit_position,
+            prepare_position: self.prepare_position,
+            events: vec![],
+            next_commit_position: self.next_commit_position,
+            next_prepare_position: self.next_prepare_position,
+            result: result,
+            error: error,
+        }
     }
 }
 
@@ -563,6 +681,7 @@ mod tests {
             (RawMessage::Pong, AdaptedMessage::Pong),
             (RawMessage::Authenticate, AdaptedMessage::Authenticate),
             (RawMessage::Authenticated, AdaptedMessage::Authenticated),
+            (RawMessage::ClientIdentified, AdaptedMessage::ClientIdentified),
         ];
 
         for (input, expected) in values {
@@ -570,6 +689,12 @@ mod tests {
         }
     }
 
+    #[test]
+    fn range_from_parts_rejects_overflowing_end() {
+        assert_eq!(range_from_parts(0, 1).unwrap(), StreamVersion::from(0u32)..StreamVersion::from(2u32));
+        assert!(range_from_parts(0, i64::max_value()).is_err());
+    }
+
     #[test]
     fn convert_bad_request() {
         test_conversions(
@@ -592,6 +717,18 @@ mod tests {
             AdaptedMessage::NotAuthenticated(Cow::Borrowed("").into()));
     }
 
+    #[test]
+    fn convert_identify_client() {
+        use raw::client_messages::IdentifyClient;
+
+        let msg = IdentifyClient {
+            version: 1,
+            connection_name: Some(Cow::Borrowed("billing")),
+        };
+
+        test_conversions(RawMessage::IdentifyClient(msg.clone()), AdaptedMessage::IdentifyClient(msg));
+    }
+
     #[test]
     fn convert_write_events() {
         use uuid::Uuid;
@@ -639,6 +776,7 @@ mod tests {
             last_event_number: 0,
             prepare_position: Some(100),
             commit_position: Some(100),
+            current_version: None,
         };
 
         test_conversions(
@@ -647,6 +785,8 @@ mod tests {
                 event_numbers: StreamVersion::try_from(0).unwrap()..StreamVersion::try_from(1).unwrap(),
                 prepare_position: Some(LogPosition::try_from(100).unwrap()),
                 commit_position: Some(LogPosition::try_from(100).unwrap()),
+                message: None,
+                current_version: None,
             })));
     }
 
@@ -669,18 +809,23 @@ mod tests {
         for (error, mapped) in errors {
             let body = raw::client_messages::WriteEventsCompleted {
                 result: Some(error),
-                message: None, // TODO: this message is lost
+                message: Some(Cow::Borrowed("it failed")),
                 first_event_number: -1,
                 last_event_number: -1,
                 prepare_position: None,
                 commit_position: None,
+                current_version: Some(3),
             };
 
             match mapped {
                 Some(mapped) => {
                     test_conversions(
                         RawMessage::WriteEventsCompleted(body),
-                        AdaptedMessage::WriteEventsCompleted(Err(mapped)));
+                        AdaptedMessage::WriteEventsCompleted(Err(WriteEventsError {
+                            failure: mapped,
+                            message: Some("it failed".into()),
+                            current_version: Some(3),
+                        })));
                 },
                 None => {
                     failing_conversion(RawMessage::WriteEventsCompleted(body));
@@ -703,6 +848,47 @@ mod tests {
             AdaptedMessage::ReadEvent(body.clone()));
     }
 
+    #[test]
+    fn convert_read_event_completed() {
+        use uuid::Uuid;
+        use raw::client_messages::{ReadEventCompleted, ResolvedIndexedEvent, EventRecord};
+        use raw::client_messages::mod_ReadEventCompleted::ReadEventResult;
+
+        let uuid = Uuid::new_v4();
+
+        let body = ReadEventCompleted {
+            result: Some(ReadEventResult::Success),
+            event: ResolvedIndexedEvent {
+                event: EventRecord {
+                    event_stream_id: "foo".into(),
+                    event_number: 3,
+                    event_id: Cow::Borrowed(uuid.as_bytes()),
+                    event_type: "bar".into(),
+                    data_content_type: 1,
+                    metadata_content_type: 0,
+                    data: Cow::Borrowed(&b"{}"[..]),
+                    metadata: None,
+                    created: None,
+                    created_epoch: None,
+                },
+                link: None,
+            },
+            error: None,
+        };
+
+        let adapted = AdaptedMessage::try_from(RawMessage::ReadEventCompleted(body.clone())).unwrap();
+
+        match adapted {
+            AdaptedMessage::ReadEventCompleted(Ok(ref event)) => {
+                assert_eq!(event.event.event_id, uuid);
+                assert_eq!(event.original_event_number(), StreamVersion::try_from(3).unwrap());
+            },
+            ref x => panic!("unexpected {:?}", x),
+        }
+
+        assert_eq!(adapted.as_raw(), RawMessage::ReadEventCompleted(body));
+    }
+
     #[test]
     fn convert_bogus_read_completed() {
         use raw::client_messages::{ReadEventCompleted, ResolvedIndexedEvent, EventRecord};
@@ -730,6 +916,331 @@ mod tests {
         failing_conversion(RawMessage::ReadEventCompleted(bogus));
     }
 
+    #[test]
+    fn convert_not_handled() {
+        use raw::client_messages::NotHandled;
+
+        let msg = NotHandled {
+            reason: Some(NotHandledReason::NotMaster),
+            additional_info: Some(Cow::Borrowed(&b"\x0a\x03foo"[..])),
+        };
+
+        test_conversions(
+            RawMessage::NotHandled(msg.clone()),
+            AdaptedMessage::NotHandled(NotHandledInfo {
+                reason: NotHandledReason::NotMaster,
+                additional_info: Some(Cow::Borrowed(&b"\x0a\x03foo"[..])),
+            }));
+
+        failing_conversion(RawMessage::NotHandled(NotHandled { reason: None, ..msg }));
+    }
+
+    #[test]
+    fn round_trip_from_raw() {
+        use raw::client_messages::{OperationResult, ReadAllEventsCompleted, WriteEventsCompleted};
+        use raw::client_messages::mod_ReadAllEventsCompleted::ReadAllResult;
+
+        let messages = vec![
+            RawMessage::WriteEventsCompleted(WriteEventsCompleted {
+                result: Some(OperationResult::Success),
+                message: Some(Cow::Borrowed("already written")),
+                first_event_number: 4,
+                last_event_number: 5,
+                prepare_position: Some(1024),
+                commit_position: Some(1024),
+                current_version: Some(5),
+            }),
+            RawMessage::WriteEventsCompleted(WriteEventsCompleted {
+                result: Some(OperationResult::WrongExpectedVersion),
+                message: Some(Cow::Borrowed("expected 3")),
+                first_event_number: -1,
+                last_event_number: -1,
+                prepare_position: None,
+                commit_position: None,
+                current_version: Some(7),
+            }),
+            RawMessage::ReadAllEventsCompleted(ReadDirection::Forward, ReadAllEventsCompleted {
+                commit_position: 2048,
+                prepare_position: 2040,
+                events: vec![],
+                next_commit_position: 4096,
+                next_prepare_position: 4090,
+                result: ReadAllResult::AccessDenied,
+                error: Some(Cow::Borrowed("no access")),
+            }),
+        ];
+
+        for raw in messages {
+            let adapted = raw.clone().try_adapt().unwrap();
+            assert_eq!(adapted.as_raw(), raw);
+        }
+    }
+
+    #[test]
+    fn round_trip_every_variant() {
+        use rand::{SeedableRng, XorShiftRng};
+
+        let mut gen = Gen(XorShiftRng::from_seed([0x2f6b_1d03, 0x9e37_79b9, 0x85eb_ca6b, 0xc2b2_ae35]));
+
+        for i in 0..VARIANTS * 50 {
+            let msg = gen.message(i % VARIANTS);
+            let raw = msg.as_raw();
+
+            let mut buf = Vec::new();
+            raw.encode(&mut buf).unwrap();
+            let decoded = RawMessage::decode(raw.discriminator(), &buf).unwrap();
+            assert_eq!(decoded, raw);
+
+            let adapted = AdaptedMessage::try_from(decoded).unwrap();
+            assert_eq!(adapted, msg);
+            assert_eq!(adapted.as_raw(), raw);
+            assert_eq!(adapted.into_owned(), msg);
+        }
+    }
+
+    const VARIANTS: usize = 27;
+
+    /// Generates arbitrary but valid adapted messages for `round_trip_every_variant`.
+    struct Gen<R: ::rand::Rng>(R);
+
+    impl<R: ::rand::Rng> Gen<R> {
+        fn message(&mut self, variant: usize) -> AdaptedMessage<'static> {
+            use raw::client_messages::{WriteEvents, NewEvent, ReadEvent, ReadStreamEvents, ReadAllEvents, IdentifyClient};
+
+            match variant {
+                0 => AdaptedMessage::HeartbeatRequest,
+                1 => AdaptedMessage::HeartbeatResponse,
+                2 => AdaptedMessage::Ping,
+                3 => AdaptedMessage::Pong,
+                4 => AdaptedMessage::WriteEvents(WriteEvents {
+                    event_stream_id: self.string(),
+                    expected_version: self.0.gen(),
+                    events: (0..self.0.gen_range(0, 4)).map(|_| NewEvent {
+                        event_id: Cow::Owned(self.bytes(16)),
+                        event_type: self.string(),
+                        data_content_type: self.0.gen(),
+                        metadata_content_type: self.0.gen(),
+                        data: Cow::Owned(self.payload()),
+                        metadata: self.option().map(|_| Cow::Owned(self.payload())),
+                    }).collect(),
+                    require_master: self.0.gen(),
+                }),
+                5 => {
+                    let start = StreamVersion::try_from(self.0.gen_range(0, i64::max_value() - 16)).unwrap();
+                    let end = StreamVersion::try_from(u64::from(start) + self.0.gen_range(0, 16)).unwrap();
+                    AdaptedMessage::WriteEventsCompleted(Ok(WriteEventsCompleted {
+                        event_numbers: start..end,
+                        prepare_position: self.option().map(|_| self.log_position()),
+                        commit_position: self.option().map(|_| self.log_position()),
+                        message: self.option().map(|_| self.string().into_owned()),
+                        current_version: self.option().map(|_| self.0.gen()),
+                    }))
+                },
+                6 => {
+                    use adapted::WriteEventsFailure::*;
+                    let failure = *self.0.choose(&[PrepareTimeout, CommitTimeout, ForwardTimeout, WrongExpectedVersion, StreamDeleted, AccessDenied]).unwrap();
+                    AdaptedMessage::WriteEventsCompleted(Err(WriteEventsError {
+                        failure: failure,
+                        message: self.option().map(|_| self.string().into_owned()),
+                        current_version: self.option().map(|_| self.0.gen()),
+                    }))
+                },
+                7 => AdaptedMessage::ReadEvent(ReadEvent {
+                    event_stream_id: self.string(),
+                    event_number: self.0.gen(),
+                    resolve_link_tos: self.0.gen(),
+                    require_master: self.0.gen(),
+                }),
+                8 => AdaptedMessage::ReadEventCompleted(Ok(self.resolved_event(false))),
+                9 => {
+                    use adapted::ReadEventError::*;
+                    let err = match self.0.gen_range(0, 5) {
+                        0 => NotFound,
+                        1 => NoStream,
+                        2 => StreamDeleted,
+                        3 => AccessDenied,
+                        _ => Error(self.option().map(|_| self.string())),
+                    };
+                    AdaptedMessage::ReadEventCompleted(Err(err))
+                },
+                10 => AdaptedMessage::ReadStreamEvents(self.direction(), ReadStreamEvents {
+                    event_stream_id: self.string(),
+                    from_event_number: self.0.gen(),
+                    max_count: self.0.gen(),
+                    resolve_link_tos: self.0.gen(),
+                    require_master: self.0.gen(),
+                }),
+                11 => {
+                    let dir = self.direction();
+                    // only backward reads can reach the start of the stream
+                    let next_page = match dir {
+                        ReadDirection::Backward if self.0.gen() => None,
+                        _ => Some(EventNumber::from(self.stream_version())),
+                    };
+                    AdaptedMessage::ReadStreamEventsCompleted(dir, Ok(ReadStreamCompleted {
+                        events: (0..self.0.gen_range(0, 4)).map(|_| self.resolved_event(false)).collect(),
+                        next_page: next_page,
+                        last_event_number: self.stream_version(),
+                        end_of_stream: self.0.gen(),
+                        last_commit_position: self.0.gen(),
+                    }))
+                },
+                12 => {
+                    use adapted::ReadStreamError::*;
+                    let err = match self.0.gen_range(0, 5) {
+                        0 => NoStream,
+                        1 => StreamDeleted,
+                        2 => NotModified,
+                        3 => AccessDenied,
+                        _ => Error(self.option().map(|_| self.string())),
+                    };
+                    AdaptedMessage::ReadStreamEventsCompleted(self.direction(), Err(err))
+                },
+                13 => AdaptedMessage::ReadAllEvents(self.direction(), ReadAllEvents {
+                    commit_position: self.0.gen(),
+                    prepare_position: self.0.gen(),
+                    max_count: self.0.gen(),
+                    resolve_link_tos: self.0.gen(),
+                    require_master: self.0.gen(),
+                }),
+                14 => AdaptedMessage::ReadAllEventsCompleted(self.direction(), Ok(ReadAllCompleted {
+                    commit_position: self.log_position(),
+                    prepare_position: self.log_position(),
+                    events: (0..self.0.gen_range(0, 4)).map(|_| self.resolved_event(true)).collect(),
+                    next_commit_position: self.log_position(),
+                    next_prepare_position: self.log_position(),
+                })),
+                15 => {
+                    use adapted::ReadAllError::*;
+                    let err = match self.0.gen_range(0, 3) {
+                        0 => NotModified,
+                        1 => AccessDenied,
+                        _ => Error(self.option().map(|_| self.string())),
+                    };
+                    AdaptedMessage::ReadAllEventsCompleted(self.direction(), Err(ReadAllFailure {
+                        error: err,
+                        commit_position: self.0.gen(),
+                        prepare_position: self.0.gen(),
+                        next_commit_position: self.0.gen(),
+                        next_prepare_position: self.0.gen(),
+                    }))
+                },
+                16 => AdaptedMessage::BadRequest(BadRequestMessage(self.string())),
+                17 => AdaptedMessage::NotHandled(NotHandledInfo {
+                    reason: *self.0.choose(&[NotHandledReason::NotReady, NotHandledReason::TooBusy, NotHandledReason::NotMaster]).unwrap(),
+                    additional_info: self.option().map(|_| Cow::Owned(self.payload())),
+                }),
+                18 => AdaptedMessage::Authenticate,
+                19 => AdaptedMessage::Authenticated,
+                20 => AdaptedMessage::NotAuthenticated(NotAuthenticatedMessage(self.string())),
+                21 => AdaptedMessage::IdentifyClient(IdentifyClient {
+                    version: self.0.gen(),
+                    connection_name: self.option().map(|_| self.string()),
+                }),
+                22 => AdaptedMessage::ClientIdentified,
+                // the rest exercise links and the edges of the positions
+                23 => AdaptedMessage::ReadEventCompleted(Ok(ResolvedEvent {
+                    link: Some(self.recorded_event()),
+                    ..self.resolved_event(false)
+                })),
+                24 => AdaptedMessage::ReadAllEventsCompleted(self.direction(), Ok(ReadAllCompleted {
+                    commit_position: LogPosition::First,
+                    prepare_position: LogPosition::First,
+                    events: vec![],
+                    next_commit_position: LogPosition::Last,
+                    next_prepare_position: LogPosition::Last,
+                })),
+                25 => AdaptedMessage::WriteEventsCompleted(Ok(WriteEventsCompleted {
+                    event_numbers: StreamVersion::try_from(0).unwrap()..StreamVersion::try_from(0).unwrap(),
+                    prepare_position: None,
+                    commit_position: None,
+                    message: None,
+                    current_version: None,
+                })),
+                26 => AdaptedMessage::BadRequest(BadRequestMessage(Cow::Borrowed(""))),
+                x => unreachable!("no variant {}", x),
+            }
+        }
+
+        fn option(&mut self) -> Option<()> {
+            if self.0.gen() { Some(()) } else { None }
+        }
+
+        fn string(&mut self) -> Cow<'static, str> {
+            let len = self.0.gen_range(0, 24);
+            Cow::Owned(self.0.gen_ascii_chars().take(len).collect())
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len).map(|_| self.0.gen()).collect()
+        }
+
+        fn payload(&mut self) -> Vec<u8> {
+            let len = self.0.gen_range(0, 64);
+            self.bytes(len)
+        }
+
+        fn direction(&mut self) -> ReadDirection {
+            if self.0.gen() { ReadDirection::Forward } else { ReadDirection::Backward }
+        }
+
+        fn stream_version(&mut self) -> StreamVersion {
+            StreamVersion::try_from(self.0.gen_range(0, i64::max_value())).unwrap()
+        }
+
+        fn log_position(&mut self) -> LogPosition {
+            match self.0.gen_range(0, 4) {
+                0 => LogPosition::First,
+                1 => LogPosition::Last,
+                _ => LogPosition::try_from(self.0.gen_range(1, i64::max_value())).unwrap(),
+            }
+        }
+
+        fn recorded_event(&mut self) -> RecordedEvent {
+            use std::time::{Duration, UNIX_EPOCH};
+            use bytes::Bytes;
+            use uuid::Uuid;
+            use ContentType;
+
+            let created_fields = *self.0.choose(&[CreatedFields::Ticks, CreatedFields::EpochMillis, CreatedFields::Both]).unwrap();
+            let created = self.option().map(|_| {
+                let secs = self.0.gen_range(0, 1 << 34);
+                match created_fields {
+                    CreatedFields::EpochMillis => UNIX_EPOCH + Duration::new(secs, self.0.gen_range(0, 1000) * 1_000_000),
+                    _ => UNIX_EPOCH + Duration::new(secs, self.0.gen_range(0, 10_000_000) * 100),
+                }
+            });
+            // without a time there is nothing telling which fields were present
+            let created_fields = if created.is_some() { created_fields } else { CreatedFields::default() };
+
+            RecordedEvent {
+                stream_id: self.string().into_owned(),
+                number: self.stream_version(),
+                event_id: Uuid::from_bytes(&self.bytes(16)).unwrap(),
+                event_type: self.string().into_owned(),
+                data_content_type: ContentType::from(self.0.gen_range(-2i32, 4)),
+                metadata_content_type: ContentType::from(self.0.gen_range(-2i32, 4)),
+                data: Bytes::from(self.payload()),
+                metadata: self.option().map(|_| Bytes::from(self.payload())),
+                // the wire has a precision of 100ns, or 1ms with only the epoch milliseconds
+                created: created,
+                created_fields: created_fields,
+            }
+        }
+
+        fn resolved_event(&mut self, positioned: bool) -> ResolvedEvent {
+            ResolvedEvent {
+                event: self.recorded_event(),
+                link: self.option().map(|_| self.recorded_event()),
+                position: if positioned {
+                    Some(Position { commit: self.log_position(), prepare: self.log_position() })
+                } else {
+                    None
+                },
+            }
+        }
+    }
+
     fn test_conversions<'a, 'b>(input: RawMessage<'a>, expected: AdaptedMessage<'b>) {
         assert_eq!(AdaptedMessage::try_from(input.clone()).unwrap(), expected);
         assert_eq!(expected.as_raw(), input);
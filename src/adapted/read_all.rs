@@ -1,51 +1,33 @@
+//! Responses to `$all` reads. The types are re-exported from `adapted`, the module is public
+//! only for the deprecated `ResolvedEvent`.
+
 use std::borrow::Cow;
-use std::convert::TryInto;
-use raw::client_messages;
 use raw::client_messages::mod_ReadAllEventsCompleted::ReadAllResult;
 
 use LogPosition;
 
 /// Successful response to `Message::ReadAllEvents`.
 #[derive(Debug, Clone, PartialEq)]
-pub struct ReadAllCompleted<'a> {
+pub struct ReadAllCompleted {
     /// Position of the commit of the current prepare
     pub commit_position: LogPosition,
     /// Position of the current prepare
     pub prepare_position: LogPosition,
-    /// The read events, with position metadata
-    pub events: Vec<ResolvedEvent<'a>>,
+    /// The read events, each with a `position`. If any of the events cannot be converted the whole
+    /// response fails to adapt, as with `ReadStreamCompleted::events`.
+    pub events: Vec<super::ResolvedEvent>,
     /// For paging: next commit position
-    pub next_commit_position: Option<LogPosition>,
+    pub next_commit_position: LogPosition,
     /// For paging: next prepare position
-    pub next_prepare_position: Option<LogPosition>,
+    pub next_prepare_position: LogPosition,
 }
 
-/// Read event in `ReadAllSuccess` response
-#[derive(Debug, Clone, PartialEq)]
-pub struct ResolvedEvent<'a> {
-    /// The read event
-    pub event: client_messages::EventRecord<'a>,
-    /// Possible linking event
-    pub link: Option<client_messages::EventRecord<'a>>,
-    /// Position where this events transaction is commited
-    pub commit_position: LogPosition,
-    /// Position where this event is stored
-    pub prepare_position: LogPosition,
-}
-
-impl<'a> From<client_messages::ResolvedEvent<'a>> for ResolvedEvent<'a> {
-    fn from(e: client_messages::ResolvedEvent<'a>) -> ResolvedEvent<'a> {
-        ResolvedEvent {
-            event: e.event.into(),
-            link: e.link.into(),
-            commit_position: e.commit_position.try_into().unwrap(),
-            prepare_position: e.prepare_position.try_into().unwrap()
-        }
-    }
-}
+/// Read event in `ReadAllCompleted`, now the same type as in the other read responses.
+#[deprecated(since = "0.3.0", note = "use adapted::ResolvedEvent, which carries the position")]
+pub type ResolvedEvent<'a> = super::ResolvedEvent;
 
 /// Failure cases of wire enum `ReadAllResult`.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, IntoOwned)]
 pub enum ReadAllError<'a> {
     /// Unknown when this happens,
     NotModified,
@@ -55,6 +37,47 @@ pub enum ReadAllError<'a> {
     AccessDenied
 }
 
+/// Failed response to `Message::ReadAllEvents`: the reason for the failure and the positions the
+/// server sent along with it, kept as they were on the wire.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadAllFailure<'a> {
+    /// The reason for failure
+    pub error: ReadAllError<'a>,
+    /// Commit position as sent by the server, usually `-1`
+    pub commit_position: i64,
+    /// Prepare position as sent by the server, usually `-1`
+    pub prepare_position: i64,
+    /// Next commit position as sent by the server, usually `-1`
+    pub next_commit_position: i64,
+    /// Next prepare position as sent by the server, usually `-1`
+    pub next_prepare_position: i64,
+}
+
+impl<'a> ReadAllFailure<'a> {
+    /// Turns into a value with the static lifetime.
+    pub fn into_owned(self) -> ReadAllFailure<'static> {
+        ReadAllFailure {
+            error: self.error.into_owned(),
+            commit_position: self.commit_position,
+            prepare_position: self.prepare_position,
+            next_commit_position: self.next_commit_position,
+            next_prepare_position: self.next_prepare_position,
+        }
+    }
+}
+
+impl<'a> From<ReadAllError<'a>> for ReadAllFailure<'a> {
+    fn from(error: ReadAllError<'a>) -> Self {
+        ReadAllFailure {
+            error: error,
+            commit_position: -1,
+            prepare_position: -1,
+            next_commit_position: -1,
+            next_prepare_position: -1,
+        }
+    }
+}
+
 impl<'a> From<(ReadAllResult, Option<Cow<'a, str>>)> for ReadAllError<'a> {
     fn from((r, msg): (ReadAllResult, Option<Cow<'a, str>>)) -> ReadAllError<'a> {
         use self::ReadAllResult::*;
@@ -2,7 +2,7 @@ use std::borrow::Cow;
 use raw::client_messages::mod_ReadEventCompleted::ReadEventResult;
 
 /// `ReadEventError` maps to non-success of `ReadEventResult`
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, IntoOwned)]
 pub enum ReadEventError<'a> {
     /// Event of requested number was not found (scavenged or never existed)
     NotFound,
@@ -1,14 +1,15 @@
 use std::borrow::Cow;
 use raw::client_messages::mod_ReadStreamEventsCompleted::ReadStreamResult;
-use raw::client_messages::ResolvedIndexedEvent;
+use super::ResolvedEvent;
 use {StreamVersion, EventNumber};
 
 /// Successful response to a `Message::ReadStreamEvents`.
 #[derive(Debug, PartialEq, Clone)]
-pub struct ReadStreamCompleted<'a> {
+pub struct ReadStreamCompleted {
     /// The actual events returned by the server. Subject to `resolve_link_tos` setting on the read
-    /// request.
-    pub events: Vec<ResolvedIndexedEvent<'a>>,
+    /// request. If any of the events cannot be converted, for example because of an invalid event
+    /// id, the whole response fails to adapt; the raw message is returned along with the error.
+    pub events: Vec<ResolvedEvent>,
     /// `EventNumber` for a query for the next page in the same direction, `None` if start has been
     /// reached when reading backwards. When reading forwards, this will never be `None` as new
     /// events might have appeared while receiving this response.
@@ -25,7 +26,7 @@ pub struct ReadStreamCompleted<'a> {
 
 /// Non-success projection of the `ReadStreamResult` enum on the wire representing
 /// a failed `ReadStreamCompleted` request.
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, IntoOwned)]
 pub enum ReadStreamError<'a> {
     /// Stream was not found
     NoStream,
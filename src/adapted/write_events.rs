@@ -15,6 +15,52 @@ pub struct WriteEventsCompleted {
 
     /// These can be used to locate last written event from the `$all` stream
     pub commit_position: Option<LogPosition>,
+
+    /// Message sent by the server along with the response, if any
+    pub message: Option<String>,
+
+    /// Version of the stream as reported by the server, if any
+    pub current_version: Option<i64>,
+}
+
+/// Failed response to `Message::WriteEvents`: the reason for the failure and whatever the server
+/// sent along with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteEventsError {
+    /// The reason for failure
+    pub failure: WriteEventsFailure,
+
+    /// Message sent by the server explaining the failure, if any
+    pub message: Option<String>,
+
+    /// Version of the stream at the time of the failure, for example when the failure was
+    /// `WrongExpectedVersion`. `-1` stands for a stream which does not exist.
+    pub current_version: Option<i64>,
+}
+
+impl From<WriteEventsFailure> for WriteEventsError {
+    fn from(failure: WriteEventsFailure) -> Self {
+        WriteEventsError {
+            failure: failure,
+            message: None,
+            current_version: None,
+        }
+    }
+}
+
+impl fmt::Display for WriteEventsError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.message {
+            Some(ref message) => write!(fmt, "{}: {}", self.failure, message),
+            None => write!(fmt, "{}", self.failure),
+        }
+    }
+}
+
+impl Error for WriteEventsError {
+    fn description(&self) -> &str {
+        self.failure.description()
+    }
 }
 
 /// Like `OperationResult` on the wire but does not have a success value. Explains the reason for
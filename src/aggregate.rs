@@ -0,0 +1,530 @@
+//! Event sourced aggregates stored in streams.
+//!
+//! An `Aggregate` is rebuilt by applying the events of its stream, named
+//! `<Aggregate::category()>-<id>`, and handles commands by adding new events to a
+//! `WriteEventsBuilder`. `Repository::load` reads the stream page by page into a `Versioned`
+//! aggregate and `Repository::save` appends the events of the handled commands expecting the
+//! version which was loaded, so concurrent modifications fail with `WrongExpectedVersion`.
+//!
+//! With `Repository::with_snapshots` the state of aggregates implementing `Aggregate::snapshot`
+//! is stored every few events in the companion stream `<stream>-snapshots`, which is limited to
+//! a single event with `$maxCount`. Loading then starts from the latest snapshot and only reads
+//! the events after it.
+
+use std::io;
+use std::fmt;
+use std::error;
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use futures::{future, Future, Stream};
+use json;
+use tokio_service::Service;
+
+use adapted::{AdaptedMessage, ReadStreamError, RecordedEvent, WriteEventsCompleted};
+use builder::{Builder, WriteEventsBuilder};
+use concurrency::{write_events, WriteError};
+use metadata::{set_stream_metadata, StreamMetadata};
+use package::Package;
+use reading::{read_stream_pages_upcasted, ReadError};
+use upcast::Upcasters;
+use {ContentType, EventNumber, ExpectedVersion, StreamVersion, UsernamePassword};
+
+/// The event type of snapshots in the snapshot streams.
+pub const SNAPSHOT_EVENT_TYPE: &'static str = "snapshot";
+
+/// Number of events read at a time when loading an aggregate.
+const PAGE_SIZE: u8 = 100;
+
+/// State rebuilt from the events of a stream.
+pub trait Aggregate: Default {
+    /// Commands handled by the aggregate
+    type Command;
+    /// Reason for rejecting a command
+    type Error;
+
+    /// Prefix of the stream names, for example `order` for streams like `order-1`.
+    fn category() -> &'static str;
+
+    /// Updates the state with an event read from the stream.
+    fn apply(&mut self, event: &RecordedEvent);
+
+    /// Validates the command against the current state and adds the resulting events to the
+    /// builder, which has the stream id and the expected version set when saving.
+    fn handle(&self, command: Self::Command, events: &mut WriteEventsBuilder) -> Result<(), Self::Error>;
+
+    /// Serializes the state for a snapshot, `None` if snapshots are not supported. Defaults to
+    /// `None`.
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores the state from a snapshot, `None` if the snapshot cannot be used in which case
+    /// the whole stream is read. Defaults to `None`.
+    fn restore(_snapshot: &[u8]) -> Option<Self> {
+        None
+    }
+}
+
+/// Returns the name of the stream of the aggregate with the given id.
+pub fn stream_of<A: Aggregate>(id: &str) -> String {
+    format!("{}-{}", A::category(), id)
+}
+
+/// Returns the name of the snapshot stream of the given stream.
+pub fn snapshot_stream_of(stream: &str) -> String {
+    format!("{}-snapshots", stream)
+}
+
+/// Failures of loading or saving aggregates.
+#[derive(Debug)]
+pub enum RepositoryError {
+    /// The request could not be sent or the response received.
+    Io(io::Error),
+    /// Reading the stream of the aggregate failed.
+    Read(ReadError),
+    /// Appending the events failed, for example with `WrongExpectedVersion` if the stream was
+    /// modified after loading.
+    Write(WriteError),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RepositoryError::Io(ref e) => write!(f, "{}", e),
+            RepositoryError::Read(ref e) => write!(f, "{}", e),
+            RepositoryError::Write(ref e) => write!(f, "Saving aggregate failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for RepositoryError {
+    fn description(&self) -> &str {
+        match *self {
+            RepositoryError::Io(ref e) => e.description(),
+            RepositoryError::Read(ref e) => e.description(),
+            RepositoryError::Write(_) => "saving aggregate failed",
+        }
+    }
+}
+
+impl From<io::Error> for RepositoryError {
+    fn from(e: io::Error) -> Self {
+        RepositoryError::Io(e)
+    }
+}
+
+impl From<ReadError> for RepositoryError {
+    fn from(e: ReadError) -> Self {
+        RepositoryError::Read(e)
+    }
+}
+
+impl From<WriteError> for RepositoryError {
+    fn from(e: WriteError) -> Self {
+        RepositoryError::Write(e)
+    }
+}
+
+/// An aggregate loaded by `Repository::load` together with the version of its stream and the
+/// events of the commands handled since loading.
+pub struct Versioned<A> {
+    id: String,
+    aggregate: A,
+    version: Option<StreamVersion>,
+    snapshot_version: Option<StreamVersion>,
+    pending: WriteEventsBuilder,
+}
+
+impl<A: Aggregate> Versioned<A> {
+    /// The id of the aggregate.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The state of the aggregate as loaded. Events of the handled commands are not applied
+    /// before they have been saved and the aggregate loaded again.
+    pub fn aggregate(&self) -> &A {
+        &self.aggregate
+    }
+
+    /// The version of the stream when loaded, `None` if it did not exist.
+    pub fn version(&self) -> Option<StreamVersion> {
+        self.version
+    }
+
+    /// Handles the command with `Aggregate::handle`, keeping the events for `Repository::save`.
+    pub fn handle(&mut self, command: A::Command) -> Result<(), A::Error> {
+        self.aggregate.handle(command, &mut self.pending)
+    }
+
+    fn expected_version(&self) -> ExpectedVersion {
+        match self.version {
+            Some(version) => ExpectedVersion::Exact(version),
+            None => ExpectedVersion::NoStream,
+        }
+    }
+}
+
+/// Loads and saves aggregates of type `A` through the client.
+pub struct Repository<S, A> {
+    client: S,
+    credentials: Option<UsernamePassword>,
+    snapshot_every: Option<u64>,
+    upcasters: Rc<Upcasters>,
+    aggregate: PhantomData<A>,
+}
+
+impl<S, A> Repository<S, A>
+    where S: Service<Request = Package, Response = Package, Error = io::Error> + Clone + 'static,
+          S::Future: 'static,
+          A: Aggregate + 'static,
+{
+    /// Creates a repository without snapshots.
+    pub fn new(client: S) -> Self {
+        Repository {
+            client: client,
+            credentials: None,
+            snapshot_every: None,
+            upcasters: Rc::new(Upcasters::new()),
+            aggregate: PhantomData,
+        }
+    }
+
+    /// Uses the given credentials for all requests.
+    pub fn with_credentials(mut self, credentials: UsernamePassword) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Reads the latest snapshot when loading, and writes a new snapshot when saving once the
+    /// loaded aggregate is at least `every` events past the previous snapshot.
+    pub fn with_snapshots(mut self, every: u64) -> Self {
+        self.snapshot_every = Some(every);
+        self
+    }
+
+    /// Upcasts the events with the chain before applying them when loading.
+    pub fn with_upcasters(mut self, upcasters: Rc<Upcasters>) -> Self {
+        self.upcasters = upcasters;
+        self
+    }
+
+    /// Loads the aggregate by applying the events after the latest snapshot, or every event if
+    /// there is none. A missing stream results in the default state.
+    pub fn load(&self, id: &str) -> Box<dyn Future<Item = Versioned<A>, Error = RepositoryError>> {
+        let id = id.to_owned();
+        let stream = stream_of::<A>(&id);
+        let client = self.client.clone();
+        let credentials = self.credentials.clone();
+        let upcasters = self.upcasters.clone();
+
+        let snapshot: Box<dyn Future<Item = Option<(A, StreamVersion)>, Error = RepositoryError>> = if self.snapshot_every.is_some() {
+            read_snapshot::<S, A>(&self.client, &snapshot_stream_of(&stream), self.credentials.clone())
+        } else {
+            Box::new(future::ok(None))
+        };
+
+        Box::new(snapshot.and_then(move |snapshot| {
+            let (aggregate, snapshot_version) = match snapshot {
+                Some((aggregate, version)) => (aggregate, Some(version)),
+                None => (A::default(), None),
+            };
+
+            let from = match snapshot_version {
+                Some(version) => next_event_number(version),
+                None => EventNumber::First,
+            };
+
+            read_stream_pages_upcasted(&client, &stream, from, PAGE_SIZE, credentials, upcasters)
+                .fold((aggregate, snapshot_version), |(mut aggregate, _), page| -> Result<(A, Option<StreamVersion>), ReadError> {
+                    for event in &page.events {
+                        aggregate.apply(&event.event);
+                    }
+                    Ok((aggregate, Some(page.last_event_number)))
+                })
+                .or_else(|e| match e {
+                    ReadError::Read(ReadStreamError::NoStream) => Ok((A::default(), None)),
+                    e => Err(e),
+                })
+                .from_err()
+                .map(move |(aggregate, version)| Versioned {
+                    id: id,
+                    aggregate: aggregate,
+                    version: version,
+                    snapshot_version: snapshot_version,
+                    pending: Builder::write_events(),
+                })
+        }))
+    }
+
+    /// Appends the events of the handled commands expecting the loaded version. Returns `None`
+    /// if there were no events to append.
+    ///
+    /// When snapshots are enabled and due, a snapshot of the loaded state is written after the
+    /// events have been appended. Failing to write the snapshot does not fail the save, as the
+    /// snapshot is only an optimization for loading.
+    pub fn save(&self, versioned: Versioned<A>) -> Box<dyn Future<Item = Option<WriteEventsCompleted>, Error = RepositoryError>> {
+        let expected_version = versioned.expected_version();
+        let Versioned { id, aggregate, version, snapshot_version, mut pending } = versioned;
+
+        if !pending.has_events() {
+            return Box::new(future::ok(None));
+        }
+
+        let stream = stream_of::<A>(&id);
+        let req = pending
+            .stream_id(stream.clone())
+            .expected_version(expected_version)
+            .build_package(self.credentials.clone(), None);
+
+        let due = match (self.snapshot_every, version) {
+            (Some(every), Some(version)) => {
+                let since = match snapshot_version {
+                    Some(snapshot) => u64::from(version) - u64::from(snapshot),
+                    None => u64::from(version) + 1,
+                };
+                since >= every
+            },
+            _ => false,
+        };
+
+        let snapshot = if due { aggregate.snapshot().map(|data| (data, version.unwrap())) } else { None };
+        let client = self.client.clone();
+        let credentials = self.credentials.clone();
+
+        Box::new(write_events(&self.client, req).from_err().and_then(move |completed| -> Box<dyn Future<Item = Option<WriteEventsCompleted>, Error = RepositoryError>> {
+            match snapshot {
+                Some((data, version)) => {
+                    let first = snapshot_version.is_none();
+                    let written = write_snapshot(&client, &snapshot_stream_of(&stream), data, version, first, credentials);
+                    Box::new(written.then(move |_| Ok(Some(completed))))
+                },
+                None => Box::new(future::ok(Some(completed))),
+            }
+        }))
+    }
+
+    /// Loads the aggregate, handles the command and saves the events.
+    pub fn execute(&self, id: &str, command: A::Command) -> Box<dyn Future<Item = Option<WriteEventsCompleted>, Error = ExecuteError<A::Error>>>
+        where A::Command: 'static,
+              A::Error: 'static,
+    {
+        let repository = Repository {
+            client: self.client.clone(),
+            credentials: self.credentials.clone(),
+            snapshot_every: self.snapshot_every,
+            upcasters: self.upcasters.clone(),
+            aggregate: PhantomData,
+        };
+
+        Box::new(self.load(id).map_err(ExecuteError::Repository).and_then(move |mut versioned| {
+            let handled = versioned.handle(command).map_err(ExecuteError::Rejected);
+            future::result(handled).and_then(move |_| repository.save(versioned).map_err(ExecuteError::Repository))
+        }))
+    }
+}
+
+/// Failures of `Repository::execute`.
+#[derive(Debug)]
+pub enum ExecuteError<E> {
+    /// Loading or saving the aggregate failed.
+    Repository(RepositoryError),
+    /// The aggregate rejected the command.
+    Rejected(E),
+}
+
+impl<E: fmt::Display> fmt::Display for ExecuteError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExecuteError::Repository(ref e) => write!(f, "{}", e),
+            ExecuteError::Rejected(ref e) => write!(f, "Command was rejected: {}", e),
+        }
+    }
+}
+
+impl<E: error::Error> error::Error for ExecuteError<E> {
+    fn description(&self) -> &str {
+        match *self {
+            ExecuteError::Repository(ref e) => e.description(),
+            ExecuteError::Rejected(ref e) => e.description(),
+        }
+    }
+}
+
+fn next_event_number(version: StreamVersion) -> EventNumber {
+    StreamVersion::try_from(u64::from(version) + 1)
+        .map(EventNumber::from)
+        .unwrap_or(EventNumber::Last)
+}
+
+/// Reads the latest snapshot and the version of the aggregate stream it was taken at. Missing,
+/// unreadable and invalid snapshots result in `None`.
+fn read_snapshot<S, A>(client: &S, stream: &str, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = Option<(A, StreamVersion)>, Error = RepositoryError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static,
+          A: Aggregate + 'static,
+{
+    let req = Builder::read_event()
+        .stream_id(stream.to_owned())
+        .event_number(EventNumber::Last)
+        .resolve_link_tos(false)
+        .build_package(credentials, None);
+
+    Box::new(client.call(req).map_err(RepositoryError::Io).map(|resp| {
+        let event = match resp.message.try_adapt() {
+            Ok(AdaptedMessage::ReadEventCompleted(Ok(event))) => event.event,
+            _ => return None,
+        };
+
+        let version = event.metadata.as_ref()
+            .and_then(|metadata| ::std::str::from_utf8(metadata).ok())
+            .and_then(|metadata| json::parse(metadata).ok())
+            .and_then(|metadata| metadata["version"].as_u64())
+            .and_then(|version| StreamVersion::try_from(version).ok());
+
+        match version {
+            Some(version) => A::restore(&event.data).map(|aggregate| (aggregate, version)),
+            None => None,
+        }
+    }))
+}
+
+/// Writes the snapshot taken at the given version, limiting the stream to the latest snapshot
+/// when writing the first one.
+fn write_snapshot<S>(client: &S, stream: &str, data: Vec<u8>, version: StreamVersion, first: bool, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = (), Error = RepositoryError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static,
+{
+    let mut builder = Builder::write_events();
+    builder.stream_id(stream.to_owned())
+        .expected_version(ExpectedVersion::Any)
+        .new_event()
+            .event_type(SNAPSHOT_EVENT_TYPE)
+            .data(data)
+            .metadata(format!("{{\"version\":{}}}", u64::from(version)).into_bytes())
+            .metadata_content_type(ContentType::Json)
+        .done();
+
+    let written = client.call(builder.build_package(credentials.clone(), None)).map_err(RepositoryError::Io);
+
+    if !first {
+        return Box::new(written.map(|_| ()));
+    }
+
+    let metadata = StreamMetadata { max_count: Some(1), ..StreamMetadata::default() };
+    let limited = set_stream_metadata(client, stream, ExpectedVersion::Any, &metadata, credentials)
+        .map(|_| ())
+        .map_err(|e| RepositoryError::Io(io::Error::new(io::ErrorKind::Other, e.to_string())));
+
+    Box::new(written.join(limited).map(|_| ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use tokio_core::reactor::Core;
+    use tokio_service::Service;
+
+    use super::{Aggregate, Repository, RepositoryError, ExecuteError, stream_of, snapshot_stream_of};
+    use adapted::{RecordedEvent, WriteEventsFailure};
+    use builder::{Builder, WriteEventsBuilder};
+    use concurrency::WriteError;
+    use fixtures::start;
+    use memory::MemoryStore;
+    use metadata::get_stream_metadata;
+    use {ContentType, ExpectedVersion};
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Counter(u64);
+
+    impl Aggregate for Counter {
+        type Command = u64;
+        type Error = &'static str;
+
+        fn category() -> &'static str {
+            "counter"
+        }
+
+        fn apply(&mut self, event: &RecordedEvent) {
+            self.0 += str::from_utf8(&event.data).unwrap().parse::<u64>().unwrap();
+        }
+
+        fn handle(&self, amount: u64, events: &mut WriteEventsBuilder) -> Result<(), Self::Error> {
+            if self.0 + amount > 100 {
+                return Err("too much");
+            }
+            events.new_event().event_type("added").data(amount.to_string().into_bytes()).done();
+            Ok(())
+        }
+
+        fn snapshot(&self) -> Option<Vec<u8>> {
+            Some(self.0.to_string().into_bytes())
+        }
+
+        fn restore(snapshot: &[u8]) -> Option<Self> {
+            str::from_utf8(snapshot).ok().and_then(|s| s.parse().ok()).map(Counter)
+        }
+    }
+
+    #[test]
+    fn load_handle_save() {
+        let mut core = Core::new().unwrap();
+        let repository = Repository::<_, Counter>::new(start(&core, MemoryStore::new()));
+
+        core.run(repository.execute("1", 40)).unwrap().unwrap();
+        core.run(repository.execute("1", 50)).unwrap().unwrap();
+
+        match core.run(repository.execute("1", 20)) {
+            Err(e @ ExecuteError::Rejected("too much")) => assert_eq!(e.to_string(), "Command was rejected: too much"),
+            x => panic!("unexpected {:?}", x.map(|_| ())),
+        }
+
+        let stale = core.run(repository.load("1")).unwrap();
+        assert_eq!(*stale.aggregate(), Counter(90));
+
+        let mut first = core.run(repository.load("1")).unwrap();
+        first.handle(5).unwrap();
+        core.run(repository.save(first)).unwrap();
+
+        let mut stale = stale;
+        stale.handle(5).unwrap();
+        match core.run(repository.save(stale)) {
+            Err(RepositoryError::Write(WriteError::Failed(WriteEventsFailure::WrongExpectedVersion))) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn loads_from_snapshot() {
+        let mut core = Core::new().unwrap();
+        let client = start(&core, MemoryStore::new());
+        let repository = Repository::<_, Counter>::new(client.clone()).with_snapshots(2);
+
+        for _ in 0..3 {
+            core.run(repository.execute("2", 1)).unwrap().unwrap();
+        }
+
+        // the third save wrote a snapshot of the two first events
+        let snapshots = snapshot_stream_of(&stream_of::<Counter>("2"));
+        let metadata = core.run(get_stream_metadata(&client, &snapshots, None)).unwrap();
+        assert_eq!(metadata.metadata.max_count, Some(1));
+
+        // replace the snapshot to see that it is used
+        let mut builder = Builder::write_events();
+        builder.stream_id(snapshots).expected_version(ExpectedVersion::Any)
+            .new_event()
+                .event_type("snapshot")
+                .data(&b"50"[..])
+                .metadata(&b"{\"version\":1}"[..])
+                .metadata_content_type(ContentType::Json)
+            .done();
+        core.run(client.call(builder.build_package(None, None))).unwrap();
+
+        let loaded = core.run(repository.load("2")).unwrap();
+        assert_eq!(*loaded.aggregate(), Counter(51));
+        assert_eq!(loaded.version().map(u64::from), Some(2));
+    }
+}
@@ -18,7 +18,7 @@ use std::borrow::Cow;
 use uuid::Uuid;
 use package::Package;
 use {UsernamePassword, ReadDirection, ExpectedVersion, EventNumber, LogPosition, ContentType};
-use raw::client_messages::{WriteEvents, NewEvent, ReadEvent, ReadStreamEvents, ReadAllEvents, DeleteStream};
+use raw::client_messages::{WriteEvents, NewEvent, ReadEvent, ReadStreamEvents, ReadAllEvents, DeleteStream, IdentifyClient};
 use raw::RawMessage;
 
 macro_rules! common_stream_id {
@@ -35,7 +35,8 @@ macro_rules! common_stream_id {
 
 macro_rules! common_expected_version {
     () => {
-        /// Sets the expected version of the stream as an optimistic locking mechanism.
+        /// Sets the expected version of the stream as an optimistic locking mechanism. Accepts any
+        /// `ExpectedVersion`, including `StreamExists`, or a `StreamVersion` for an exact version.
         pub fn expected_version<V: Into<ExpectedVersion>>(&mut self, version: V) -> &mut Self {
             self.expected_version = Some(version.into());
             self
@@ -144,6 +145,11 @@ impl Builder {
     pub fn read_all_events() -> ReadAllEventsBuilder {
         ReadAllEventsBuilder::new()
     }
+
+    /// Builder for `IdentifyClient` which names the connection on servers supporting it.
+    pub fn identify_client() -> IdentifyClientBuilder {
+        IdentifyClientBuilder::new()
+    }
 }
 
 /// Builder for messages without any additional contents.
@@ -209,6 +215,11 @@ impl WriteEventsBuilder {
         NewEventBuilder::new(self)
     }
 
+    /// `true` if any events have been added.
+    pub(crate) fn has_events(&self) -> bool {
+        !self.events.is_empty()
+    }
+
     fn push_event(&mut self, event: NewEvent<'static>) -> &mut Self {
         self.events.push(event);
         self
@@ -569,6 +580,56 @@ impl DeleteStreamBuilder {
     common_build_package!();
 }
 
+/// The client version sent in `IdentifyClient`, the same as the one of the .NET client.
+pub const CLIENT_VERSION: i32 = 1;
+
+/// Builder for an `IdentifyClient` message, which makes the connection name visible in the server
+/// connection statistics and logs. Servers older than EventStore 4 respond with `BadRequest`.
+///
+/// # Example
+///
+/// ```
+/// use eventstore_tcp::Builder;
+///
+/// let package = Builder::identify_client()
+///     .connection_name("billing-worker-1")
+///     .build_package(None, None);
+/// ```
+pub struct IdentifyClientBuilder {
+    version: Option<i32>,
+    connection_name: Option<Cow<'static, str>>,
+}
+
+impl IdentifyClientBuilder {
+    fn new() -> Self {
+        IdentifyClientBuilder {
+            version: None,
+            connection_name: None,
+        }
+    }
+
+    /// Sets the client version. Defaults to `CLIENT_VERSION`.
+    pub fn version(&mut self, version: i32) -> &mut Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Sets the name of the connection. Defaults to no name, in which case the server makes one up.
+    pub fn connection_name<S: Into<Cow<'static, str>>>(&mut self, name: S) -> &mut Self {
+        self.connection_name = Some(name.into());
+        self
+    }
+
+    fn build_message(&mut self) -> RawMessage<'static> {
+        RawMessage::IdentifyClient(IdentifyClient {
+            version: self.version.unwrap_or(CLIENT_VERSION),
+            connection_name: self.connection_name.take(),
+        })
+    }
+
+    common_build_package!();
+}
+
 fn build_package<M: Into<RawMessage<'static>>>(msg: M, authentication: Option<UsernamePassword>, correlation_id: Option<Uuid>) -> Package {
     Package {
         authentication: authentication,
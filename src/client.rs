@@ -1,25 +1,103 @@
 use std::io;
+use std::rc::Rc;
+use std::cell::Cell;
 use std::net::SocketAddr;
+use std::time::Duration;
 
-use futures::Future;
+use futures::{future, Future, Stream};
+use futures::future::{Either, Loop};
 
-use tokio_core::reactor::Handle;
+use tokio_core::reactor::{Handle, Timeout, Interval};
 use tokio_io::{AsyncWrite, AsyncRead};
 use tokio_io::codec::{Encoder, Decoder, Framed};
 use tokio_core::net::TcpStream;
-use tokio_proto::TcpClient;
-use tokio_proto::multiplex::{ClientProto, ClientService, NewRequestIdSource, RequestIdSource};
+use tokio_proto::BindClient;
+use tokio_proto::multiplex::{ClientProto, NewRequestIdSource, RequestIdSource};
 use tokio_service::Service;
 use bytes::BytesMut;
 
 use package::Package;
+use builder::Builder;
 use codec::PackageCodec;
+use raw::RawMessage;
+use settings::ConnectionSettings;
+use {UsernamePassword, ProtocolVersion};
 
 use uuid::Uuid;
 
+/// Type erased client service bound to some transport.
+type BoxedService = dyn Service<Request = Package, Response = Package, Error = io::Error, Future = Box<dyn Future<Item = Package, Error = io::Error>>>;
+
 /// `tokio_service::Service` implementation of the client.
+#[derive(Clone)]
 pub struct EventStoreClient {
-    inner: ClientService<TcpStream, PackageProto>,
+    inner: Rc<BoxedService>,
+    options: Rc<Options>,
+}
+
+/// Boxes the futures of the wrapped service.
+struct Boxing<S>(S);
+
+impl<S> Service for Boxing<S>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static
+{
+    type Request = Package;
+    type Response = Package;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = Package, Error = io::Error>>;
+
+    fn call(&self, req: Package) -> Self::Future {
+        Box::new(self.0.call(req))
+    }
+}
+
+/// Per connection options derived from `ConnectionSettings`.
+struct Options {
+    handle: Handle,
+    credentials: Option<UsernamePassword>,
+    require_master: bool,
+    operation_timeout: Option<Duration>,
+    max_in_flight: usize,
+    protocol_version: Cell<ProtocolVersion>,
+    in_flight: Cell<usize>,
+    broken: Cell<bool>,
+}
+
+impl Options {
+    fn new(handle: &Handle) -> Self {
+        Options {
+            handle: handle.clone(),
+            credentials: None,
+            require_master: false,
+            operation_timeout: None,
+            max_in_flight: usize::max_value(),
+            protocol_version: Cell::new(ProtocolVersion::default()),
+            in_flight: Cell::new(0),
+            broken: Cell::new(false),
+        }
+    }
+
+    fn from_settings(settings: &ConnectionSettings, handle: &Handle) -> Self {
+        Options {
+            credentials: settings.default_credentials.clone(),
+            require_master: settings.require_master,
+            operation_timeout: settings.operation_timeout,
+            max_in_flight: settings.max_in_flight,
+            protocol_version: Cell::new(settings.protocol_version),
+            ..Options::new(handle)
+        }
+    }
+}
+
+/// Decrements the in-flight counter when dropped.
+struct InFlight(Rc<Options>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let current = self.0.in_flight.get();
+        self.0.in_flight.set(current - 1);
+    }
 }
 
 impl EventStoreClient {
@@ -28,14 +106,156 @@ impl EventStoreClient {
     /// Returns a future representing the client which can be used to send and receive `Package`
     /// values.
     pub fn connect(addr: &SocketAddr, handle: &Handle) -> Box<dyn Future<Item = Self, Error = io::Error>> {
-        let ret = TcpClient::new(PackageProto)
-            .connect(addr, handle)
-            .map(|client_service| {
-                EventStoreClient { inner: client_service }
-            });
+        let handle = handle.clone();
+        let ret = TcpStream::connect(addr, &handle)
+            .map(move |stream| EventStoreClient::from_transport(stream, &handle));
+
+        Box::new(ret)
+    }
+
+    /// Creates a client over an already established transport, such as an Unix domain socket,
+    /// a stream tunneled through a proxy or an in-memory `pipe::duplex`. The transport is used
+    /// as is, so any handshakes (like TLS) must have been completed already.
+    pub fn from_transport<T>(io: T, handle: &Handle) -> Self
+        where T: AsyncRead + AsyncWrite + 'static
+    {
+        EventStoreClient::bind(io, Options::new(handle))
+    }
+
+    /// Creates a client over an already established transport like `from_transport` but applies
+    /// the `ConnectionSettings` excluding the ones related to connecting.
+    pub fn from_transport_with_settings<T>(io: T, settings: &ConnectionSettings, handle: &Handle) -> io::Result<Self>
+        where T: AsyncRead + AsyncWrite + 'static
+    {
+        let client = EventStoreClient::bind(io, Options::from_settings(settings, handle));
+
+        if let Some(interval) = settings.heartbeat_interval {
+            client.spawn_heartbeats(interval, settings.heartbeat_timeout, handle)?;
+        }
+
+        Ok(client)
+    }
+
+    /// Connect to an EventStore database using the given `ConnectionSettings`. The host is
+    /// resolved and every resolved address is tried in order. If none of the addresses can be
+    /// connected to, connecting is retried `settings.max_reconnections` times.
+    ///
+    /// The returned client will add the default credentials to requests without any, apply the
+    /// operation timeout and send heartbeats according to the settings. With the `tls` feature
+    /// the connection is secured when `settings.tls` is set.
+    pub fn connect_with_settings(settings: &ConnectionSettings, handle: &Handle) -> Box<dyn Future<Item = Self, Error = io::Error>> {
+        let addrs = match settings.resolve() {
+            Ok(addrs) => addrs,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let settings = settings.clone();
+        let handle = handle.clone();
+
+        let ret = future::loop_fn(0, move |attempt| {
+            let retry_handle = handle.clone();
+            let delay = settings.reconnection_delay;
+            let max_reconnections = settings.max_reconnections;
+
+            connect_first(addrs.clone(), &settings, &handle)
+                .then(move |res| -> Box<dyn Future<Item = Loop<EventStoreClient, usize>, Error = io::Error>> {
+                    match res {
+                        Ok(client) => Box::new(future::ok(Loop::Break(client))),
+                        Err(_) if attempt < max_reconnections => {
+                            let next = future::result(Timeout::new(delay, &retry_handle))
+                                .flatten()
+                                .map(move |_| Loop::Continue(attempt + 1));
+                            Box::new(next)
+                        },
+                        Err(e) => Box::new(future::err(e)),
+                    }
+                })
+        });
 
         Box::new(ret)
     }
+
+    /// Identifies the client to the server with the given connection name, which will show up
+    /// in the connection statistics and logs of the server. The returned future resolves to this
+    /// client when the server has acknowledged the name. Servers older than EventStore 4 respond
+    /// with `BadRequest`, which is ignored.
+    ///
+    /// As only EventStore 4 and newer acknowledge the identification, the client switches to
+    /// `ProtocolVersion::V4` when it is acknowledged.
+    pub fn identify<S: Into<String>>(self, connection_name: S) -> Box<dyn Future<Item = Self, Error = io::Error>> {
+        let req = Builder::identify_client()
+            .connection_name(connection_name.into())
+            .build_package(None, None);
+
+        Box::new(self.call(req).and_then(move |resp| {
+            match resp.message {
+                RawMessage::ClientIdentified => {
+                    self.options.protocol_version.set(ProtocolVersion::V4);
+                    Ok(self)
+                },
+                RawMessage::BadRequest(_) => Ok(self),
+                other => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected response to IdentifyClient: {:?}", other))),
+            }
+        }))
+    }
+
+    /// The version of the protocol used for checking the requests, see `ProtocolVersion`.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.options.protocol_version.get()
+    }
+
+    fn bind<T>(io: T, options: Options) -> Self
+        where T: AsyncRead + AsyncWrite + 'static
+    {
+        let service = PackageProto.bind_client(&options.handle, io);
+
+        EventStoreClient {
+            inner: Rc::new(Boxing(service)),
+            options: Rc::new(options),
+        }
+    }
+
+    /// Periodically sends `HeartbeatRequest` until the client is dropped or the server fails to
+    /// respond in time, after which the client is marked broken.
+    fn spawn_heartbeats(&self, interval: Duration, timeout: Duration, handle: &Handle) -> io::Result<()> {
+        let inner = Rc::downgrade(&self.inner);
+        let options = Rc::downgrade(&self.options);
+        let timer_handle = handle.clone();
+
+        let beats = Interval::new(interval, handle)?
+            .map_err(|_| ())
+            .for_each(move |_| -> Box<dyn Future<Item = (), Error = ()>> {
+                let service = match inner.upgrade() {
+                    Some(service) => service,
+                    // client has been dropped
+                    None => return Box::new(future::err(())),
+                };
+
+                let options = options.clone();
+                let req = Package {
+                    authentication: None,
+                    correlation_id: Uuid::new_v4(),
+                    message: RawMessage::HeartbeatRequest,
+                };
+
+                let beat = with_timeout(service.call(req), timeout, &timer_handle, "heartbeat timed out");
+
+                Box::new(beat.then(move |res| {
+                    match res {
+                        Ok(_) => Ok(()),
+                        Err(_) => {
+                            if let Some(options) = options.upgrade() {
+                                options.broken.set(true);
+                            }
+                            Err(())
+                        }
+                    }
+                }))
+            });
+
+        handle.spawn(beats);
+        Ok(())
+    }
 }
 
 impl Service for EventStoreClient {
@@ -44,9 +264,125 @@ impl Service for EventStoreClient {
     type Error = io::Error;
     type Future = Box<dyn Future<Item = Package, Error = io::Error>>;
 
-    fn call(&self, req: Package) -> Self::Future {
-        self.inner.call(req).boxed()
+    fn call(&self, mut req: Package) -> Self::Future {
+        let options = &self.options;
+
+        if options.broken.get() {
+            return Box::new(future::err(io::Error::new(io::ErrorKind::BrokenPipe, "connection has failed to respond to heartbeats")));
+        }
+
+        if options.in_flight.get() >= options.max_in_flight {
+            return Box::new(future::err(io::Error::new(io::ErrorKind::Other, "too many requests in flight")));
+        }
+
+        if let Err(e) = options.protocol_version.get().check(&req.message) {
+            return Box::new(future::err(io::Error::new(io::ErrorKind::InvalidInput, e.to_string())));
+        }
+
+        if req.authentication.is_none() {
+            req.authentication = options.credentials.clone();
+        }
+
+        if options.require_master {
+            force_require_master(&mut req.message);
+        }
+
+        options.in_flight.set(options.in_flight.get() + 1);
+        let guard = InFlight(self.options.clone());
+
+        let resp = self.inner.call(req);
+        let resp = match options.operation_timeout {
+            Some(timeout) => with_timeout(resp, timeout, &options.handle, "operation timed out"),
+            None => Box::new(resp),
+        };
+
+        Box::new(resp.then(move |res| {
+            drop(guard);
+            res
+        }))
+    }
+}
+
+fn force_require_master(msg: &mut RawMessage<'static>) {
+    match *msg {
+        RawMessage::WriteEvents(ref mut x) => x.require_master = true,
+        RawMessage::DeleteStream(ref mut x) => x.require_master = true,
+        RawMessage::ReadEvent(ref mut x) => x.require_master = true,
+        RawMessage::ReadStreamEvents(_, ref mut x) => x.require_master = true,
+        RawMessage::ReadAllEvents(_, ref mut x) => x.require_master = true,
+        _ => {},
+    }
+}
+
+type Connecting = Box<dyn Future<Item = EventStoreClient, Error = io::Error>>;
+
+/// Connects to the first address accepting the connection in the configured time.
+fn connect_first(addrs: Vec<SocketAddr>, settings: &ConnectionSettings, handle: &Handle) -> Connecting {
+    let settings = settings.clone();
+    let handle = handle.clone();
+    let initial: Connecting =
+        Box::new(future::err(io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")));
+
+    addrs.into_iter().fold(initial, move |prev, addr| {
+        let settings = settings.clone();
+        let handle = handle.clone();
+        let next: Connecting = Box::new(prev.or_else(move |_| {
+            let attempt = establish(&addr, &settings, &handle);
+            with_timeout(attempt, settings.connection_timeout, &handle, "connection attempt timed out")
+        }));
+        next
+    })
+}
+
+/// Connects to a single address, performing the TLS handshake and identifying the client when
+/// configured.
+fn establish(addr: &SocketAddr, settings: &ConnectionSettings, handle: &Handle) -> Connecting {
+    let name = settings.connection_name.clone();
+    let connected = connect_transport(addr, settings, handle);
+
+    match name {
+        Some(name) => Box::new(connected.and_then(move |client| client.identify(name))),
+        None => connected,
+    }
+}
+
+fn connect_transport(addr: &SocketAddr, settings: &ConnectionSettings, handle: &Handle) -> Connecting {
+    let settings = settings.clone();
+    let handle = handle.clone();
+    let stream = TcpStream::connect(addr, &handle);
+
+    #[cfg(feature = "tls")]
+    {
+        if let Some(tls) = settings.tls.clone() {
+            let host = settings.host.clone();
+            let ret = stream
+                .and_then(move |stream| ::tls::handshake(stream, &tls, &host))
+                .and_then(move |stream| EventStoreClient::from_transport_with_settings(stream, &settings, &handle));
+            return Box::new(ret);
+        }
     }
+
+    Box::new(stream.and_then(move |stream| EventStoreClient::from_transport_with_settings(stream, &settings, &handle)))
+}
+
+/// Fails the future with `io::ErrorKind::TimedOut` if it does not complete in time.
+pub fn with_timeout<F>(f: F, duration: Duration, handle: &Handle, what: &'static str) -> Box<dyn Future<Item = F::Item, Error = io::Error>>
+    where F: Future<Error = io::Error> + 'static,
+          F::Item: 'static
+{
+    let timeout = match Timeout::new(duration, handle) {
+        Ok(timeout) => timeout,
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    Box::new(f.select2(timeout).then(move |res| {
+        match res {
+            Ok(Either::A((item, _))) => Ok(item),
+            Ok(Either::B((_, _))) => Err(io::Error::new(io::ErrorKind::TimedOut, what)),
+            Err(Either::A((e, _))) => Err(e),
+            Err(Either::B((e, _))) => Err(e),
+        }
+    }))
 }
 
 /*
@@ -124,3 +460,91 @@ impl<T: AsyncRead + AsyncWrite + 'static> ClientProto<T> for PackageProto {
         Ok(io.framed(Separator))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use futures::{Future, Stream, Sink};
+    use tokio_core::reactor::{Core, Handle};
+    use tokio_io::AsyncRead;
+    use tokio_service::Service;
+    use super::EventStoreClient;
+    use codec::PackageCodec;
+    use package::Package;
+    use raw::RawMessage;
+    use builder::Builder;
+    use pipe;
+    use ProtocolVersion;
+
+    #[test]
+    fn ping_over_in_memory_transport() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let (client_io, server_io) = pipe::duplex();
+        let (sink, stream) = server_io.framed(PackageCodec).split();
+
+        let server = stream.take(1)
+            .map(|req: Package| {
+                assert_eq!(req.message, RawMessage::Ping);
+                Package {
+                    authentication: None,
+                    correlation_id: req.correlation_id,
+                    message: RawMessage::Pong,
+                }
+            })
+            .forward(sink)
+            .map(|_| ())
+            .map_err(|e| panic!("server failed: {}", e));
+
+        handle.spawn(server);
+
+        let client = EventStoreClient::from_transport(client_io, &handle);
+        let resp = core.run(client.call(Builder::ping().build_package(None, None))).unwrap();
+
+        assert_eq!(resp.message, RawMessage::Pong);
+    }
+
+    /// Answers a single request with the given message.
+    fn answer_once(response: RawMessage<'static>, handle: &Handle) -> EventStoreClient {
+        let (client_io, server_io) = pipe::duplex();
+        let (sink, stream) = server_io.framed(PackageCodec).split();
+
+        let server = stream.take(1)
+            .map(move |req: Package| {
+                match req.message {
+                    RawMessage::IdentifyClient(ref x) => assert_eq!(x.connection_name.as_ref().map(|x| &x[..]), Some("billing")),
+                    ref other => panic!("unexpected request: {:?}", other),
+                }
+                Package {
+                    authentication: None,
+                    correlation_id: req.correlation_id,
+                    message: response.clone(),
+                }
+            })
+            .forward(sink)
+            .map(|_| ())
+            .map_err(|e| panic!("server failed: {}", e));
+
+        handle.spawn(server);
+        EventStoreClient::from_transport(client_io, handle)
+    }
+
+    #[test]
+    fn identify_upgrades_protocol() {
+        let mut core = Core::new().unwrap();
+        let client = answer_once(RawMessage::ClientIdentified, &core.handle());
+
+        let client = core.run(client.identify("billing")).unwrap();
+        assert_eq!(client.protocol_version(), ProtocolVersion::V4);
+    }
+
+    #[test]
+    fn identify_falls_back_on_bad_request() {
+        let mut core = Core::new().unwrap();
+        let client = answer_once(RawMessage::BadRequest(Cow::Borrowed(&b""[..]).into()), &core.handle());
+
+        let client = core.run(client.identify("billing")).unwrap();
+        assert_eq!(client.protocol_version(), ProtocolVersion::V3);
+    }
+}
@@ -0,0 +1,725 @@
+//! Cluster aware connections. Instead of connecting to a fixed node, the members of the cluster
+//! are discovered by asking the gossip endpoints of seed nodes and a node is chosen according to
+//! a `NodePreference`.
+//!
+//! `ClusterClient` repeats the discovery whenever the connection is lost or the connected node
+//! responds with `NotHandled(NotMaster)`.
+//!
+//! The gossip is fetched through the `GossipSource` trait. `HttpGossip` queries the
+//! `/gossip?format=json` HTTP endpoint of the seeds, but any source such as a static list can be
+//! used.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! extern crate futures;
+//! extern crate tokio_core;
+//! extern crate tokio_service;
+//! extern crate eventstore_tcp;
+//!
+//! use futures::Future;
+//! use tokio_core::reactor::Core;
+//! use tokio_service::Service;
+//! use eventstore_tcp::Builder;
+//! use eventstore_tcp::cluster::{ClusterClient, ClusterSettings, GossipSeed};
+//!
+//! fn main() {
+//!     let mut core = Core::new().unwrap();
+//!
+//!     let settings = ClusterSettings::new(vec![
+//!         GossipSeed::new("es1.internal", 2113),
+//!         GossipSeed::new("es2.internal", 2113),
+//!         GossipSeed::new("es3.internal", 2113),
+//!     ]);
+//!
+//!     let job = ClusterClient::connect(&settings, &core.handle())
+//!         .and_then(|client| client.call(Builder::ping().build_package(None, None)));
+//!
+//!     core.run(job).unwrap();
+//! }
+//! ```
+
+use std::io;
+use std::rc::{Rc, Weak};
+use std::cell::{Cell, RefCell};
+use std::net::ToSocketAddrs;
+use std::str;
+use std::time::Duration;
+
+use futures::{future, Future};
+use futures::future::{Loop, Shared};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::io::{write_all, read_to_end};
+use tokio_service::Service;
+use json;
+use rand::{self, Rng};
+use uuid::Uuid;
+
+use client::{EventStoreClient, with_timeout};
+use package::Package;
+use raw::RawMessage;
+use raw::client_messages::mod_NotHandled::NotHandledReason;
+use settings::ConnectionSettings;
+
+/// HTTP endpoint of a node used for fetching the gossip.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GossipSeed {
+    /// Hostname or an IP address of the node.
+    pub host: String,
+    /// Port of the HTTP interface, usually 2113.
+    pub port: u16,
+}
+
+impl GossipSeed {
+    /// Creates a new seed for the HTTP interface at host and port.
+    pub fn new<S: Into<String>>(host: S, port: u16) -> Self {
+        GossipSeed {
+            host: host.into(),
+            port: port,
+        }
+    }
+}
+
+/// State of a cluster member as reported by gossip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VNodeState {
+    /// The node is starting up.
+    Initializing,
+    /// State could not be determined, also used for any unrecognized states.
+    Unknown,
+    /// The node is about to become a replica.
+    PreReplica,
+    /// The node is catching up with the master.
+    CatchingUp,
+    /// The node is an up-to-date clone, not part of the quorum.
+    Clone,
+    /// The node is a follower in the quorum.
+    Slave,
+    /// The node is about to become master.
+    PreMaster,
+    /// The node is the master and accepts writes.
+    Master,
+    /// The cluster manager process.
+    Manager,
+    /// The node is shutting down.
+    ShuttingDown,
+    /// The node has shut down.
+    Shutdown,
+}
+
+impl<'a> From<&'a str> for VNodeState {
+    fn from(s: &'a str) -> Self {
+        use self::VNodeState::*;
+        match s {
+            "Initializing" => Initializing,
+            "PreReplica" => PreReplica,
+            "CatchingUp" => CatchingUp,
+            "Clone" => Clone,
+            "Slave" => Slave,
+            "PreMaster" => PreMaster,
+            "Master" => Master,
+            "Manager" => Manager,
+            "ShuttingDown" => ShuttingDown,
+            "Shutdown" => Shutdown,
+            _ => Unknown,
+        }
+    }
+}
+
+/// Information about a single member of the cluster.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberInfo {
+    /// Unique id of the node instance, if reported.
+    pub instance_id: Option<Uuid>,
+    /// Reported state of the node.
+    pub state: VNodeState,
+    /// `false` if the node is not responding to the gossip of other nodes.
+    pub is_alive: bool,
+    /// Address of the external TCP interface.
+    pub external_tcp_ip: String,
+    /// Port of the external TCP interface.
+    pub external_tcp_port: u16,
+    /// Port of the external secure TCP interface, zero when disabled.
+    pub external_secure_tcp_port: u16,
+    /// Address of the external HTTP interface.
+    pub external_http_ip: String,
+    /// Port of the external HTTP interface.
+    pub external_http_port: u16,
+}
+
+impl MemberInfo {
+    /// Creates a live member with the given state and external TCP interface. Mostly useful for
+    /// custom `GossipSource` implementations.
+    pub fn new<S: Into<String>>(state: VNodeState, tcp_ip: S, tcp_port: u16) -> Self {
+        let ip = tcp_ip.into();
+        MemberInfo {
+            instance_id: None,
+            state: state,
+            is_alive: true,
+            external_http_ip: ip.clone(),
+            external_tcp_ip: ip,
+            external_tcp_port: tcp_port,
+            external_secure_tcp_port: 0,
+            external_http_port: 2113,
+        }
+    }
+
+    /// Settings for connecting to this member, based on the given template.
+    pub fn connection_settings(&self, template: &ConnectionSettings) -> ConnectionSettings {
+        let mut settings = template.clone();
+        settings.host = self.external_tcp_ip.clone();
+        settings.port = self.external_tcp_port;
+
+        #[cfg(feature = "tls")]
+        {
+            if settings.tls.is_some() && self.external_secure_tcp_port != 0 {
+                settings.port = self.external_secure_tcp_port;
+            }
+        }
+
+        settings
+    }
+
+    /// The HTTP interface of this member as a gossip seed.
+    pub fn gossip_seed(&self) -> GossipSeed {
+        GossipSeed::new(self.external_http_ip.clone(), self.external_http_port)
+    }
+}
+
+/// Parses the JSON returned by the `/gossip` endpoint.
+pub fn parse_gossip(s: &str) -> io::Result<Vec<MemberInfo>> {
+    let parsed = json::parse(s).map_err(|e| invalid_data(format!("invalid gossip json: {}", e)))?;
+
+    if !parsed["members"].is_array() {
+        return Err(invalid_data("gossip did not contain members".into()));
+    }
+
+    parsed["members"].members()
+        .map(|member| {
+            let port = |key: &str| -> io::Result<u16> {
+                member[key].as_u16().ok_or_else(|| invalid_data(format!("missing or invalid {}", key)))
+            };
+
+            let string = |key: &str| -> io::Result<String> {
+                member[key].as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| invalid_data(format!("missing or invalid {}", key)))
+            };
+
+            Ok(MemberInfo {
+                instance_id: member["instanceId"].as_str().and_then(|s| Uuid::parse_str(s).ok()),
+                state: member["state"].as_str().map(VNodeState::from).unwrap_or(VNodeState::Unknown),
+                is_alive: member["isAlive"].as_bool().unwrap_or(false),
+                external_tcp_ip: string("externalTcpIp")?,
+                external_tcp_port: port("externalTcpPort")?,
+                external_secure_tcp_port: member["externalSecureTcpPort"].as_u16().unwrap_or(0),
+                external_http_ip: string("externalHttpIp")?,
+                external_http_port: port("externalHttpPort")?,
+            })
+        })
+        .collect()
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Source of the cluster member information.
+pub trait GossipSource {
+    /// Fetches the member list as seen by the given seed.
+    fn fetch(&self, seed: &GossipSeed) -> Box<dyn Future<Item = Vec<MemberInfo>, Error = io::Error>>;
+}
+
+/// Fetches gossip from the HTTP interface of the seeds using plain HTTP/1.0.
+pub struct HttpGossip {
+    handle: Handle,
+}
+
+impl HttpGossip {
+    /// Creates a new source using the given handle for the HTTP connections.
+    pub fn new(handle: &Handle) -> Self {
+        HttpGossip { handle: handle.clone() }
+    }
+}
+
+impl GossipSource for HttpGossip {
+    fn fetch(&self, seed: &GossipSeed) -> Box<dyn Future<Item = Vec<MemberInfo>, Error = io::Error>> {
+        let addr = match (seed.host.as_str(), seed.port).to_socket_addrs().map(|mut addrs| addrs.next()) {
+            Ok(Some(addr)) => addr,
+            Ok(None) => return Box::new(future::err(io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to any address", seed.host)))),
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let request = format!("GET /gossip?format=json HTTP/1.0\r\nHost: {}:{}\r\nAccept: application/json\r\n\r\n", seed.host, seed.port);
+
+        let ret = TcpStream::connect(&addr, &self.handle)
+            .and_then(move |stream| write_all(stream, request.into_bytes()))
+            .and_then(|(stream, _)| read_to_end(stream, Vec::new()))
+            .and_then(|(_, response)| parse_http_response(&response));
+
+        Box::new(ret)
+    }
+}
+
+fn parse_http_response(response: &[u8]) -> io::Result<Vec<MemberInfo>> {
+    let split = response.windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid_data("incomplete http response".into()))?;
+
+    let head = str::from_utf8(&response[..split]).map_err(|_| invalid_data("invalid http response".into()))?;
+    let status = head.lines().next().and_then(|line| line.split(' ').nth(1));
+
+    if status != Some("200") {
+        return Err(invalid_data(format!("unexpected gossip response: {}", head.lines().next().unwrap_or(""))));
+    }
+
+    let body = str::from_utf8(&response[split + 4..]).map_err(|_| invalid_data("gossip was not utf-8".into()))?;
+    parse_gossip(body)
+}
+
+/// Which member of the cluster to connect to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodePreference {
+    /// Always connect to the master.
+    Master,
+    /// Prefer a follower, falling back to a clone and finally to the master.
+    FollowerForReads,
+    /// Any live master, follower or clone.
+    Random,
+}
+
+impl NodePreference {
+    /// Chooses a live member according to the preference. Members with equal rank are chosen
+    /// randomly.
+    pub fn select<'a>(&self, members: &'a [MemberInfo]) -> Option<&'a MemberInfo> {
+        let ranked = |states: &[VNodeState]| -> Vec<&'a MemberInfo> {
+            members.iter()
+                .filter(|m| m.is_alive && states.contains(&m.state))
+                .collect()
+        };
+
+        let candidates = match *self {
+            NodePreference::Master => ranked(&[VNodeState::Master]),
+            NodePreference::FollowerForReads => {
+                [VNodeState::Slave, VNodeState::Clone, VNodeState::Master].iter()
+                    .map(|state| ranked(&[*state]))
+                    .find(|found| !found.is_empty())
+                    .unwrap_or_else(Vec::new)
+            },
+            NodePreference::Random => ranked(&[VNodeState::Master, VNodeState::Slave, VNodeState::Clone]),
+        };
+
+        rand::thread_rng().choose(&candidates).cloned()
+    }
+}
+
+/// Settings for the cluster discovery.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterSettings {
+    /// Nodes whose gossip endpoints are queried, in order, until one responds.
+    pub seeds: Vec<GossipSeed>,
+    /// Which member to connect to. Defaults to `NodePreference::Master`.
+    pub preference: NodePreference,
+    /// Maximum time to wait for a single seed to respond. Defaults to one second.
+    pub gossip_timeout: Duration,
+    /// How many times the seeds are queried before giving up, when no seed responds or no
+    /// suitable member is found. Defaults to 10.
+    pub max_discover_attempts: usize,
+    /// Delay between the discovery attempts. Defaults to 500 milliseconds.
+    pub discover_delay: Duration,
+    /// Settings used to connect to the chosen member, only the host and port are replaced.
+    pub connection: ConnectionSettings,
+}
+
+impl ClusterSettings {
+    /// Default settings for the given seeds.
+    pub fn new(seeds: Vec<GossipSeed>) -> Self {
+        ClusterSettings {
+            seeds: seeds,
+            preference: NodePreference::Master,
+            gossip_timeout: Duration::from_secs(1),
+            max_discover_attempts: 10,
+            discover_delay: Duration::from_millis(500),
+            connection: ConnectionSettings::default(),
+        }
+    }
+}
+
+type Fetching = Box<dyn Future<Item = Vec<MemberInfo>, Error = io::Error>>;
+
+/// Fetches the member list from the first seed responding in time.
+pub fn fetch_members(source: Rc<dyn GossipSource>, seeds: &[GossipSeed], timeout: Duration, handle: &Handle) -> Box<dyn Future<Item = Vec<MemberInfo>, Error = io::Error>> {
+    let handle = handle.clone();
+    let initial: Fetching = Box::new(future::err(io::Error::new(io::ErrorKind::NotFound, "no gossip seeds")));
+
+    seeds.iter().cloned().fold(initial, move |prev, seed| {
+        let source = source.clone();
+        let handle = handle.clone();
+        let next: Fetching = Box::new(prev.or_else(move |_| {
+            with_timeout(source.fetch(&seed), timeout, &handle, "gossip request timed out")
+        }));
+        next
+    })
+}
+
+/// Finds a member to connect to by repeatedly fetching the gossip until a member matching the
+/// preference is found or `settings.max_discover_attempts` is reached.
+pub fn discover(source: Rc<dyn GossipSource>, settings: &ClusterSettings, handle: &Handle) -> Box<dyn Future<Item = MemberInfo, Error = io::Error>> {
+    let settings = settings.clone();
+    let handle = handle.clone();
+
+    let ret = future::loop_fn(1, move |attempt| {
+        let preference = settings.preference;
+        let max_attempts = settings.max_discover_attempts;
+        let delay = settings.discover_delay;
+        let retry_handle = handle.clone();
+
+        fetch_members(source.clone(), &settings.seeds, settings.gossip_timeout, &handle)
+            .map(move |members| preference.select(&members).cloned())
+            .then(move |res| -> Box<dyn Future<Item = Loop<MemberInfo, usize>, Error = io::Error>> {
+                let err = match res {
+                    Ok(Some(member)) => return Box::new(future::ok(Loop::Break(member))),
+                    Ok(None) => io::Error::new(io::ErrorKind::NotFound, "no suitable cluster member found"),
+                    Err(e) => e,
+                };
+
+                if attempt >= max_attempts {
+                    return Box::new(future::err(err));
+                }
+
+                let next = future::result(Timeout::new(delay, &retry_handle))
+                    .flatten()
+                    .map(move |_| Loop::Continue(attempt + 1));
+                Box::new(next)
+            })
+    });
+
+    Box::new(ret)
+}
+
+type Connecting = Box<dyn Future<Item = (u64, EventStoreClient), Error = io::Error>>;
+
+enum State {
+    Disconnected,
+    Connecting(Shared<Connecting>),
+    Connected(u64, EventStoreClient),
+}
+
+struct Inner {
+    source: Rc<dyn GossipSource>,
+    settings: ClusterSettings,
+    handle: Handle,
+    state: RefCell<State>,
+    /// Increased on every new connection so that failures of old connections are not mistaken for
+    /// failures of the current one.
+    generation: Cell<u64>,
+}
+
+/// `tokio_service::Service` connecting to a member of the cluster found through gossip. The
+/// discovery is repeated on the next request after the connection has been lost or the member
+/// responded with `NotHandled(NotMaster)`. Requests answered with `NotMaster` are resent once
+/// after the rediscovery.
+#[derive(Clone)]
+pub struct ClusterClient {
+    inner: Rc<Inner>,
+}
+
+impl ClusterClient {
+    /// Discovers the cluster through the HTTP gossip of the seeds and connects to the preferred
+    /// member.
+    pub fn connect(settings: &ClusterSettings, handle: &Handle) -> Box<dyn Future<Item = Self, Error = io::Error>> {
+        let client = ClusterClient::with_gossip_source(HttpGossip::new(handle), settings, handle);
+        Box::new(client.client().map(move |_| client))
+    }
+
+    /// Creates a client using a custom source of gossip. The discovery is done on the first
+    /// request.
+    pub fn with_gossip_source<G: GossipSource + 'static>(source: G, settings: &ClusterSettings, handle: &Handle) -> Self {
+        ClusterClient {
+            inner: Rc::new(Inner {
+                source: Rc::new(source),
+                settings: settings.clone(),
+                handle: handle.clone(),
+                state: RefCell::new(State::Disconnected),
+                generation: Cell::new(0),
+            })
+        }
+    }
+
+    /// The current connection, or a future of the discovery and connection in progress.
+    fn client(&self) -> Connecting {
+        let mut state = self.inner.state.borrow_mut();
+
+        match *state {
+            State::Connected(generation, ref client) => return Box::new(future::ok((generation, client.clone()))),
+            State::Connecting(ref shared) => return unshare(shared.clone()),
+            State::Disconnected => {},
+        }
+
+        let generation = self.inner.generation.get() + 1;
+        self.inner.generation.set(generation);
+
+        let template = self.inner.settings.connection.clone();
+        let handle = self.inner.handle.clone();
+        let weak = Rc::downgrade(&self.inner);
+
+        let connecting: Connecting = Box::new(discover(self.inner.source.clone(), &self.inner.settings, &self.inner.handle)
+            .and_then(move |member| EventStoreClient::connect_with_settings(&member.connection_settings(&template), &handle))
+            .map(move |client| (generation, client))
+            .then(move |res| {
+                if let Some(inner) = weak.upgrade() {
+                    if inner.generation.get() == generation {
+                        *inner.state.borrow_mut() = match res {
+                            Ok((_, ref client)) => State::Connected(generation, client.clone()),
+                            Err(_) => State::Disconnected,
+                        };
+                    }
+                }
+                res
+            }));
+
+        let shared = connecting.shared();
+        *state = State::Connecting(shared.clone());
+        unshare(shared)
+    }
+
+    /// Forgets the connection of the given generation, if it is still the current one.
+    fn disconnected(inner: &Weak<Inner>, generation: u64) {
+        if let Some(inner) = inner.upgrade() {
+            if inner.generation.get() == generation {
+                *inner.state.borrow_mut() = State::Disconnected;
+            }
+        }
+    }
+
+    fn send(&self, req: Package) -> Box<dyn Future<Item = Package, Error = io::Error>> {
+        let inner = Rc::downgrade(&self.inner);
+
+        Box::new(self.client().and_then(move |(generation, client)| {
+            client.call(req).then(move |res| {
+                match res {
+                    Ok(ref resp) if is_not_master(resp) => ClusterClient::disconnected(&inner, generation),
+                    Err(ref e) if is_connection_loss(e) => ClusterClient::disconnected(&inner, generation),
+                    _ => {},
+                }
+                res
+            })
+        }))
+    }
+}
+
+impl Service for ClusterClient {
+    type Request = Package;
+    type Response = Package;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = Package, Error = io::Error>>;
+
+    fn call(&self, req: Package) -> Self::Future {
+        let this = self.clone();
+        let retried = req.clone();
+
+        Box::new(self.send(req).and_then(move |resp| -> Self::Future {
+            if is_not_master(&resp) {
+                this.send(retried)
+            } else {
+                Box::new(future::ok(resp))
+            }
+        }))
+    }
+}
+
+fn unshare(shared: Shared<Connecting>) -> Connecting {
+    Box::new(shared
+        .map(|item| (*item).clone())
+        .map_err(|e| io::Error::new(e.kind(), e.to_string())))
+}
+
+fn is_not_master(resp: &Package) -> bool {
+    match resp.message {
+        RawMessage::NotHandled(ref info) => info.reason == Some(NotHandledReason::NotMaster),
+        _ => false,
+    }
+}
+
+/// Errors after which the connection cannot be used anymore.
+fn is_connection_loss(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::BrokenPipe |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::NotConnected |
+        io::ErrorKind::UnexpectedEof => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures::{future, Future, Stream, Sink};
+    use tokio_core::net::TcpListener;
+    use tokio_core::reactor::{Core, Handle};
+    use tokio_io::AsyncRead;
+    use tokio_service::Service;
+
+    use super::*;
+    use builder::Builder;
+    use codec::PackageCodec;
+    use raw::client_messages::NotHandled;
+
+    /// Returns the member lists in order, repeating the last one.
+    struct FakeGossip {
+        responses: RefCell<Vec<io::Result<Vec<MemberInfo>>>>,
+        fetches: Rc<Cell<usize>>,
+    }
+
+    impl FakeGossip {
+        fn new(responses: Vec<io::Result<Vec<MemberInfo>>>) -> Self {
+            FakeGossip {
+                responses: RefCell::new(responses),
+                fetches: Rc::new(Cell::new(0)),
+            }
+        }
+    }
+
+    impl GossipSource for FakeGossip {
+        fn fetch(&self, _: &GossipSeed) -> Box<dyn Future<Item = Vec<MemberInfo>, Error = io::Error>> {
+            self.fetches.set(self.fetches.get() + 1);
+            let mut responses = self.responses.borrow_mut();
+            let next = if responses.len() > 1 {
+                responses.remove(0)
+            } else {
+                match responses[0] {
+                    Ok(ref members) => Ok(members.clone()),
+                    Err(ref e) => Err(io::Error::new(e.kind(), e.to_string())),
+                }
+            };
+            Box::new(future::result(next))
+        }
+    }
+
+    /// Node which responds to every request with the given message.
+    fn fake_node(reply: RawMessage<'static>, handle: &Handle) -> u16 {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), handle).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let conn_handle = handle.clone();
+
+        let server = listener.incoming().for_each(move |(stream, _)| {
+            let reply = reply.clone();
+            let (sink, stream) = stream.framed(PackageCodec).split();
+            let conn = stream
+                .map(move |req: Package| Package {
+                    authentication: None,
+                    correlation_id: req.correlation_id,
+                    message: reply.clone(),
+                })
+                .forward(sink)
+                .map(|_| ())
+                .map_err(|_| ());
+            conn_handle.spawn(conn);
+            Ok(())
+        }).map_err(|_| ());
+
+        handle.spawn(server);
+        port
+    }
+
+    fn settings() -> ClusterSettings {
+        let mut settings = ClusterSettings::new(vec![GossipSeed::new("seed1", 2113), GossipSeed::new("seed2", 2113)]);
+        settings.discover_delay = Duration::from_millis(1);
+        settings.max_discover_attempts = 3;
+        settings.connection.heartbeat_interval = None;
+        settings.connection.max_reconnections = 0;
+        settings
+    }
+
+    fn member(state: VNodeState, port: u16) -> MemberInfo {
+        MemberInfo::new(state, "127.0.0.1", port)
+    }
+
+    #[test]
+    fn parse_gossip_members() {
+        let gossip = r#"{"members":[
+            {"instanceId":"2b2c7e4e-6e3f-4a5c-9d24-3a1a5e0e5b1a","state":"Master","isAlive":true,
+             "externalTcpIp":"10.0.0.1","externalTcpPort":1113,"externalSecureTcpPort":0,
+             "externalHttpIp":"10.0.0.1","externalHttpPort":2113},
+            {"state":"Slave","isAlive":false,"externalTcpIp":"10.0.0.2","externalTcpPort":1113,
+             "externalHttpIp":"10.0.0.2","externalHttpPort":2113}
+        ],"serverIp":"10.0.0.1","serverPort":2113}"#;
+
+        let members = parse_gossip(gossip).unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].state, VNodeState::Master);
+        assert!(members[0].instance_id.is_some());
+        assert_eq!(members[1].external_tcp_ip, "10.0.0.2");
+        assert!(!members[1].is_alive);
+
+        assert!(parse_gossip("{}").is_err());
+        assert!(parse_http_response(b"HTTP/1.1 404 Not Found\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn select_by_preference() {
+        let mut dead_slave = member(VNodeState::Slave, 1);
+        dead_slave.is_alive = false;
+        let members = vec![dead_slave, member(VNodeState::Master, 2), member(VNodeState::Clone, 3), member(VNodeState::Manager, 4)];
+
+        assert_eq!(NodePreference::Master.select(&members).unwrap().external_tcp_port, 2);
+        assert_eq!(NodePreference::FollowerForReads.select(&members).unwrap().external_tcp_port, 3);
+        assert!([2, 3].contains(&NodePreference::Random.select(&members).unwrap().external_tcp_port));
+        assert!(NodePreference::Master.select(&members[2..]).is_none());
+    }
+
+    #[test]
+    fn discovery_retries_until_suitable_member() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let source = FakeGossip::new(vec![
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, "seed down")),
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, "seed down")),
+            Ok(vec![member(VNodeState::PreMaster, 1)]),
+            Ok(vec![member(VNodeState::Master, 1)]),
+        ]);
+        let fetches = source.fetches.clone();
+
+        let found = core.run(discover(Rc::new(source), &settings(), &handle)).unwrap();
+        assert_eq!(found.state, VNodeState::Master);
+        assert_eq!(fetches.get(), 4);
+    }
+
+    #[test]
+    fn rediscovers_after_not_master() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let not_master = RawMessage::NotHandled(NotHandled {
+            reason: Some(NotHandledReason::NotMaster),
+            additional_info: None,
+        });
+
+        let old_master = fake_node(not_master, &handle);
+        let new_master = fake_node(RawMessage::Pong, &handle);
+
+        let source = FakeGossip::new(vec![
+            Ok(vec![member(VNodeState::Master, old_master)]),
+            Ok(vec![member(VNodeState::Slave, old_master), member(VNodeState::Master, new_master)]),
+        ]);
+        let fetches = source.fetches.clone();
+
+        let client = ClusterClient::with_gossip_source(source, &settings(), &handle);
+        let resp = core.run(client.call(Builder::ping().build_package(None, None))).unwrap();
+
+        assert_eq!(resp.message, RawMessage::Pong);
+        assert_eq!(fetches.get(), 2);
+
+        // the new connection is reused
+        core.run(client.call(Builder::ping().build_package(None, None))).unwrap();
+        assert_eq!(fetches.get(), 2);
+    }
+}
@@ -23,6 +23,9 @@ bitflags!{
     }
 }
 
+/// The largest frame length accepted, the same as the maximum package size of EventStore.
+pub const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
 /// Stateless simple PackageCodec
 pub struct PackageCodec;
 
@@ -38,6 +41,10 @@ impl PackageCodec {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "length is too small"))
         }
 
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "length is too large"))
+        }
+
         if buf.len() < len + 4 {
             return Ok(None);
         }
@@ -165,6 +172,12 @@ mod tests {
                           });
     }
 
+    #[test]
+    fn decode_too_large() {
+        let mut buf = Vec::<u8>::from_hex("ffffff7f03007b50a1b034b9224e8f9d708c394fab2d").unwrap().into();
+        assert!(PackageCodec.decode(&mut buf).is_err());
+    }
+
     #[test]
     fn decode_ping_with_junk() {
         test_decoding_hex("1300000003007b50a1b034b9224e8f9d708c394fab2d00",
@@ -217,7 +230,8 @@ mod tests {
                                   first_event_number: 30,
                                   last_event_number: 39,
                                   prepare_position: Some(181349124),
-                                  commit_position: Some(181349124)
+                                  commit_position: Some(181349124),
+                                  current_version: None
                               }).into()
                           });
     }
@@ -236,7 +250,8 @@ mod tests {
                                   first_event_number: 30,
                                   last_event_number: 39,
                                   prepare_position: Some(181349124),
-                                  commit_position: Some(181349124)
+                                  commit_position: Some(181349124),
+                                  current_version: None
                               }).into()
                           });
 
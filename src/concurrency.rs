@@ -0,0 +1,363 @@
+//! Appending to a stream based on its current contents with optimistic concurrency.
+//!
+//! `update_stream` folds the events of a stream into a state, lets the caller decide on the new
+//! events based on the state and appends them expecting the version which was read. If another
+//! writer appended to the stream in between, the write fails with `WrongExpectedVersion` and the
+//! whole loop is run again on the updated stream, up to a given number of attempts.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! extern crate futures;
+//! extern crate tokio_core;
+//! extern crate eventstore_tcp;
+//!
+//! use futures::Future;
+//! use tokio_core::reactor::Core;
+//! use eventstore_tcp::EventStoreClient;
+//! use eventstore_tcp::concurrency::update_stream;
+//!
+//! fn main() {
+//!     let mut core = Core::new().unwrap();
+//!     let addr = "127.0.0.1:1113".parse().unwrap();
+//!
+//!     let job = EventStoreClient::connect(&addr, &core.handle())
+//!         .map_err(From::from)
+//!         .and_then(|client| {
+//!             update_stream(&client, "seats-1", 0u32,
+//!                 |reserved, event| if event.event.event_type == "SeatReserved" { reserved + 1 } else { reserved },
+//!                 |reserved, builder| {
+//!                     if *reserved >= 100 {
+//!                         return Err("sold out");
+//!                     }
+//!                     builder.new_event().event_type("SeatReserved").data(&b"{}"[..]).done();
+//!                     Ok(())
+//!                 },
+//!                 5, None)
+//!         });
+//!
+//!     core.run(job).unwrap();
+//! }
+//! ```
+
+use std::io;
+use std::fmt;
+use std::error;
+use std::rc::Rc;
+
+use futures::{future, Future, Stream};
+use futures::future::Loop;
+use tokio_service::Service;
+
+use adapted::{AdaptedMessage, ReadStreamError, ResolvedEvent, WriteEventsCompleted, WriteEventsFailure};
+use builder::{Builder, WriteEventsBuilder};
+use package::Package;
+use raw::RawMessage;
+use reading::{read_stream_pages, ReadError};
+use {EventNumber, ExpectedVersion, StreamVersion, UsernamePassword};
+
+/// Number of events read at a time when folding the stream.
+const PAGE_SIZE: u8 = 100;
+
+/// Failures of appending events to a stream, shared with `aggregate::Repository`.
+#[derive(Debug)]
+pub enum WriteError {
+    /// The write could not be sent or the response received.
+    Io(io::Error),
+    /// The server refused the write.
+    Failed(WriteEventsFailure),
+    /// The server responded with an unexpected message, such as `NotAuthenticated`.
+    UnexpectedResponse(RawMessage<'static>),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WriteError::Io(ref e) => write!(f, "{}", e),
+            WriteError::Failed(ref e) => write!(f, "Writing stream failed: {}", e),
+            WriteError::UnexpectedResponse(ref msg) => write!(f, "Unexpected response: {:?}", msg),
+        }
+    }
+}
+
+impl error::Error for WriteError {
+    fn description(&self) -> &str {
+        match *self {
+            WriteError::Io(ref e) => e.description(),
+            WriteError::Failed(_) => "writing stream failed",
+            WriteError::UnexpectedResponse(_) => "unexpected response",
+        }
+    }
+}
+
+impl From<io::Error> for WriteError {
+    fn from(e: io::Error) -> Self {
+        WriteError::Io(e)
+    }
+}
+
+/// Failures of `update_stream`.
+#[derive(Debug)]
+pub enum UpdateError<E> {
+    /// Reading the stream failed.
+    Read(ReadError),
+    /// Writing failed for another reason than the expected version.
+    Write(WriteError),
+    /// Every attempt was interrupted by another writer.
+    Conflict {
+        /// Number of attempts made
+        attempts: usize,
+        /// Version of the stream when it was last read, `None` if it did not exist
+        last_version: Option<StreamVersion>,
+    },
+    /// The decide function returned an error.
+    Rejected(E),
+}
+
+impl<E: fmt::Display> fmt::Display for UpdateError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UpdateError::Read(ref e) => write!(f, "{}", e),
+            UpdateError::Write(ref e) => write!(f, "{}", e),
+            UpdateError::Conflict { attempts, last_version } =>
+                write!(f, "Stream was concurrently modified on all {} attempts, last read version: {:?}", attempts, last_version),
+            UpdateError::Rejected(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<E: error::Error> error::Error for UpdateError<E> {
+    fn description(&self) -> &str {
+        match *self {
+            UpdateError::Read(ref e) => e.description(),
+            UpdateError::Write(ref e) => e.description(),
+            UpdateError::Conflict { .. } => "stream was concurrently modified",
+            UpdateError::Rejected(ref e) => e.description(),
+        }
+    }
+}
+
+impl<E> From<io::Error> for UpdateError<E> {
+    fn from(e: io::Error) -> Self {
+        UpdateError::Write(WriteError::Io(e))
+    }
+}
+
+impl<E> From<WriteError> for UpdateError<E> {
+    fn from(e: WriteError) -> Self {
+        UpdateError::Write(e)
+    }
+}
+
+impl<E> From<ReadError> for UpdateError<E> {
+    fn from(e: ReadError) -> Self {
+        UpdateError::Read(e)
+    }
+}
+
+/// Sends the `WriteEvents` request and adapts the response.
+pub(crate) fn write_events<S>(client: &S, req: Package) -> Box<dyn Future<Item = WriteEventsCompleted, Error = WriteError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static,
+{
+    Box::new(client.call(req).from_err().and_then(|resp| {
+        match resp.message.try_adapt() {
+            Ok(AdaptedMessage::WriteEventsCompleted(Ok(completed))) => Ok(completed),
+            Ok(AdaptedMessage::WriteEventsCompleted(Err(e))) => Err(WriteError::Failed(e.failure)),
+            Ok(other) => Err(WriteError::UnexpectedResponse(other.as_raw().into_owned())),
+            Err((raw, _)) => Err(WriteError::UnexpectedResponse(raw)),
+        }
+    }))
+}
+
+/// Folds every event of the stream into the initial state and returns the state with the
+/// version of the stream, `None` if the stream does not exist.
+fn load<S, T, F>(client: &S, stream: &str, initial: T, fold: Rc<F>, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = (T, Option<StreamVersion>), Error = ReadError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error> + Clone + 'static,
+          S::Future: 'static,
+          T: Clone + 'static,
+          F: Fn(T, &ResolvedEvent) -> T + 'static,
+{
+    let empty = initial.clone();
+
+    Box::new(read_stream_pages(client, stream, EventNumber::First, PAGE_SIZE, credentials)
+        .fold((initial, None), move |(state, _), page| -> Result<(T, Option<StreamVersion>), ReadError> {
+            let state = page.events.iter().fold(state, |state, event| fold(state, event));
+            Ok((state, Some(page.last_event_number)))
+        })
+        .or_else(move |e| match e {
+            ReadError::Read(ReadStreamError::NoStream) => Ok((empty, None)),
+            e => Err(e),
+        }))
+}
+
+/// Reads the stream folding its events with `fold` starting from `initial`, and appends the
+/// events `decide` adds to the builder expecting the version which was read. The builder has
+/// the stream id and the expected version already set. On `WrongExpectedVersion` the stream is
+/// read again, at most `max_attempts` times in total.
+///
+/// Returns the result of the write, or `None` if `decide` added no events.
+pub fn update_stream<S, T, F, D, E>(client: &S, stream: &str, initial: T, fold: F, decide: D, max_attempts: usize, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = Option<WriteEventsCompleted>, Error = UpdateError<E>>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error> + Clone + 'static,
+          S::Future: 'static,
+          T: Clone + 'static,
+          F: Fn(T, &ResolvedEvent) -> T + 'static,
+          D: Fn(&T, &mut WriteEventsBuilder) -> Result<(), E> + 'static,
+          E: 'static,
+{
+    let client = client.clone();
+    let stream = stream.to_owned();
+    let fold = Rc::new(fold);
+    let decide = Rc::new(decide);
+
+    let ret = future::loop_fn(1, move |attempt| {
+        let writer = client.clone();
+        let stream_id = stream.clone();
+        let decide = decide.clone();
+        let credentials2 = credentials.clone();
+
+        load(&client, &stream, initial.clone(), fold.clone(), credentials.clone())
+            .from_err()
+            .and_then(move |(state, version)| -> Box<dyn Future<Item = Loop<Option<WriteEventsCompleted>, usize>, Error = UpdateError<E>>> {
+                let mut builder = Builder::write_events();
+                builder.stream_id(stream_id).expected_version(match version {
+                    Some(version) => ExpectedVersion::Exact(version),
+                    None => ExpectedVersion::NoStream,
+                });
+
+                if let Err(e) = decide(&state, &mut builder) {
+                    return Box::new(future::err(UpdateError::Rejected(e)));
+                }
+
+                if !builder.has_events() {
+                    return Box::new(future::ok(Loop::Break(None)));
+                }
+
+                let req = builder.build_package(credentials2, None);
+
+                Box::new(write_events(&writer, req).then(move |res| {
+                    match res {
+                        Ok(completed) => Ok(Loop::Break(Some(completed))),
+                        Err(WriteError::Failed(WriteEventsFailure::WrongExpectedVersion)) => {
+                            if attempt < max_attempts {
+                                Ok(Loop::Continue(attempt + 1))
+                            } else {
+                                Err(UpdateError::Conflict { attempts: attempt, last_version: version })
+                            }
+                        },
+                        Err(e) => Err(UpdateError::Write(e)),
+                    }
+                }))
+            })
+    });
+
+    Box::new(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::cell::Cell;
+    use std::convert::TryFrom;
+    use std::rc::Rc;
+
+    use futures::Future;
+    use tokio_core::reactor::Core;
+    use tokio_service::Service;
+
+    use super::{update_stream, UpdateError};
+    use adapted::ResolvedEvent;
+    use builder::{Builder, WriteEventsBuilder};
+    use fixtures::start;
+    use memory::MemoryStore;
+    use package::Package;
+    use raw::RawMessage;
+    use {EventStoreClient, ExpectedVersion, StreamVersion};
+
+    /// Appends an event of another writer before the first `times` writes.
+    #[derive(Clone)]
+    struct Interfering {
+        client: EventStoreClient,
+        times: Rc<Cell<usize>>,
+    }
+
+    impl Service for Interfering {
+        type Request = Package;
+        type Response = Package;
+        type Error = io::Error;
+        type Future = Box<dyn Future<Item = Package, Error = io::Error>>;
+
+        fn call(&self, req: Package) -> Self::Future {
+            let stream = match req.message {
+                RawMessage::WriteEvents(ref w) if self.times.get() > 0 => w.event_stream_id.clone().into_owned(),
+                _ => return Box::new(self.client.call(req)),
+            };
+
+            self.times.set(self.times.get() - 1);
+
+            let mut builder = Builder::write_events();
+            builder.stream_id(stream).expected_version(ExpectedVersion::Any);
+            builder.new_event().event_type("other").data(&b"{}"[..]).done();
+
+            let client = self.client.clone();
+            Box::new(self.client.call(builder.build_package(None, None)).and_then(move |_| client.call(req)))
+        }
+    }
+
+    fn interfering(core: &Core, times: usize) -> Interfering {
+        Interfering {
+            client: start(core, MemoryStore::new()),
+            times: Rc::new(Cell::new(times)),
+        }
+    }
+
+    fn count(n: usize, _: &ResolvedEvent) -> usize {
+        n + 1
+    }
+
+    fn append_count(n: &usize, builder: &mut WriteEventsBuilder) -> Result<(), io::Error> {
+        builder.new_event().event_type("counted").data(n.to_string().into_bytes()).done();
+        Ok(())
+    }
+
+    fn v(n: u64) -> StreamVersion {
+        StreamVersion::try_from(n).unwrap()
+    }
+
+    #[test]
+    fn retries_after_concurrent_write() {
+        let mut core = Core::new().unwrap();
+        let client = interfering(&core, 2);
+
+        let completed = core.run(update_stream(&client, "foo", 0, count, append_count, 3, None)).unwrap().unwrap();
+
+        // both interfering writes were seen by the last attempt
+        assert_eq!(completed.event_numbers, v(2)..v(3));
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut core = Core::new().unwrap();
+        let client = interfering(&core, 2);
+
+        match core.run(update_stream(&client, "foo", 0, count, append_count, 2, None)) {
+            Err(UpdateError::Conflict { attempts: 2, last_version }) => assert_eq!(last_version, Some(v(0))),
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn rejected_or_nothing_to_write() {
+        let mut core = Core::new().unwrap();
+        let client = interfering(&core, 0);
+
+        let nothing = update_stream(&client, "foo", 0, count, |_: &usize, _: &mut WriteEventsBuilder| Ok::<_, io::Error>(()), 1, None);
+        assert_eq!(core.run(nothing).unwrap(), None);
+
+        let rejected = update_stream(&client, "foo", 0, count, |_: &usize, _: &mut WriteEventsBuilder| Err("no"), 1, None);
+        match core.run(rejected) {
+            Err(UpdateError::Rejected("no")) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+}
@@ -4,7 +4,17 @@ pub enum ContentType {
     /// Raw bytes
     Bytes,
     /// JSON values usable with projections in EventStore
-    Json
+    Json,
+    /// Any other value found on the wire, kept as is so that it survives a round-trip. `Other(0)`
+    /// and `Other(1)` are read back as `Bytes` and `Json`.
+    Other(i32),
+}
+
+impl ContentType {
+    /// `true` for `ContentType::Json`
+    pub fn is_json(&self) -> bool {
+        *self == ContentType::Json
+    }
 }
 
 impl From<ContentType> for i32 {
@@ -12,6 +22,36 @@ impl From<ContentType> for i32 {
         match content_type {
             ContentType::Bytes => 0,
             ContentType::Json => 1,
+            ContentType::Other(x) => x,
+        }
+    }
+}
+
+/// Decoding the wire value never fails as unknown values are kept in `ContentType::Other`, which
+/// also makes `TryFrom<i32>` available.
+impl From<i32> for ContentType {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => ContentType::Bytes,
+            1 => ContentType::Json,
+            x => ContentType::Other(x),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::ContentType;
+
+    #[test]
+    fn round_trips() {
+        for value in &[0, 1, 2, -1, i32::max_value()] {
+            let content_type = ContentType::try_from(*value).unwrap();
+            assert_eq!(i32::from(content_type), *value);
+        }
+
+        assert_eq!(ContentType::from(1), ContentType::Json);
+        assert_eq!(ContentType::from(7), ContentType::Other(7));
+    }
+}
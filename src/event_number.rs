@@ -1,4 +1,5 @@
-use StreamVersion;
+use std::convert::TryFrom;
+use {StreamVersion, Error, ErrorKind};
 
 /// `EventNumber` is similar to `StreamVersion` and `ExpectedVersion` but is used when specifying a
 /// position to read from in the stream. Allows specifying the first or last (when reading
@@ -19,7 +20,7 @@ impl From<StreamVersion> for EventNumber {
     }
 }
 
-impl From<EventNumber> for i32 {
+impl From<EventNumber> for i64 {
     /// Returns the wire representation.
     fn from(number: EventNumber) -> Self {
         use self::EventNumber::*;
@@ -30,3 +31,73 @@ impl From<EventNumber> for i32 {
         }
     }
 }
+
+impl From<EventNumber> for i32 {
+    /// Returns the wire representation of EventStore 3.9. Kept for compatibility, prefer `i64`.
+    /// Event numbers saturate like `StreamVersion` does.
+    fn from(number: EventNumber) -> Self {
+        use self::EventNumber::*;
+        match number {
+            First => 0,
+            Exact(x) => x.into(),
+            Last => -1
+        }
+    }
+}
+
+impl TryFrom<i64> for EventNumber {
+    type Error = Error;
+
+    /// Parses the wire representation. As the first event is `0` on the wire, `Exact(0)` is read
+    /// back as `First`.
+    fn try_from(number: i64) -> Result<Self, Self::Error> {
+        use self::EventNumber::*;
+        match number {
+            0 => Ok(First),
+            -1 => Ok(Last),
+            n if n > 0 => Ok(Exact(StreamVersion::try_from(n)?)),
+            n => Err(ErrorKind::InvalidEventNumber(n).into()),
+        }
+    }
+}
+
+impl TryFrom<i32> for EventNumber {
+    type Error = Error;
+
+    /// Parses the wire representation of EventStore 3.9. Kept for compatibility, prefer `i64`.
+    fn try_from(number: i32) -> Result<Self, Self::Error> {
+        EventNumber::try_from(number as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::EventNumber;
+    use StreamVersion;
+
+    #[test]
+    fn event_number_round_trips() {
+        let all = [
+            EventNumber::First,
+            EventNumber::Exact(StreamVersion::try_from(1).unwrap()),
+            EventNumber::Exact(StreamVersion::try_from(i64::max_value()).unwrap()),
+            EventNumber::Last,
+        ];
+
+        for number in &all {
+            let wire: i64 = (*number).into();
+            assert_eq!(EventNumber::try_from(wire).unwrap(), *number);
+        }
+
+        // the first event has no other representation on the wire
+        let zero: i64 = EventNumber::Exact(StreamVersion::try_from(0).unwrap()).into();
+        assert_eq!(EventNumber::try_from(zero).unwrap(), EventNumber::First);
+        assert!(EventNumber::try_from(-2i64).is_err());
+
+        assert_eq!(EventNumber::try_from(-1i32).unwrap(), EventNumber::Last);
+        assert!(EventNumber::try_from(-2i32).is_err());
+        assert_eq!(i32::from(EventNumber::Last), -1);
+        assert_eq!(i32::from(EventNumber::Exact(StreamVersion::try_from(i64::max_value()).unwrap())), i32::max_value());
+    }
+}
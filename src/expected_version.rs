@@ -1,31 +1,146 @@
-use StreamVersion;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use {StreamVersion, Error, ErrorKind};
 
 /// `ExpectedVersion` represents the different modes of optimistic locking when writing to a stream
-/// using `WriteEventsBuilder`.
+/// using `WriteEventsBuilder` or deleting it with `DeleteStreamBuilder`.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum ExpectedVersion {
     /// No optimistic locking
     Any,
-    /// Expect a stream not to exist
+    /// Expect a stream not to exist or to be empty, see `ExpectedVersion::EMPTY_STREAM`
     NoStream,
+    /// Expect a stream to exist, with any number of events
+    StreamExists,
     /// Expect exact number of events in the stream
     Exact(StreamVersion)
 }
 
-impl From<ExpectedVersion> for i32 {
+impl ExpectedVersion {
+    /// Expect a stream to have no events, such as a stream with only metadata. The protocol uses
+    /// the same value for this and `NoStream`, so this is an alias for `NoStream`.
+    pub const EMPTY_STREAM: ExpectedVersion = ExpectedVersion::NoStream;
+}
+
+impl From<ExpectedVersion> for i64 {
     /// Returns the wire representation.
     fn from(version: ExpectedVersion) -> Self {
         use self::ExpectedVersion::*;
         match version {
             Any => -2,
             NoStream => -1,
+            StreamExists => -4,
             Exact(ver) => ver.into()
         }
     }
 }
 
+impl From<ExpectedVersion> for i32 {
+    /// Returns the wire representation of EventStore 3.9. Kept for compatibility, prefer `i64`.
+    /// Exact versions saturate like `StreamVersion` does.
+    fn from(version: ExpectedVersion) -> Self {
+        use self::ExpectedVersion::*;
+        match version {
+            Any => -2,
+            NoStream => -1,
+            StreamExists => -4,
+            Exact(ver) => ver.into()
+        }
+    }
+}
+
+impl TryFrom<i64> for ExpectedVersion {
+    type Error = Error;
+
+    /// Parses the wire representation. Fails on negative values which are not sentinels of the
+    /// protocol, such as the server internal `-3`.
+    fn try_from(version: i64) -> Result<Self, Self::Error> {
+        use self::ExpectedVersion::*;
+        match version {
+            -2 => Ok(Any),
+            -1 => Ok(NoStream),
+            -4 => Ok(StreamExists),
+            n if n >= 0 => Ok(Exact(StreamVersion::try_from(n)?)),
+            n => Err(ErrorKind::InvalidExpectedVersion(n).into()),
+        }
+    }
+}
+
+impl TryFrom<i32> for ExpectedVersion {
+    type Error = Error;
+
+    /// Parses the wire representation of EventStore 3.9. Kept for compatibility, prefer `i64`.
+    fn try_from(version: i32) -> Result<Self, Self::Error> {
+        ExpectedVersion::try_from(version as i64)
+    }
+}
+
 impl From<StreamVersion> for ExpectedVersion {
     fn from(version: StreamVersion) -> Self {
         ExpectedVersion::Exact(version)
     }
 }
+
+impl FromStr for ExpectedVersion {
+    type Err = Error;
+
+    /// Parses `any`, `created` or `empty` (both `NoStream`), `exists` or a non-negative stream
+    /// version, as used by the testclient.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::ExpectedVersion::*;
+        match s {
+            "any" => Ok(Any),
+            "created" | "empty" => Ok(NoStream),
+            "exists" => Ok(StreamExists),
+            n => {
+                let n = n.parse::<u64>()
+                    .map_err(|_| Error::from(ErrorKind::UnparseableExpectedVersion(s.to_owned())))?;
+                Ok(Exact(StreamVersion::try_from(n)?))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::ExpectedVersion;
+    use StreamVersion;
+
+    #[test]
+    fn expected_version_round_trips() {
+        let max = StreamVersion::try_from(i64::max_value()).unwrap();
+        let all = [
+            ExpectedVersion::Any,
+            ExpectedVersion::NoStream,
+            ExpectedVersion::StreamExists,
+            ExpectedVersion::Exact(StreamVersion::try_from(0).unwrap()),
+            ExpectedVersion::Exact(max),
+        ];
+
+        for version in &all {
+            let wire: i64 = (*version).into();
+            assert_eq!(ExpectedVersion::try_from(wire).unwrap(), *version);
+        }
+
+        assert_eq!(i64::from(ExpectedVersion::StreamExists), -4);
+        assert_eq!(ExpectedVersion::EMPTY_STREAM, ExpectedVersion::NoStream);
+        assert!(ExpectedVersion::try_from(-3i64).is_err());
+        assert!(ExpectedVersion::try_from(-5i64).is_err());
+
+        assert_eq!(ExpectedVersion::try_from(-4i32).unwrap(), ExpectedVersion::StreamExists);
+        assert!(ExpectedVersion::try_from(-3i32).is_err());
+        assert_eq!(i32::from(ExpectedVersion::StreamExists), -4);
+    }
+
+    #[test]
+    fn expected_version_from_str() {
+        assert_eq!("any".parse::<ExpectedVersion>().unwrap(), ExpectedVersion::Any);
+        assert_eq!("created".parse::<ExpectedVersion>().unwrap(), ExpectedVersion::NoStream);
+        assert_eq!("empty".parse::<ExpectedVersion>().unwrap(), ExpectedVersion::NoStream);
+        assert_eq!("exists".parse::<ExpectedVersion>().unwrap(), ExpectedVersion::StreamExists);
+        assert_eq!("42".parse::<ExpectedVersion>().unwrap(), ExpectedVersion::Exact(StreamVersion::try_from(42).unwrap()));
+        assert!("-1".parse::<ExpectedVersion>().is_err());
+        assert!("whatever".parse::<ExpectedVersion>().is_err());
+    }
+}
@@ -0,0 +1,357 @@
+//! Fault injection for testing how code using a `Service<Request = Package, Response = Package>`
+//! such as `EventStoreClient` copes with a misbehaving server.
+//!
+//! `FaultInjector` wraps a service and applies a `Fault` to some of the requests, chosen either
+//! randomly with configured probabilities from a seeded generator, or by a fixed schedule. Both
+//! are deterministic: the same seed or schedule with the same requests injects the same faults.
+//!
+//! The synthesized responses are created from `AdaptedMessage` values so they are exactly what a
+//! server would send.
+
+use std::io;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{future, Future};
+use futures::sync::oneshot;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_service::Service;
+
+use adapted::{AdaptedMessage, NotHandledInfo, WriteEventsFailure};
+use package::Package;
+use raw::RawMessage;
+use raw::client_messages::mod_NotHandled::NotHandledReason;
+
+/// A way of misbehaving for a single request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// The request is sent but the connection is lost before the response arrives: the request
+    /// fails with `io::ErrorKind::ConnectionReset` and the wrapped service is dropped, so that
+    /// every later request fails with `io::ErrorKind::NotConnected`
+    Disconnect,
+    /// The response is delayed by the given duration
+    Delay(Duration),
+    /// The request is not sent and is answered with `NotHandled` of the given reason. Requests
+    /// which servers always handle, such as heartbeats, pings and authentication, are not
+    /// affected.
+    NotHandled(NotHandledReason),
+    /// The request is sent but the result of a `WriteEvents` is replaced with the given failure,
+    /// as happens for example when the commit times out after the events have been written.
+    /// Other requests are not affected.
+    WriteFailure(WriteEventsFailure),
+    /// The response is held back until the response of a later request has completed or
+    /// `FaultInjector::release_held` is called
+    Reorder,
+}
+
+impl Fault {
+    fn applies_to(&self, msg: &RawMessage) -> bool {
+        match *self {
+            Fault::NotHandled(_) => match *msg {
+                RawMessage::HeartbeatRequest
+                    | RawMessage::HeartbeatResponse
+                    | RawMessage::Ping
+                    | RawMessage::Authenticate
+                    | RawMessage::IdentifyClient(_) => false,
+                _ => true,
+            },
+            Fault::WriteFailure(_) => match *msg {
+                RawMessage::WriteEvents(_) => true,
+                _ => false,
+            },
+            _ => true,
+        }
+    }
+}
+
+enum Plan {
+    Random(XorShiftRng, Vec<(f64, Fault)>),
+    Scheduled(VecDeque<Option<Fault>>),
+}
+
+impl Plan {
+    fn next(&mut self) -> Option<Fault> {
+        match *self {
+            Plan::Random(ref mut rng, ref faults) => {
+                let mut roll = rng.next_f64();
+                for &(probability, ref fault) in faults {
+                    if roll < probability {
+                        return Some(fault.clone());
+                    }
+                    roll -= probability;
+                }
+                None
+            },
+            Plan::Scheduled(ref mut schedule) => schedule.pop_front().and_then(|x| x),
+        }
+    }
+}
+
+/// `Service` injecting faults into the requests and responses of the wrapped service, see the
+/// module documentation.
+pub struct FaultInjector<S> {
+    /// `None` after `Fault::Disconnect`
+    inner: RefCell<Option<S>>,
+    plan: RefCell<Plan>,
+    handle: Handle,
+    injected: RefCell<Vec<Fault>>,
+    sequence: Cell<u64>,
+    held: Rc<RefCell<Vec<(u64, oneshot::Sender<()>)>>>,
+}
+
+impl<S> FaultInjector<S> {
+    /// Injects the faults added with `fault` randomly, using a generator seeded with the given
+    /// seed which must not be all zeroes. The handle is used for `Fault::Delay`.
+    pub fn random(inner: S, seed: [u32; 4], handle: &Handle) -> Self {
+        FaultInjector::with_plan(inner, Plan::Random(XorShiftRng::from_seed(seed), Vec::new()), handle)
+    }
+
+    /// Applies the faults of the schedule to the requests in order, `None` letting the request
+    /// through as is. Requests after the end of the schedule are not affected. The handle is used
+    /// for `Fault::Delay`.
+    pub fn scheduled<I: IntoIterator<Item = Option<Fault>>>(inner: S, schedule: I, handle: &Handle) -> Self {
+        FaultInjector::with_plan(inner, Plan::Scheduled(schedule.into_iter().collect()), handle)
+    }
+
+    fn with_plan(inner: S, plan: Plan, handle: &Handle) -> Self {
+        FaultInjector {
+            inner: RefCell::new(Some(inner)),
+            plan: RefCell::new(plan),
+            handle: handle.clone(),
+            injected: RefCell::new(Vec::new()),
+            sequence: Cell::new(0),
+            held: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Adds a fault to be injected with the given probability, between 0 and 1. The
+    /// probabilities of all the faults should not add up to more than 1. Has no effect on
+    /// scheduled injectors.
+    pub fn fault(self, probability: f64, fault: Fault) -> Self {
+        if let Plan::Random(_, ref mut faults) = *self.plan.borrow_mut() {
+            faults.push((probability, fault));
+        }
+        self
+    }
+
+    /// The faults injected so far, in the order of the requests.
+    pub fn injected(&self) -> Vec<Fault> {
+        self.injected.borrow().clone()
+    }
+
+    /// Releases all the responses held back by `Fault::Reorder`.
+    pub fn release_held(&self) {
+        release(&self.held, u64::max_value());
+    }
+}
+
+/// Releases the responses held back before the given request.
+fn release(held: &RefCell<Vec<(u64, oneshot::Sender<()>)>>, before: u64) {
+    let mut held = held.borrow_mut();
+    let mut i = 0;
+    while i < held.len() {
+        if held[i].0 < before {
+            // the receiver might have been dropped with the response future
+            let _ = held.remove(i).1.send(());
+        } else {
+            i += 1;
+        }
+    }
+}
+
+impl<S> FaultInjector<S>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+{
+    fn call_inner(&self, req: Package) -> S::Future {
+        self.inner.borrow().as_ref().expect("wrapped service has been dropped").call(req)
+    }
+}
+
+impl<S> Service for FaultInjector<S>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static,
+{
+    type Request = Package;
+    type Response = Package;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = Package, Error = io::Error>>;
+
+    fn call(&self, req: Package) -> Self::Future {
+        if self.inner.borrow().is_none() {
+            return Box::new(future::err(io::Error::new(io::ErrorKind::NotConnected, "connection was dropped by fault injection")));
+        }
+
+        let sequence = self.sequence.get();
+        self.sequence.set(sequence + 1);
+
+        let fault = self.plan.borrow_mut().next().and_then(|fault| {
+            if fault.applies_to(&req.message) { Some(fault) } else { None }
+        });
+
+        if let Some(ref fault) = fault {
+            self.injected.borrow_mut().push(fault.clone());
+        }
+
+        let correlation_id = req.correlation_id;
+        let respond = move |msg: AdaptedMessage| Package {
+            authentication: None,
+            correlation_id: correlation_id,
+            message: msg.as_raw().into_owned(),
+        };
+
+        if let Some(Fault::NotHandled(reason)) = fault {
+            let info = NotHandledInfo { reason: reason, additional_info: None };
+            return Box::new(future::ok(respond(AdaptedMessage::NotHandled(info))));
+        }
+
+        let response: Box<dyn Future<Item = Package, Error = io::Error>> = match fault {
+            None | Some(Fault::NotHandled(_)) => Box::new(self.call_inner(req)),
+            Some(Fault::Disconnect) => {
+                let sent = self.call_inner(req);
+                self.inner.borrow_mut().take();
+                Box::new(sent.then(|_| {
+                    Err(io::Error::new(io::ErrorKind::ConnectionReset, "connection was dropped by fault injection"))
+                }))
+            },
+            Some(Fault::Delay(delay)) => match Timeout::new(delay, &self.handle) {
+                Ok(timeout) => Box::new(self.call_inner(req).join(timeout).map(|(resp, _)| resp)),
+                Err(e) => Box::new(future::err(e)),
+            },
+            Some(Fault::WriteFailure(failure)) => Box::new(self.call_inner(req).map(move |_| {
+                respond(AdaptedMessage::WriteEventsCompleted(Err(failure.into())))
+            })),
+            Some(Fault::Reorder) => {
+                let (tx, rx) = oneshot::channel();
+                self.held.borrow_mut().push((sequence, tx));
+                let released = rx.then(|_| Ok(()));
+                return Box::new(self.call_inner(req).join(released).map(|(resp, _)| resp));
+            },
+        };
+
+        let held = self.held.clone();
+        Box::new(response.then(move |res| {
+            release(&held, sequence);
+            res
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::convert::TryFrom;
+
+    use futures::Future;
+    use futures::future::Either;
+    use tokio_core::reactor::Core;
+    use tokio_service::Service;
+
+    use super::{FaultInjector, Fault};
+    use adapted::{AdaptedMessage, WriteEventsCompleted, WriteEventsFailure};
+    use builder::Builder;
+    use fixtures::write;
+    use mock::{MockService, Kind};
+    use package::Package;
+    use raw::client_messages::mod_NotHandled::NotHandledReason;
+    use StreamVersion;
+
+    fn ping() -> Package {
+        Builder::ping().build_package(None, None)
+    }
+
+    fn mock() -> MockService {
+        let mock = MockService::new();
+        mock.on(Kind::Ping).reply(AdaptedMessage::Pong);
+        mock.on(Kind::WriteEvents).reply(AdaptedMessage::WriteEventsCompleted(Ok(WriteEventsCompleted {
+            event_numbers: StreamVersion::try_from(0).unwrap()..StreamVersion::try_from(1).unwrap(),
+            prepare_position: None,
+            commit_position: None,
+            message: None,
+            current_version: None,
+        })));
+        mock
+    }
+
+    #[test]
+    fn scheduled_faults() {
+        let mut core = Core::new().unwrap();
+        let mock = mock();
+        let faults = FaultInjector::scheduled(mock.clone(), vec![
+            Some(Fault::NotHandled(NotHandledReason::TooBusy)),
+            Some(Fault::WriteFailure(WriteEventsFailure::CommitTimeout)),
+            Some(Fault::WriteFailure(WriteEventsFailure::PrepareTimeout)),
+            None,
+            Some(Fault::Disconnect),
+        ], &core.handle());
+
+        let resp = core.run(faults.call(write())).unwrap();
+        match resp.message.try_adapt().unwrap() {
+            AdaptedMessage::NotHandled(info) => assert_eq!(info.reason, NotHandledReason::TooBusy),
+            x => panic!("unexpected {:?}", x),
+        }
+
+        let resp = core.run(faults.call(write())).unwrap();
+        assert_eq!(resp.message.try_adapt().unwrap(), AdaptedMessage::WriteEventsCompleted(Err(WriteEventsFailure::CommitTimeout.into())));
+
+        // write failures do not apply to pings
+        core.run(faults.call(ping())).unwrap();
+        core.run(faults.call(write())).unwrap();
+
+        let err = core.run(faults.call(write())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+
+        // the connection stays dropped
+        let err = core.run(faults.call(ping())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+
+        // not handled requests never reach the server
+        mock.assert_requested(Kind::WriteEvents, Some("foo"), 3);
+        assert_eq!(faults.injected(), vec![
+            Fault::NotHandled(NotHandledReason::TooBusy),
+            Fault::WriteFailure(WriteEventsFailure::CommitTimeout),
+            Fault::Disconnect,
+        ]);
+    }
+
+    #[test]
+    fn seeded_faults_repeat() {
+        let core = Core::new().unwrap();
+        let seed = [0x1234_5678, 0x9abc_def0, 0x0fed_cba9, 0x8765_4321];
+
+        let run = || {
+            let faults = FaultInjector::random(mock(), seed, &core.handle())
+                .fault(0.3, Fault::NotHandled(NotHandledReason::NotMaster))
+                .fault(0.2, Fault::WriteFailure(WriteEventsFailure::ForwardTimeout));
+
+            for _ in 0..100 {
+                let _ = faults.call(write()).wait();
+            }
+
+            faults.injected()
+        };
+
+        let first = run();
+        assert_eq!(first, run());
+        assert!(first.len() > 25 && first.len() < 75, "{} faults", first.len());
+    }
+
+    #[test]
+    fn reorders_responses() {
+        let mut core = Core::new().unwrap();
+        let faults = FaultInjector::scheduled(mock(), vec![Some(Fault::Reorder)], &core.handle());
+
+        let first = ping();
+        let second = ping();
+        let second_id = second.correlation_id;
+
+        let raced = faults.call(first).select2(faults.call(second));
+        let resp = match core.run(raced) {
+            Ok(Either::B((resp, _))) => resp,
+            _ => panic!("held response completed first"),
+        };
+        assert_eq!(resp.correlation_id, second_id);
+    }
+}
@@ -0,0 +1,53 @@
+//! Requests, events and servers shared by the unit tests, and by the tests of other crates with
+//! the `testing` feature.
+
+use futures::Future;
+use tokio_core::reactor::Core;
+
+use builder::{Builder, WriteEventsBuilder};
+use memory::MemoryStore;
+use package::Package;
+use pipe;
+use raw::RawMessage;
+use raw::client_messages::EventRecord;
+use server::Server;
+use {EventStoreClient, ExpectedVersion};
+
+/// `WriteEvents` of a single `bar` event with a new event id to the stream `foo`.
+pub fn write() -> Package {
+    let mut builder = Builder::write_events();
+    builder.stream_id("foo").expected_version(ExpectedVersion::Any);
+    builder.new_event().event_type("bar").data(&b"{}"[..]).done();
+    builder.build_package(None, None)
+}
+
+/// Serves `store` on the core and returns a client connected to it.
+pub fn start(core: &Core, store: MemoryStore) -> EventStoreClient {
+    let handle = core.handle();
+    let (client_io, server_io) = pipe::duplex();
+    handle.spawn(Server::new(store, &handle).serve(server_io).map_err(|e| panic!("server failed: {}", e)));
+    EventStoreClient::from_transport(client_io, &handle)
+}
+
+/// Builds the `WriteEvents` and returns the events as the server would return them when reading
+/// the stream, numbered from zero.
+pub fn recorded_from_write(builder: &mut WriteEventsBuilder) -> Vec<EventRecord<'static>> {
+    let write = match builder.build_package(None, None).message {
+        RawMessage::WriteEvents(w) => w,
+        x => panic!("unexpected {:?}", x),
+    };
+
+    let stream = write.event_stream_id;
+    write.events.into_iter().enumerate().map(|(i, event)| EventRecord {
+        event_stream_id: stream.clone(),
+        event_number: i as i64,
+        event_id: event.event_id,
+        event_type: event.event_type,
+        data_content_type: event.data_content_type,
+        metadata_content_type: event.metadata_content_type,
+        data: event.data,
+        metadata: event.metadata,
+        created: None,
+        created_epoch: None,
+    }).collect()
+}
@@ -12,6 +12,13 @@
 //! moment using a custom fork. It does not yet support `tokio_proto::streaming::multiplex` which is
 //! needed to support subscriptions.
 //!
+//! # Services
+//!
+//! The helper modules, such as `metadata`, work with any
+//! `tokio_service::Service<Request = Package, Response = Package, Error = io::Error>` instead of
+//! `EventStoreClient` only. The client can then be wrapped, for example with
+//! `routing::RoutingClient`, and the code using the helpers can be tested without a server.
+//!
 //! # Panics
 //!
 //! There should not be any panicing now that `adapted` and `raw` are separate.
@@ -93,6 +100,22 @@ extern crate tokio_core;
 extern crate tokio_proto;
 extern crate tokio_service;
 extern crate bytes;
+extern crate json;
+extern crate rand;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate tokio_rustls;
+#[cfg(feature = "tls")]
+extern crate webpki;
+#[cfg(feature = "tls")]
+extern crate webpki_roots;
+#[cfg(feature = "serialization")]
+extern crate serde;
+#[cfg(feature = "serialization")]
+extern crate serde_json;
 #[macro_use]
 extern crate derive_more;
 #[macro_use]
@@ -119,6 +142,51 @@ pub mod codec;
 mod client;
 pub use client::EventStoreClient;
 
+pub mod server;
+
+// test tooling, enabled with the `testing` feature for the tests of other crates
+#[cfg(any(test, feature = "testing"))]
+pub mod memory;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod recording;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod faults;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod fixtures;
+
+pub mod retry;
+
+pub mod settings;
+pub use settings::ConnectionSettings;
+
+pub mod pipe;
+
+#[cfg(feature = "tls")]
+pub mod tls;
+
+pub mod cluster;
+
+pub mod routing;
+
+pub mod metadata;
+pub mod acl;
+
+pub mod reading;
+pub mod upcast;
+pub mod concurrency;
+pub mod aggregate;
+
+#[cfg(feature = "serialization")]
+pub mod serialization;
+#[cfg(feature = "serialization")]
+pub mod registry;
+
 pub mod builder;
 pub use builder::Builder;
 
@@ -134,6 +202,9 @@ pub use stream_version::StreamVersion;
 mod expected_version;
 pub use expected_version::ExpectedVersion;
 
+mod protocol_version;
+pub use protocol_version::ProtocolVersion;
+
 mod content_type;
 pub use content_type::ContentType;
 
@@ -141,6 +212,7 @@ mod errors {
     use std::str;
     use std::io;
     use std::fmt;
+    use ProtocolVersion;
 
     /// Enum describing the locations where a result value can be missing
     #[derive(Debug, PartialEq)]
@@ -151,6 +223,8 @@ mod errors {
         ReadEvent,
         /// Missing from ReadStreamEventsCompleted
         ReadStream,
+        /// Missing from NotHandled
+        NotHandled,
     }
 
     impl fmt::Display for ResultStatusKind {
@@ -160,6 +234,7 @@ mod errors {
                 WriteEvents => "WriteEventsCompleted::result",
                 ReadEvent => "ReadEventCompleted::result",
                 ReadStream => "ReadStreamEventsCompleted::result",
+                NotHandled => "NotHandled::reason",
             })
         }
     }
@@ -176,15 +251,33 @@ mod errors {
             MissingResultField(which: ResultStatusKind) {
                 display("Missing result field: {}", which)
             }
-            InvalidStreamVersion(value: i32) {
+            InvalidStreamVersion(value: i64) {
                 display("Invalid stream version: {}", value)
             }
-            InvalidEventNumber(value: i32) {
+            InvalidEventNumber(value: i64) {
                 display("Invalid event number: {}", value)
             }
+            InvalidExpectedVersion(value: i64) {
+                display("Invalid expected version: {}", value)
+            }
+            UnparseableExpectedVersion(value: String) {
+                display("Invalid expected version {:?}, expected any, created, empty, exists or a stream version", value)
+            }
+            UnsupportedEventNumber(value: i64, protocol: ProtocolVersion) {
+                display("Event number {} is not supported by protocol {:?}", value, protocol)
+            }
+            UnsupportedProtocolVersion(value: String) {
+                display("Unsupported protocol version: {:?}", value)
+            }
             InvalidLogPosition(value: i64) {
                 display("Invalid log position: {}", value)
             }
+            InvalidEventId(len: usize) {
+                display("Invalid event id: expected 16 bytes, got {}", len)
+            }
+            InvalidCreationTime(value: i64) {
+                display("Invalid event creation time: {}", value)
+            }
             UnsupportedDiscriminator(d: u8) {
                 display("Unsupported discriminator 0x{:02x}", d)
             }
@@ -194,6 +287,12 @@ mod errors {
             WriteEventsInvalidTransaction {
                 display("Unexpected write events result: invalid transaction")
             }
+            InvalidConnectionString(reason: String) {
+                display("Invalid connection string: {}", reason)
+            }
+            InvalidStreamMetadata(reason: String) {
+                display("Invalid stream metadata: {}", reason)
+            }
         }
     }
 
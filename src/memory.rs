@@ -0,0 +1,1030 @@
+//! In-memory EventStore speaking the 3.9 protocol, for tests which cannot run the real server.
+//!
+//! `MemoryStore` is a `server::Handler` keeping every event in memory. It supports:
+//!
+//!  * appending with expected version checks and idempotent writes by event id
+//!  * reading events, streams and `$all` in both directions, optionally resolving `$>` links
+//!  * soft and hard deletes, soft deleted streams being recreated by the next write
+//!  * metadata in the `$$` metastreams: `$tb`, `$maxCount`, `$maxAge` and `$acl` are applied on
+//!    reads, `$settings` provides the default ACLs
+//!  * volatile subscriptions to a stream or `$all`
+//!  * username and password authentication, `admin` and `ops` exist with password `changeit`
+//!
+//! Transactions, persistent subscriptions and scavenging are not supported. The single node is
+//! always the master, so `require_master` is ignored. Unlike the real server, `$maxAge` and
+//! `$maxCount` only hide the events instead of removing them.
+//!
+//! # Example
+//!
+//! ```no_run
+//! extern crate futures;
+//! extern crate tokio_core;
+//! extern crate eventstore_tcp;
+//!
+//! use futures::Future;
+//! use tokio_core::reactor::Core;
+//!
+//! use eventstore_tcp::EventStoreClient;
+//! use eventstore_tcp::memory::MemoryStore;
+//!
+//! fn main() {
+//!     let mut core = Core::new().unwrap();
+//!     let handle = core.handle();
+//!
+//!     // port 0 binds to an ephemeral port
+//!     let addr = MemoryStore::new().spawn(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+//!
+//!     let client = core.run(EventStoreClient::connect(&addr, &handle)).unwrap();
+//!     // use the client as with the real server
+//! #   drop(client);
+//! }
+//! ```
+
+use std::io;
+use std::cmp;
+use std::mem;
+use std::str;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use futures::{future, Future};
+use tokio_core::reactor::Handle;
+use uuid::Uuid;
+
+use acl::{self, Operation, SystemSettings, SETTINGS_STREAM};
+use adapted::{CreatedFields, Position, ReadAllCompleted, ReadAllError, ReadEventError, ReadStreamCompleted, ReadStreamError, RecordedEvent, ResolvedEvent, WriteEventsCompleted, WriteEventsFailure};
+use metadata::{self, StreamMetadata, METADATA_EVENT_TYPE};
+use raw::{self, RawMessage, OperationResult};
+use raw::client_messages::mod_SubscriptionDropped::SubscriptionDropReason;
+use server::{Context, Handler, HandlerFuture, Pusher, Reply, Server};
+use {ContentType, EventNumber, ExpectedVersion, LogPosition, ReadDirection, StreamVersion};
+
+/// `$tb` of soft deleted streams and the number of the tombstone of hard deleted streams,
+/// `EventNumber.DeletedStream` of EventStore 4.
+const DELETED_STREAM: u64 = i64::max_value() as u64;
+
+/// Event type of the link events resolved with `resolve_link_tos`.
+const LINK_EVENT_TYPE: &'static str = "$>";
+
+/// Event type of the event written to `$all` when a stream is hard deleted.
+const TOMBSTONE_EVENT_TYPE: &'static str = "$streamDeleted";
+
+/// Stream used for the access checks of `$all` reads and subscriptions.
+const ALL_STREAM: &'static str = "$all";
+
+/// In-memory `server::Handler`, see the module documentation. Cloning the store shares the
+/// events.
+#[derive(Clone)]
+pub struct MemoryStore {
+    state: Rc<RefCell<State>>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl MemoryStore {
+    /// Creates an empty store with the default users of EventStore: `admin` in `$admins` and
+    /// `ops` in `$ops`, both with password `changeit`.
+    pub fn new() -> Self {
+        MemoryStore::without_users()
+            .with_user("admin", "changeit", &[acl::ADMINS])
+            .with_user("ops", "changeit", &["$ops"])
+    }
+
+    /// Creates an empty store without any users.
+    pub fn without_users() -> Self {
+        MemoryStore {
+            state: Rc::new(RefCell::new(State::default())),
+        }
+    }
+
+    /// Adds or replaces a user with the given password and groups.
+    pub fn with_user(self, username: &str, password: &str, groups: &[&str]) -> Self {
+        self.state.borrow_mut().users.insert(username.to_owned(), User {
+            password: password.to_owned(),
+            groups: groups.iter().map(|x| x.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Starts serving the store on the given address in the background of `handle`, returning
+    /// the bound address. Use port 0 to bind to an ephemeral port.
+    pub fn spawn(self, addr: &SocketAddr, handle: &Handle) -> io::Result<SocketAddr> {
+        let listening = Server::new(self, handle).listen(addr)?;
+        let local_addr = listening.local_addr();
+        handle.spawn(listening.map_err(|_| ()));
+        Ok(local_addr)
+    }
+}
+
+struct User {
+    password: String,
+    groups: Vec<String>,
+}
+
+struct Stored {
+    event: RecordedEvent,
+    /// Both the commit and the prepare position in `$all`
+    position: u64,
+}
+
+impl Stored {
+    fn position(&self) -> Position {
+        Position {
+            commit: LogPosition::Exact(self.position),
+            prepare: LogPosition::Exact(self.position),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stream {
+    /// Indices of the events in the log, event number being the index in this vector
+    events: Vec<usize>,
+    hard_deleted: bool,
+}
+
+struct Subscription {
+    correlation_id: Uuid,
+    /// `None` for subscriptions to `$all`
+    stream: Option<String>,
+    /// The authenticated user, checked against the ACL on every delivery
+    user: Option<String>,
+    resolve_link_tos: bool,
+    pusher: Pusher,
+}
+
+#[derive(Default)]
+struct State {
+    users: HashMap<String, User>,
+    log: Vec<Stored>,
+    streams: HashMap<String, Stream>,
+    subscriptions: Vec<Subscription>,
+}
+
+fn version(number: u64) -> StreamVersion {
+    StreamVersion::try_from(number).expect("event numbers are within i64")
+}
+
+/// The stream whose metadata applies to the given stream: metastreams share the metadata of
+/// their stream.
+fn base_stream(stream: &str) -> &str {
+    if stream.starts_with("$$") {
+        &stream[2..]
+    } else {
+        stream
+    }
+}
+
+/// Event written by the store itself, with a new id and empty data.
+fn system_event(stream: &str, event_type: &str) -> RecordedEvent {
+    RecordedEvent {
+        stream_id: stream.to_owned(),
+        number: version(0),
+        event_id: Uuid::new_v4(),
+        event_type: event_type.to_owned(),
+        data_content_type: ContentType::Bytes,
+        metadata_content_type: ContentType::Bytes,
+        data: Bytes::new(),
+        metadata: None,
+        created: Some(SystemTime::now()),
+        created_fields: CreatedFields::Both,
+    }
+}
+
+fn check_expected_version(expected: ExpectedVersion, current: Option<u64>, soft_deleted: bool) -> bool {
+    match expected {
+        ExpectedVersion::Any => true,
+        ExpectedVersion::NoStream => current.is_none() || soft_deleted,
+        ExpectedVersion::StreamExists => current.is_some() && !soft_deleted,
+        ExpectedVersion::Exact(v) => current == Some(u64::from(v)),
+    }
+}
+
+impl State {
+    /// Name of the authenticated user, `Err` for unknown credentials.
+    fn user(&self, ctx: &Context) -> Result<Option<String>, ()> {
+        match ctx.authentication() {
+            Some(creds) => match self.users.get(&*creds.0) {
+                Some(user) if user.password == creds.1 => Ok(Some(creds.0.to_string())),
+                _ => Err(()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn allowed(&self, user: Option<&str>, stream: &str, op: Operation) -> bool {
+        let user = user.and_then(|name| self.users.get(name).map(|u| (name, u)));
+
+        if let Some((_, u)) = user {
+            if u.groups.iter().any(|g| g == acl::ADMINS) {
+                return true;
+            }
+        }
+
+        let metadata = self.metadata(base_stream(stream));
+        let (roles, _) = acl::effective_acl(stream, op, &metadata, &self.settings());
+
+        roles.iter().any(|role| {
+            role == acl::ALL || user.map_or(false, |(name, u)| role == name || u.groups.contains(role))
+        })
+    }
+
+    fn settings(&self) -> SystemSettings {
+        self.last_event(SETTINGS_STREAM)
+            .and_then(|e| SystemSettings::from_json(&e.data).ok())
+            .unwrap_or_default()
+    }
+
+    fn metadata(&self, stream: &str) -> StreamMetadata {
+        if stream.starts_with("$$") {
+            // metastreams only keep the latest metadata
+            return StreamMetadata { max_count: Some(1), ..StreamMetadata::default() };
+        }
+
+        self.last_event(&metadata::metastream_of(stream))
+            .and_then(|e| StreamMetadata::from_json(&e.data).ok())
+            .unwrap_or_default()
+    }
+
+    fn last_event(&self, stream: &str) -> Option<&RecordedEvent> {
+        self.streams.get(stream)
+            .and_then(|s| s.events.last())
+            .map(|&i| &self.log[i].event)
+    }
+
+    fn last_number(&self, stream: &str) -> Option<u64> {
+        self.streams.get(stream)
+            .and_then(|s| (s.events.len() as u64).checked_sub(1))
+    }
+
+    fn is_hard_deleted(&self, stream: &str) -> bool {
+        [stream, base_stream(stream)].iter()
+            .any(|s| self.streams.get(*s).map_or(false, |s| s.hard_deleted))
+    }
+
+    fn is_soft_deleted(&self, stream: &str) -> bool {
+        !stream.starts_with("$$")
+            && self.metadata(stream).truncate_before == Some(version(DELETED_STREAM))
+    }
+
+    /// The lowest event number not hidden by `$tb`, `$maxCount` or `$maxAge`.
+    fn first_visible(&self, stream: &str) -> u64 {
+        let events = match self.streams.get(stream) {
+            Some(s) => &s.events,
+            None => return 0,
+        };
+
+        let len = events.len() as u64;
+        let meta = self.metadata(stream);
+        let mut first = meta.truncate_before.map(u64::from).unwrap_or(0);
+
+        if let Some(max_count) = meta.max_count {
+            first = cmp::max(first, len.saturating_sub(max_count));
+        }
+
+        if let Some(max_age) = meta.max_age {
+            let now = SystemTime::now();
+            let expired = |number: u64| {
+                self.log[events[number as usize]].event.created
+                    .and_then(|created| now.duration_since(created).ok())
+                    .map_or(false, |age| age > max_age)
+            };
+
+            while first < len && expired(first) {
+                first += 1;
+            }
+        }
+
+        first
+    }
+
+    /// The target of a `$>` link event, if it still exists.
+    fn link_target(&self, link: &RecordedEvent) -> Option<usize> {
+        let data = str::from_utf8(&link.data).ok()?;
+        let at = data.find('@')?;
+        let number = data[..at].parse::<u64>().ok()?;
+        let stream = &data[at + 1..];
+
+        if self.is_hard_deleted(stream) || number < self.first_visible(stream) {
+            return None;
+        }
+
+        self.streams.get(stream)
+            .and_then(|s| s.events.get(number as usize))
+            .cloned()
+    }
+
+    fn resolve(&self, index: usize, resolve_link_tos: bool, positioned: bool) -> ResolvedEvent {
+        let stored = &self.log[index];
+        let position = if positioned { Some(stored.position()) } else { None };
+
+        if resolve_link_tos && stored.event.event_type == LINK_EVENT_TYPE {
+            if let Some(target) = self.link_target(&stored.event) {
+                return ResolvedEvent {
+                    event: self.log[target].event.clone(),
+                    link: Some(stored.event.clone()),
+                    position: position,
+                };
+            }
+        }
+
+        ResolvedEvent {
+            event: stored.event.clone(),
+            link: None,
+            position: position,
+        }
+    }
+
+    /// Appends the event to the log and sends it to the matching subscriptions, returning the
+    /// position.
+    fn commit(&mut self, event: RecordedEvent) -> u64 {
+        let position = self.log.len() as u64 + 1;
+        self.log.push(Stored { event: event, position: position });
+        let index = self.log.len() - 1;
+
+        let subscriptions = mem::replace(&mut self.subscriptions, Vec::new());
+        let alive = subscriptions.into_iter().filter(|s| {
+            let stream = &self.log[index].event.stream_id;
+            if s.stream.as_ref().map_or(false, |x| x != stream) {
+                return true;
+            }
+
+            // the acl may have changed since subscribing
+            let target = s.stream.as_ref().map_or(ALL_STREAM, String::as_str);
+            if !self.allowed(s.user.as_ref().map(String::as_str), target, Operation::Read) {
+                let dropped = raw::SubscriptionDropped { reason: SubscriptionDropReason::AccessDenied };
+                let _ = s.pusher.push(s.correlation_id, RawMessage::SubscriptionDropped(dropped));
+                return false;
+            }
+
+            let event = self.resolve(index, s.resolve_link_tos, true);
+            let appeared = raw::StreamEventAppeared { event: event.as_raw_positioned().into_owned() };
+
+            // a failed push means the connection has been closed
+            s.pusher.push(s.correlation_id, RawMessage::StreamEventAppeared(appeared)).is_ok()
+        }).collect();
+
+        self.subscriptions = alive;
+        position
+    }
+
+    /// Appends the event to the end of `event.stream_id`, assigning the event number. Returns
+    /// the position.
+    fn append(&mut self, mut event: RecordedEvent) -> u64 {
+        let stream = event.stream_id.clone();
+        let number = self.streams.get(&stream).map_or(0, |s| s.events.len() as u64);
+        event.number = version(number);
+
+        let position = self.commit(event);
+
+        let index = self.log.len() - 1;
+        self.streams.entry(stream).or_insert_with(Stream::default).events.push(index);
+        position
+    }
+
+    fn append_metadata(&mut self, stream: &str, metadata: &StreamMetadata) -> u64 {
+        let mut event = system_event(&metadata::metastream_of(stream), METADATA_EVENT_TYPE);
+        event.data_content_type = ContentType::Json;
+        event.data = Bytes::from(metadata.to_json().into_bytes());
+        self.append(event)
+    }
+
+    /// The event numbers of an earlier write with the same event ids, `Some(Err(()))` if the ids
+    /// only partially match.
+    fn idempotent(&self, stream: &str, expected: ExpectedVersion, ids: &[Uuid]) -> Option<Result<Range<u64>, ()>> {
+        let events = &self.streams.get(stream)?.events;
+        let first_id = *ids.first()?;
+        let id_of = |i: &usize| self.log[*i].event.event_id;
+
+        let start = match expected {
+            ExpectedVersion::Exact(v) => u64::from(v) + 1,
+            ExpectedVersion::NoStream => 0,
+            ExpectedVersion::Any | ExpectedVersion::StreamExists =>
+                events.iter().position(|i| id_of(i) == first_id)? as u64,
+        };
+
+        let existing = events.iter().skip(start as usize).take(ids.len()).map(id_of).collect::<Vec<_>>();
+
+        if existing.first() != Some(&first_id) {
+            None
+        } else if existing == ids {
+            Some(Ok(start..start + ids.len() as u64))
+        } else {
+            Some(Err(()))
+        }
+    }
+
+    fn written(&self, stream: &str, numbers: Range<u64>) -> WriteEventsCompleted {
+        let position = self.streams.get(stream)
+            .and_then(|s| s.events.get(numbers.start as usize))
+            .map(|&i| LogPosition::Exact(self.log[i].position));
+
+        WriteEventsCompleted {
+            event_numbers: version(numbers.start)..version(numbers.end),
+            prepare_position: position,
+            commit_position: position,
+            message: None,
+            current_version: None,
+        }
+    }
+
+    fn write_events(&mut self, ctx: &Context, req: raw::WriteEvents<'static>) -> Reply<Result<WriteEventsCompleted, WriteEventsFailure>> {
+        let user = match self.user(ctx) {
+            Ok(user) => user,
+            Err(()) => return Reply::NotAuthenticated,
+        };
+
+        let stream = req.event_stream_id.into_owned();
+
+        if !self.allowed(user.as_ref().map(String::as_str), &stream, Operation::Write) {
+            return Reply::Completed(Err(WriteEventsFailure::AccessDenied));
+        }
+
+        if self.is_hard_deleted(&stream) {
+            return Reply::Completed(Err(WriteEventsFailure::StreamDeleted));
+        }
+
+        let expected = match ExpectedVersion::try_from(req.expected_version) {
+            Ok(expected) => expected,
+            Err(e) => return Reply::BadRequest(e.to_string()),
+        };
+
+        let ids = match req.events.iter().map(|e| Uuid::from_bytes(&e.event_id)).collect::<Result<Vec<_>, _>>() {
+            Ok(ids) => ids,
+            Err(_) => return Reply::BadRequest("invalid event id".to_owned()),
+        };
+
+        match self.idempotent(&stream, expected, &ids) {
+            Some(Ok(numbers)) => return Reply::Completed(Ok(self.written(&stream, numbers))),
+            Some(Err(())) => return Reply::Completed(Err(WriteEventsFailure::WrongExpectedVersion)),
+            None => {},
+        }
+
+        let current = self.last_number(&stream);
+        let soft_deleted = self.is_soft_deleted(&stream);
+
+        if !check_expected_version(expected, current, soft_deleted) {
+            return Reply::Completed(Err(WriteEventsFailure::WrongExpectedVersion));
+        }
+
+        let first = current.map_or(0, |x| x + 1);
+
+        if soft_deleted && !ids.is_empty() {
+            // recreate the stream, hiding the events written before the delete
+            let mut metadata = self.metadata(&stream);
+            metadata.truncate_before = Some(version(first));
+            self.append_metadata(&stream, &metadata);
+        }
+
+        for (event, id) in req.events.into_iter().zip(ids) {
+            self.append(RecordedEvent {
+                stream_id: stream.clone(),
+                number: version(0),
+                event_id: id,
+                event_type: event.event_type.into_owned(),
+                data_content_type: event.data_content_type.into(),
+                metadata_content_type: event.metadata_content_type.into(),
+                data: Bytes::from(&event.data[..]),
+                metadata: event.metadata.as_ref().map(|x| Bytes::from(&x[..])),
+                created: Some(SystemTime::now()),
+                created_fields: CreatedFields::Both,
+            });
+        }
+
+        let last = self.last_number(&stream).map_or(0, |x| x + 1);
+        Reply::Completed(Ok(self.written(&stream, first..last)))
+    }
+
+    fn delete_stream(&mut self, ctx: &Context, req: raw::DeleteStream<'static>) -> Reply<raw::DeleteStreamCompleted<'static>> {
+        let user = match self.user(ctx) {
+            Ok(user) => user,
+            Err(()) => return Reply::NotAuthenticated,
+        };
+
+        let failed = |result| raw::DeleteStreamCompleted {
+            result: Some(result),
+            ..raw::DeleteStreamCompleted::default()
+        };
+
+        let stream = req.event_stream_id.into_owned();
+
+        if !self.allowed(user.as_ref().map(String::as_str), &stream, Operation::Delete) {
+            return Reply::Completed(failed(OperationResult::AccessDenied));
+        }
+
+        if self.is_hard_deleted(&stream) {
+            return Reply::Completed(failed(OperationResult::StreamDeleted));
+        }
+
+        let expected = match ExpectedVersion::try_from(req.expected_version) {
+            Ok(expected) => expected,
+            Err(e) => return Reply::BadRequest(e.to_string()),
+        };
+
+        if !check_expected_version(expected, self.last_number(&stream), self.is_soft_deleted(&stream)) {
+            return Reply::Completed(failed(OperationResult::WrongExpectedVersion));
+        }
+
+        let position = if req.hard_delete.unwrap_or(false) {
+            self.streams.entry(stream.clone()).or_insert_with(Stream::default).hard_deleted = true;
+            let mut tombstone = system_event(&stream, TOMBSTONE_EVENT_TYPE);
+            tombstone.number = version(DELETED_STREAM);
+            self.commit(tombstone)
+        } else {
+            let mut metadata = self.metadata(&stream);
+            metadata.truncate_before = Some(version(DELETED_STREAM));
+            self.append_metadata(&stream, &metadata)
+        };
+
+        Reply::Completed(raw::DeleteStreamCompleted {
+            result: Some(OperationResult::Success),
+            message: None,
+            prepare_position: Some(position as i64),
+            commit_position: Some(position as i64),
+        })
+    }
+
+    fn read_event(&self, ctx: &Context, req: raw::ReadEvent<'static>) -> Reply<Result<ResolvedEvent, ReadEventError<'static>>> {
+        let user = match self.user(ctx) {
+            Ok(user) => user,
+            Err(()) => return Reply::NotAuthenticated,
+        };
+
+        let stream = &*req.event_stream_id;
+
+        if !self.allowed(user.as_ref().map(String::as_str), stream, Operation::Read) {
+            return Reply::Completed(Err(ReadEventError::AccessDenied));
+        }
+
+        if self.is_hard_deleted(stream) {
+            return Reply::Completed(Err(ReadEventError::StreamDeleted));
+        }
+
+        let events = match self.streams.get(stream) {
+            Some(s) if !self.is_soft_deleted(stream) => &s.events,
+            _ => return Reply::Completed(Err(ReadEventError::NoStream)),
+        };
+
+        let first = self.first_visible(stream);
+        let number = match req.event_number {
+            -1 => events.len() as u64 - 1,
+            n if n >= 0 => n as u64,
+            n => return Reply::BadRequest(format!("invalid event number: {}", n)),
+        };
+
+        if number < first || number >= events.len() as u64 {
+            return Reply::Completed(Err(ReadEventError::NotFound));
+        }
+
+        Reply::Completed(Ok(self.resolve(events[number as usize], req.resolve_link_tos, false)))
+    }
+
+    fn read_stream_events(&self, ctx: &Context, dir: ReadDirection, req: raw::ReadStreamEvents<'static>) -> Reply<Result<ReadStreamCompleted, ReadStreamError<'static>>> {
+        let user = match self.user(ctx) {
+            Ok(user) => user,
+            Err(()) => return Reply::NotAuthenticated,
+        };
+
+        let stream = &*req.event_stream_id;
+
+        if !self.allowed(user.as_ref().map(String::as_str), stream, Operation::Read) {
+            return Reply::Completed(Err(ReadStreamError::AccessDenied));
+        }
+
+        if self.is_hard_deleted(stream) {
+            return Reply::Completed(Err(ReadStreamError::StreamDeleted));
+        }
+
+        let events = match self.streams.get(stream) {
+            Some(s) if !self.is_soft_deleted(stream) => &s.events,
+            _ => return Reply::Completed(Err(ReadStreamError::NoStream)),
+        };
+
+        let len = events.len() as u64;
+        let first = cmp::min(self.first_visible(stream), len);
+        let count = cmp::max(req.max_count, 0) as u64;
+
+        let (numbers, next_page, end_of_stream): (Vec<u64>, Option<EventNumber>, bool) = match dir {
+            ReadDirection::Forward => {
+                let from = cmp::max(req.from_event_number, 0) as u64;
+                let start = cmp::min(cmp::max(from, first), len);
+                let end = cmp::min(start.saturating_add(count), len);
+
+                ((start..end).collect(), Some(version(end).into()), end == len)
+            },
+            ReadDirection::Backward => {
+                let from = if req.from_event_number < 0 { len } else { req.from_event_number as u64 + 1 };
+                let end = cmp::min(from, len);
+                let start = cmp::max(end.saturating_sub(count), first);
+                let start = cmp::min(start, end);
+                let next_page = if start > first { Some(version(start - 1).into()) } else { None };
+
+                ((start..end).rev().collect(), next_page, next_page.is_none())
+            },
+        };
+
+        let last_commit_position = events.last().map_or(0, |&i| self.log[i].position as i64);
+
+        Reply::Completed(Ok(ReadStreamCompleted {
+            events: numbers.into_iter()
+                .map(|n| self.resolve(events[n as usize], req.resolve_link_tos, false))
+                .collect(),
+            next_page: next_page,
+            last_event_number: version(len.saturating_sub(1)),
+            end_of_stream: end_of_stream,
+            last_commit_position: last_commit_position,
+        }))
+    }
+
+    fn read_all_events(&self, ctx: &Context, dir: ReadDirection, req: raw::ReadAllEvents) -> Reply<Result<ReadAllCompleted, ReadAllError<'static>>> {
+        let user = match self.user(ctx) {
+            Ok(user) => user,
+            Err(()) => return Reply::NotAuthenticated,
+        };
+
+        if !self.allowed(user.as_ref().map(String::as_str), ALL_STREAM, Operation::Read) {
+            return Reply::Completed(Err(ReadAllError::AccessDenied));
+        }
+
+        let (commit, prepare) = match (LogPosition::try_from(req.commit_position), LogPosition::try_from(req.prepare_position)) {
+            (Ok(commit), Ok(prepare)) => (commit, prepare),
+            (Err((_, e)), _) | (_, Err((_, e))) => return Reply::Completed(Err(ReadAllError::Error(Some(Cow::Owned(e.to_string()))))),
+        };
+
+        let len = self.log.len();
+        let count = cmp::max(req.max_count, 0) as usize;
+
+        // positions start from 1, the event at index i is at position i + 1
+        let indices = match (dir, commit) {
+            (ReadDirection::Forward, LogPosition::Exact(p)) => {
+                let start = cmp::min((p as usize).saturating_sub(1), len);
+                (start..cmp::min(start.saturating_add(count), len)).collect::<Vec<_>>()
+            },
+            (ReadDirection::Forward, LogPosition::First) => (0..cmp::min(count, len)).collect(),
+            (ReadDirection::Forward, LogPosition::Last) => Vec::new(),
+            (ReadDirection::Backward, pos) => {
+                let end = match pos {
+                    LogPosition::Exact(p) => cmp::min((p as usize).saturating_sub(1), len),
+                    LogPosition::First => 0,
+                    LogPosition::Last => len,
+                };
+                (end.saturating_sub(count)..end).rev().collect()
+            },
+        };
+
+        let next = match (dir, indices.last()) {
+            (ReadDirection::Forward, Some(&i)) => LogPosition::Exact(self.log[i].position + 1),
+            (ReadDirection::Backward, Some(&i)) => LogPosition::Exact(self.log[i].position),
+            (ReadDirection::Forward, None) => commit,
+            (ReadDirection::Backward, None) => LogPosition::First,
+        };
+
+        Reply::Completed(Ok(ReadAllCompleted {
+            commit_position: commit,
+            prepare_position: prepare,
+            events: indices.into_iter().map(|i| self.resolve(i, req.resolve_link_tos, true)).collect(),
+            next_commit_position: next,
+            next_prepare_position: next,
+        }))
+    }
+
+    fn subscribe(&mut self, ctx: &Context, req: raw::SubscribeToStream<'static>) -> Reply<Result<raw::SubscriptionConfirmation, SubscriptionDropReason>> {
+        let user = match self.user(ctx) {
+            Ok(user) => user,
+            Err(()) => return Reply::NotAuthenticated,
+        };
+
+        let stream = if req.event_stream_id.is_empty() { None } else { Some(req.event_stream_id.into_owned()) };
+
+        if !self.allowed(user.as_ref().map(String::as_str), stream.as_ref().map_or(ALL_STREAM, String::as_str), Operation::Read) {
+            return Reply::Completed(Err(SubscriptionDropReason::AccessDenied));
+        }
+
+        let confirmation = raw::SubscriptionConfirmation {
+            last_commit_position: self.log.len() as i64,
+            last_event_number: stream.as_ref().and_then(|s| self.last_number(s)).map(|x| x as i64),
+        };
+
+        self.subscriptions.push(Subscription {
+            correlation_id: *ctx.correlation_id(),
+            stream: stream,
+            user: user,
+            resolve_link_tos: req.resolve_link_tos,
+            pusher: ctx.pusher().clone(),
+        });
+
+        Reply::Completed(Ok(confirmation))
+    }
+}
+
+impl Handler for MemoryStore {
+    fn write_events(&self, ctx: &Context, req: raw::WriteEvents<'static>) -> HandlerFuture<Result<WriteEventsCompleted, WriteEventsFailure>> {
+        Box::new(future::ok(self.state.borrow_mut().write_events(ctx, req)))
+    }
+
+    fn delete_stream(&self, ctx: &Context, req: raw::DeleteStream<'static>) -> HandlerFuture<raw::DeleteStreamCompleted<'static>> {
+        Box::new(future::ok(self.state.borrow_mut().delete_stream(ctx, req)))
+    }
+
+    fn read_event(&self, ctx: &Context, req: raw::ReadEvent<'static>) -> HandlerFuture<Result<ResolvedEvent, ReadEventError<'static>>> {
+        Box::new(future::ok(self.state.borrow().read_event(ctx, req)))
+    }
+
+    fn read_stream_events(&self, ctx: &Context, dir: ReadDirection, req: raw::ReadStreamEvents<'static>) -> HandlerFuture<Result<ReadStreamCompleted, ReadStreamError<'static>>> {
+        Box::new(future::ok(self.state.borrow().read_stream_events(ctx, dir, req)))
+    }
+
+    fn read_all_events(&self, ctx: &Context, dir: ReadDirection, req: raw::ReadAllEvents) -> HandlerFuture<Result<ReadAllCompleted, ReadAllError<'static>>> {
+        Box::new(future::ok(self.state.borrow().read_all_events(ctx, dir, req)))
+    }
+
+    fn subscribe_to_stream(&self, ctx: &Context, req: raw::SubscribeToStream<'static>) -> HandlerFuture<Result<raw::SubscriptionConfirmation, SubscriptionDropReason>> {
+        // registered only when polled, so that no events are pushed before the confirmation
+        let state = self.state.clone();
+        let ctx = ctx.clone();
+        Box::new(future::lazy(move || Ok::<_, io::Error>(state.borrow_mut().subscribe(&ctx, req))))
+    }
+
+    fn unsubscribe_from_stream(&self, ctx: &Context) -> HandlerFuture<()> {
+        let id = *ctx.correlation_id();
+        self.state.borrow_mut().subscriptions.retain(|s| s.correlation_id != id);
+        Box::new(future::ok(Reply::Completed(())))
+    }
+
+    fn authenticate(&self, ctx: &Context) -> HandlerFuture<bool> {
+        let state = self.state.borrow();
+        let ok = match state.user(ctx) {
+            Ok(Some(_)) => true,
+            _ => false,
+        };
+        Box::new(future::ok(Reply::Completed(ok)))
+    }
+
+    fn identify_client(&self, _ctx: &Context, _req: raw::IdentifyClient<'static>) -> HandlerFuture<()> {
+        // EventStore 3.9 does not know the message
+        Box::new(future::ok(Reply::BadRequest("unsupported request".to_owned())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use futures::{Future, Stream, Sink};
+    use tokio_core::reactor::Core;
+    use tokio_io::AsyncRead;
+    use tokio_service::Service;
+    use uuid::Uuid;
+
+    use super::MemoryStore;
+    use acl::{self, StreamAcl};
+    use adapted::{AdaptedMessage, ReadAllError, ReadAllFailure, ReadStreamError, WriteEventsFailure};
+    use builder::Builder;
+    use codec::PackageCodec;
+    use fixtures::start;
+    use metadata::{StreamMetadata, METADATA_EVENT_TYPE};
+    use package::Package;
+    use pipe;
+    use raw::{self, RawMessage};
+    use raw::client_messages::mod_SubscriptionDropped::SubscriptionDropReason;
+    use server::Server;
+    use {EventNumber, EventStoreClient, ExpectedVersion, LogPosition, ReadDirection, StreamVersion, UsernamePassword};
+
+    fn call(core: &mut Core, client: &EventStoreClient, req: Package) -> AdaptedMessage<'static> {
+        let resp = core.run(client.call(req)).unwrap();
+        resp.message.try_adapt().unwrap().into_owned()
+    }
+
+    fn write(stream: &str, expected: ExpectedVersion, ids: &[Uuid]) -> Package {
+        let mut builder = Builder::write_events();
+        builder.stream_id(stream.to_owned()).expected_version(expected);
+        for id in ids {
+            builder.new_event().event_id(*id).event_type("test").data(&b"{}"[..]).done();
+        }
+        builder.build_package(None, None)
+    }
+
+    fn read_stream(stream: &str, dir: ReadDirection, from: EventNumber, count: u8) -> Package {
+        Builder::read_stream_events()
+            .stream_id(stream.to_owned())
+            .direction(dir)
+            .from_event_number(from)
+            .max_count(count)
+            .build_package(None, None)
+    }
+
+    fn numbers(msg: AdaptedMessage) -> Vec<u64> {
+        match msg {
+            AdaptedMessage::ReadStreamEventsCompleted(_, Ok(completed)) =>
+                completed.events.iter().map(|e| u64::from(e.event.number)).collect(),
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    fn v(n: u64) -> StreamVersion {
+        StreamVersion::try_from(n).unwrap()
+    }
+
+    #[test]
+    fn expected_versions_and_idempotency() {
+        let mut core = Core::new().unwrap();
+        let client = start(&core, MemoryStore::new());
+        let ids = [Uuid::new_v4(), Uuid::new_v4()];
+
+        match call(&mut core, &client, write("foo", ExpectedVersion::NoStream, &ids)) {
+            AdaptedMessage::WriteEventsCompleted(Ok(completed)) => assert_eq!(completed.event_numbers, v(0)..v(2)),
+            x => panic!("unexpected {:?}", x),
+        }
+
+        // retrying the same write succeeds without writing anything
+        match call(&mut core, &client, write("foo", ExpectedVersion::NoStream, &ids)) {
+            AdaptedMessage::WriteEventsCompleted(Ok(completed)) => assert_eq!(completed.event_numbers, v(0)..v(2)),
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match call(&mut core, &client, write("foo", ExpectedVersion::Exact(v(0)), &[Uuid::new_v4()])) {
+            AdaptedMessage::WriteEventsCompleted(Err(fail)) => assert_eq!(fail.failure, WriteEventsFailure::WrongExpectedVersion),
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match call(&mut core, &client, write("foo", ExpectedVersion::Exact(v(1)), &[Uuid::new_v4()])) {
+            AdaptedMessage::WriteEventsCompleted(Ok(completed)) => assert_eq!(completed.event_numbers, v(2)..v(3)),
+            x => panic!("unexpected {:?}", x),
+        }
+
+        assert_eq!(numbers(call(&mut core, &client, read_stream("foo", ReadDirection::Forward, EventNumber::First, 2))), vec![0, 1]);
+        assert_eq!(numbers(call(&mut core, &client, read_stream("foo", ReadDirection::Backward, EventNumber::Last, 2))), vec![2, 1]);
+        assert_eq!(numbers(call(&mut core, &client, read_stream("foo", ReadDirection::Backward, EventNumber::Exact(v(0)), 2))), vec![0]);
+    }
+
+    #[test]
+    fn soft_and_hard_delete() {
+        let mut core = Core::new().unwrap();
+        let client = start(&core, MemoryStore::new());
+
+        call(&mut core, &client, write("foo", ExpectedVersion::Any, &[Uuid::new_v4(), Uuid::new_v4()]));
+
+        let delete = |hard| Builder::delete_stream()
+            .stream_id("foo")
+            .expected_version(ExpectedVersion::Any)
+            .hard_delete(hard)
+            .build_package(None, None);
+
+        call(&mut core, &client, delete(false));
+
+        match call(&mut core, &client, read_stream("foo", ReadDirection::Forward, EventNumber::First, 10)) {
+            AdaptedMessage::ReadStreamEventsCompleted(_, Err(ReadStreamError::NoStream)) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+
+        // writing recreates the stream, continuing the event numbers
+        call(&mut core, &client, write("foo", ExpectedVersion::NoStream, &[Uuid::new_v4()]));
+        assert_eq!(numbers(call(&mut core, &client, read_stream("foo", ReadDirection::Forward, EventNumber::First, 10))), vec![2]);
+
+        call(&mut core, &client, delete(true));
+
+        match call(&mut core, &client, read_stream("foo", ReadDirection::Forward, EventNumber::First, 10)) {
+            AdaptedMessage::ReadStreamEventsCompleted(_, Err(ReadStreamError::StreamDeleted)) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match call(&mut core, &client, write("foo", ExpectedVersion::Any, &[Uuid::new_v4()])) {
+            AdaptedMessage::WriteEventsCompleted(Err(fail)) => assert_eq!(fail.failure, WriteEventsFailure::StreamDeleted),
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn authentication_and_acls() {
+        let mut core = Core::new().unwrap();
+        let client = start(&core, MemoryStore::new());
+
+        let read_all = |creds: Option<UsernamePassword>| Builder::read_all_events()
+            .direction(ReadDirection::Forward)
+            .positions(LogPosition::First, LogPosition::First)
+            .max_count(10)
+            .build_package(creds, None);
+
+        match call(&mut core, &client, read_all(None)) {
+            AdaptedMessage::ReadAllEventsCompleted(_, Err(ReadAllFailure { error: ReadAllError::AccessDenied, .. })) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match call(&mut core, &client, read_all(Some(UsernamePassword::new("admin", "wrong")))) {
+            AdaptedMessage::NotAuthenticated(_) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match call(&mut core, &client, read_all(Some(UsernamePassword::new("admin", "changeit")))) {
+            AdaptedMessage::ReadAllEventsCompleted(ReadDirection::Forward, Ok(completed)) => assert!(completed.events.is_empty()),
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn volatile_subscription() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let store = MemoryStore::new();
+        let client = start(&core, store.clone());
+
+        let (sub_io, server_io) = pipe::duplex();
+        handle.spawn(Server::new(store, &handle).serve(server_io).map_err(|e| panic!("server failed: {}", e)));
+        let (sink, stream) = sub_io.framed(PackageCodec).split();
+
+        let subscribe = Package {
+            authentication: None,
+            correlation_id: Uuid::new_v4(),
+            message: RawMessage::SubscribeToStream(raw::SubscribeToStream {
+                event_stream_id: "foo".into(),
+                resolve_link_tos: false,
+            }),
+        };
+
+        let sink = core.run(sink.send(subscribe.clone())).unwrap();
+        let (confirmation, stream) = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+
+        match confirmation.unwrap().message {
+            RawMessage::SubscriptionConfirmation(ref x) => assert_eq!(x.last_event_number, None),
+            ref x => panic!("unexpected {:?}", x),
+        }
+
+        call(&mut core, &client, write("bar", ExpectedVersion::Any, &[Uuid::new_v4()]));
+        let id = Uuid::new_v4();
+        call(&mut core, &client, write("foo", ExpectedVersion::Any, &[id]));
+
+        let (appeared, stream) = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+        let appeared = appeared.unwrap();
+        assert_eq!(appeared.correlation_id, subscribe.correlation_id);
+        match appeared.message {
+            RawMessage::StreamEventAppeared(ref x) => assert_eq!(&x.event.event.event_id[..], id.as_bytes()),
+            ref x => panic!("unexpected {:?}", x),
+        }
+
+        let unsubscribe = Package {
+            authentication: None,
+            correlation_id: subscribe.correlation_id,
+            message: RawMessage::UnsubscribeFromStream(raw::UnsubscribeFromStream {}),
+        };
+
+        core.run(sink.send(unsubscribe)).unwrap();
+        let (dropped, _) = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+        match dropped.unwrap().message {
+            RawMessage::SubscriptionDropped(_) => {},
+            ref x => panic!("unexpected {:?}", x),
+        }
+
+    }
+
+    #[test]
+    fn subscription_checks_acl_on_delivery() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let store = MemoryStore::new();
+        let client = start(&core, store.clone());
+        let admin = || Some(UsernamePassword::new("admin", "changeit"));
+
+        let (sub_io, server_io) = pipe::duplex();
+        handle.spawn(Server::new(store, &handle).serve(server_io).map_err(|e| panic!("server failed: {}", e)));
+        let (sink, stream) = sub_io.framed(PackageCodec).split();
+
+        let subscribe = Package {
+            authentication: None,
+            correlation_id: Uuid::new_v4(),
+            message: RawMessage::SubscribeToStream(raw::SubscribeToStream {
+                event_stream_id: "foo".into(),
+                resolve_link_tos: false,
+            }),
+        };
+
+        let _sink = core.run(sink.send(subscribe)).unwrap();
+        let (_, stream) = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+
+        let metadata = StreamMetadata { acl: Some(StreamAcl::uniform(&[acl::ADMINS])), ..StreamMetadata::default() };
+        let mut builder = Builder::write_events();
+        builder.stream_id("$$foo").expected_version(ExpectedVersion::Any);
+        builder.new_event().event_type(METADATA_EVENT_TYPE).data(metadata.to_json().into_bytes()).done();
+        call(&mut core, &client, builder.build_package(admin(), None));
+
+        let mut req = write("foo", ExpectedVersion::Any, &[Uuid::new_v4()]);
+        req.authentication = admin();
+        call(&mut core, &client, req);
+
+        let (dropped, _) = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+        match dropped.unwrap().message {
+            RawMessage::SubscriptionDropped(ref x) => assert_eq!(x.reason, SubscriptionDropReason::AccessDenied),
+            ref x => panic!("unexpected {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! Typed stream metadata. The metadata of a stream is stored as the latest `$metadata` event of
+//! the `$$<stream>` metastream, as a JSON object.
+//!
+//! `get_stream_metadata` and `set_stream_metadata` work with any service, see
+//! [Services](../index.html#services).
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! extern crate futures;
+//! extern crate tokio_core;
+//! extern crate eventstore_tcp;
+//!
+//! use std::time::Duration;
+//! use futures::Future;
+//! use tokio_core::reactor::Core;
+//! use eventstore_tcp::EventStoreClient;
+//! use eventstore_tcp::metadata::{get_stream_metadata, set_stream_metadata};
+//!
+//! fn main() {
+//!     let mut core = Core::new().unwrap();
+//!     let addr = "127.0.0.1:1113".parse().unwrap();
+//!
+//!     let job = EventStoreClient::connect(&addr, &core.handle())
+//!         .map_err(From::from)
+//!         .and_then(|client| {
+//!             get_stream_metadata(&client, "orders-1", None).and_then(move |current| {
+//!                 let mut metadata = current.metadata.clone();
+//!                 metadata.max_age = Some(Duration::from_secs(30 * 24 * 3600));
+//!                 set_stream_metadata(&client, "orders-1", current.expected_version(), &metadata, None)
+//!             })
+//!         });
+//!
+//!     core.run(job).unwrap();
+//! }
+//! ```
+
+use std::io;
+use std::fmt;
+use std::error;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::str;
+use std::time::Duration;
+
+use futures::Future;
+use json::{self, JsonValue};
+use tokio_service::Service;
+
+use adapted::{AdaptedMessage, ReadEventError, WriteEventsCompleted, WriteEventsFailure};
+use builder::Builder;
+use package::Package;
+use raw::RawMessage;
+use {ContentType, EventNumber, ExpectedVersion, StreamVersion, UsernamePassword};
+use errors::{Error, ErrorKind};
+use acl::StreamAcl;
+
+/// The event type of metadata events in metastreams.
+pub const METADATA_EVENT_TYPE: &'static str = "$metadata";
+
+/// Returns the name of the metastream of the given stream.
+pub fn metastream_of(stream: &str) -> String {
+    format!("$${}", stream)
+}
+
+/// Metadata of a stream. Unset properties are not written.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StreamMetadata {
+    /// `$maxAge`: events older than this are removed on scavenge. Written in whole seconds.
+    pub max_age: Option<Duration>,
+    /// `$maxCount`: only this many latest events are kept.
+    pub max_count: Option<u64>,
+    /// `$tb`: events before this event number are removed, used for soft deletion.
+    pub truncate_before: Option<StreamVersion>,
+    /// `$cacheControl`: the caching period for HTTP responses. Written in whole seconds.
+    pub cache_control: Option<Duration>,
+    /// `$acl`: access control list of the stream. Roles which are not set fall back to the
+    /// defaults of `$settings`.
+    pub acl: Option<StreamAcl>,
+    /// Any other properties, including application specific ones.
+    pub custom: BTreeMap<String, JsonValue>,
+}
+
+impl StreamMetadata {
+    /// Parses the metadata from the JSON data of a `$metadata` event.
+    pub fn from_json(data: &[u8]) -> Result<Self, Error> {
+        let s = str::from_utf8(data)?;
+        let parsed = json::parse(s).map_err(|e| invalid(format!("{}", e)))?;
+
+        if !parsed.is_object() {
+            bail!(invalid("expected a json object"));
+        }
+
+        let mut metadata = StreamMetadata::default();
+
+        for (key, value) in parsed.entries() {
+            if value.is_null() {
+                continue;
+            }
+
+            match key {
+                "$maxAge" => metadata.max_age = Some(Duration::from_secs(number(key, value)?)),
+                "$maxCount" => metadata.max_count = Some(number(key, value)?),
+                "$tb" => {
+                    let tb = number(key, value)?;
+                    metadata.truncate_before = Some(StreamVersion::try_from(tb)?);
+                },
+                "$cacheControl" => metadata.cache_control = Some(Duration::from_secs(number(key, value)?)),
+                "$acl" => metadata.acl = Some(StreamAcl::from_json(value)?),
+                _ => { metadata.custom.insert(key.to_owned(), value.clone()); },
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// Serializes the metadata into JSON for a `$metadata` event.
+    pub fn to_json(&self) -> String {
+        let mut obj = JsonValue::new_object();
+
+        for (key, value) in &self.custom {
+            obj[key.as_str()] = value.clone();
+        }
+
+        if let Some(max_age) = self.max_age {
+            obj["$maxAge"] = max_age.as_secs().into();
+        }
+
+        if let Some(max_count) = self.max_count {
+            obj["$maxCount"] = max_count.into();
+        }
+
+        if let Some(tb) = self.truncate_before {
+            obj["$tb"] = u64::from(tb).into();
+        }
+
+        if let Some(cache_control) = self.cache_control {
+            obj["$cacheControl"] = cache_control.as_secs().into();
+        }
+
+        if let Some(ref acl) = self.acl {
+            obj["$acl"] = acl.to_json();
+        }
+
+        obj.dump()
+    }
+}
+
+fn number(key: &str, value: &JsonValue) -> Result<u64, Error> {
+    value.as_u64().ok_or_else(|| invalid(format!("invalid {}: {}", key, value.dump())).into())
+}
+
+fn invalid<S: Into<String>>(reason: S) -> ErrorKind {
+    ErrorKind::InvalidStreamMetadata(reason.into())
+}
+
+/// Stream metadata together with the version of the metastream it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionedMetadata {
+    /// The metadata, or the default if none has been set.
+    pub metadata: StreamMetadata,
+    /// Event number of the metadata event in the metastream, `None` if the metastream does not
+    /// exist.
+    pub version: Option<StreamVersion>,
+}
+
+impl VersionedMetadata {
+    /// Expected version for replacing exactly this metadata with `set_stream_metadata`.
+    pub fn expected_version(&self) -> ExpectedVersion {
+        match self.version {
+            Some(version) => ExpectedVersion::Exact(version),
+            None => ExpectedVersion::NoStream,
+        }
+    }
+}
+
+/// Failures of reading or writing stream metadata.
+#[derive(Debug)]
+pub enum MetadataError {
+    /// The request could not be sent or the response received.
+    Io(io::Error),
+    /// Reading the metastream failed.
+    Read(ReadEventError<'static>),
+    /// Writing to the metastream failed.
+    Write(WriteEventsFailure),
+    /// The stored metadata is not valid.
+    Invalid(Error),
+    /// The server responded with an unexpected message, such as `NotAuthenticated`.
+    UnexpectedResponse(RawMessage<'static>),
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MetadataError::Io(ref e) => write!(f, "{}", e),
+            MetadataError::Read(ref e) => write!(f, "Reading stream metadata failed: {:?}", e),
+            MetadataError::Write(ref e) => write!(f, "Writing stream metadata failed: {:?}", e),
+            MetadataError::Invalid(ref e) => write!(f, "{}", e),
+            MetadataError::UnexpectedResponse(ref msg) => write!(f, "Unexpected response: {:?}", msg),
+        }
+    }
+}
+
+impl error::Error for MetadataError {
+    fn description(&self) -> &str {
+        match *self {
+            MetadataError::Io(ref e) => e.description(),
+            MetadataError::Read(_) => "reading stream metadata failed",
+            MetadataError::Write(_) => "writing stream metadata failed",
+            MetadataError::Invalid(ref e) => e.description(),
+            MetadataError::UnexpectedResponse(_) => "unexpected response",
+        }
+    }
+}
+
+impl From<io::Error> for MetadataError {
+    fn from(e: io::Error) -> Self {
+        MetadataError::Io(e)
+    }
+}
+
+impl From<Error> for MetadataError {
+    fn from(e: Error) -> Self {
+        MetadataError::Invalid(e)
+    }
+}
+
+/// Reads the latest metadata of the stream. A missing metastream results in the default
+/// metadata.
+pub fn get_stream_metadata<S>(client: &S, stream: &str, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = VersionedMetadata, Error = MetadataError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static
+{
+    Box::new(read_last_event(client, metastream_of(stream), credentials).and_then(|found| -> Result<VersionedMetadata, MetadataError> {
+        Ok(match found {
+            Some((version, data)) => VersionedMetadata {
+                metadata: StreamMetadata::from_json(&data)?,
+                version: Some(version),
+            },
+            None => VersionedMetadata {
+                metadata: StreamMetadata::default(),
+                version: None,
+            },
+        })
+    }))
+}
+
+/// Replaces the metadata of the stream by writing a new `$metadata` event to the metastream.
+pub fn set_stream_metadata<S>(client: &S, stream: &str, expected_version: ExpectedVersion, metadata: &StreamMetadata, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = WriteEventsCompleted, Error = MetadataError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static
+{
+    write_json_event(client, metastream_of(stream), expected_version, METADATA_EVENT_TYPE, metadata.to_json(), credentials)
+}
+
+/// Reads the data and the event number of the last event of a stream, `None` if the stream does
+/// not exist.
+pub(crate) fn read_last_event<S>(client: &S, stream: String, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = Option<(StreamVersion, Vec<u8>)>, Error = MetadataError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static
+{
+    let req = Builder::read_event()
+        .stream_id(stream)
+        .event_number(EventNumber::Last)
+        .resolve_link_tos(false)
+        .build_package(credentials, None);
+
+    Box::new(client.call(req).from_err().and_then(|resp| -> Result<Option<(StreamVersion, Vec<u8>)>, MetadataError> {
+        match resp.message.try_adapt() {
+            Ok(AdaptedMessage::ReadEventCompleted(Ok(event))) => {
+                Ok(Some((event.event.number, event.event.data.to_vec())))
+            },
+            Ok(AdaptedMessage::ReadEventCompleted(Err(ReadEventError::NotFound))) |
+            Ok(AdaptedMessage::ReadEventCompleted(Err(ReadEventError::NoStream))) => Ok(None),
+            Ok(AdaptedMessage::ReadEventCompleted(Err(e))) => Err(MetadataError::Read(e)),
+            Ok(other) => Err(MetadataError::UnexpectedResponse(other.as_raw().into_owned())),
+            Err((raw, _)) => Err(MetadataError::UnexpectedResponse(raw)),
+        }
+    }))
+}
+
+/// Writes a single JSON event.
+pub(crate) fn write_json_event<S>(client: &S, stream: String, expected_version: ExpectedVersion, event_type: &'static str, json: String, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = WriteEventsCompleted, Error = MetadataError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static
+{
+    let mut builder = Builder::write_events();
+    builder.stream_id(stream)
+        .expected_version(expected_version)
+        .new_event()
+            .event_type(event_type)
+            .data(json.into_bytes())
+            .data_content_type(ContentType::Json)
+        .done();
+
+    let req = builder.build_package(credentials, None);
+
+    Box::new(client.call(req).from_err().and_then(|resp| -> Result<WriteEventsCompleted, MetadataError> {
+        match resp.message.try_adapt() {
+            Ok(AdaptedMessage::WriteEventsCompleted(Ok(completed))) => Ok(completed),
+            Ok(AdaptedMessage::WriteEventsCompleted(Err(e))) => Err(MetadataError::Write(e.failure)),
+            Ok(other) => Err(MetadataError::UnexpectedResponse(other.as_raw().into_owned())),
+            Err((raw, _)) => Err(MetadataError::UnexpectedResponse(raw)),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::cell::RefCell;
+    use std::time::Duration;
+
+    use futures::{future, Future};
+    use tokio_service::Service;
+    use uuid::Uuid;
+
+    use super::*;
+    use raw::client_messages::{ReadEventCompleted, ResolvedIndexedEvent, EventRecord, WriteEventsCompleted as RawWriteEventsCompleted, OperationResult};
+    use raw::client_messages::mod_ReadEventCompleted::ReadEventResult;
+
+    /// Responds with the given message and remembers the request.
+    struct Reply {
+        response: RawMessage<'static>,
+        requests: RefCell<Vec<Package>>,
+    }
+
+    impl Reply {
+        fn new(response: RawMessage<'static>) -> Self {
+            Reply { response: response, requests: RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl Service for Reply {
+        type Request = Package;
+        type Response = Package;
+        type Error = io::Error;
+        type Future = future::FutureResult<Package, io::Error>;
+
+        fn call(&self, req: Package) -> Self::Future {
+            let resp = Package { message: self.response.clone(), ..req.clone() };
+            self.requests.borrow_mut().push(req);
+            future::ok(resp)
+        }
+    }
+
+    fn read_completed(result: ReadEventResult, number: i64, data: &str) -> RawMessage<'static> {
+        RawMessage::ReadEventCompleted(ReadEventCompleted {
+            result: Some(result),
+            event: ResolvedIndexedEvent {
+                event: EventRecord {
+                    event_stream_id: "$$foo".into(),
+                    event_number: number,
+                    event_id: Uuid::new_v4().as_bytes().to_vec().into(),
+                    event_type: METADATA_EVENT_TYPE.into(),
+                    data: data.as_bytes().to_vec().into(),
+                    ..Default::default()
+                },
+                link: None,
+            },
+            error: None,
+        })
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let json = r#"{"$maxAge":3600,"$maxCount":10,"$tb":5,"$cacheControl":60,"$acl":{"$r":"$all"},"owner":"team-a"}"#;
+        let metadata = StreamMetadata::from_json(json.as_bytes()).unwrap();
+
+        assert_eq!(metadata.max_age, Some(Duration::from_secs(3600)));
+        assert_eq!(metadata.max_count, Some(10));
+        assert_eq!(metadata.truncate_before, Some(StreamVersion::from(5u32)));
+        assert_eq!(metadata.cache_control, Some(Duration::from_secs(60)));
+        assert_eq!(metadata.acl.as_ref().unwrap().read, Some(vec!["$all".to_owned()]));
+        assert_eq!(metadata.custom["owner"], "team-a");
+
+        let again = StreamMetadata::from_json(metadata.to_json().as_bytes()).unwrap();
+        assert_eq!(metadata, again);
+    }
+
+    #[test]
+    fn invalid_json() {
+        assert!(StreamMetadata::from_json(b"[]").is_err());
+        assert!(StreamMetadata::from_json(br#"{"$maxCount":-1}"#).is_err());
+        assert!(StreamMetadata::from_json(br#"{"$maxAge":"1d"}"#).is_err());
+    }
+
+    #[test]
+    fn get_existing() {
+        let client = Reply::new(read_completed(ReadEventResult::Success, 3, r#"{"$maxCount":2}"#));
+        let got = get_stream_metadata(&client, "foo", None).wait().unwrap();
+
+        assert_eq!(got.metadata.max_count, Some(2));
+        assert_eq!(got.expected_version(), ExpectedVersion::Exact(StreamVersion::from(3u32)));
+
+        match client.requests.borrow()[0].message {
+            RawMessage::ReadEvent(ref req) => {
+                assert_eq!(req.event_stream_id, "$$foo");
+                assert_eq!(req.event_number, -1);
+            },
+            ref x => panic!("unexpected request: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn get_missing() {
+        let client = Reply::new(read_completed(ReadEventResult::NoStream, -1, ""));
+        let got = get_stream_metadata(&client, "foo", None).wait().unwrap();
+
+        assert_eq!(got.metadata, StreamMetadata::default());
+        assert_eq!(got.expected_version(), ExpectedVersion::NoStream);
+    }
+
+    #[test]
+    fn set_writes_metadata_event() {
+        let client = Reply::new(RawMessage::WriteEventsCompleted(RawWriteEventsCompleted {
+            result: Some(OperationResult::Success),
+            message: None,
+            first_event_number: 0,
+            last_event_number: 0,
+            prepare_position: Some(100),
+            commit_position: Some(100),
+            current_version: None,
+        }));
+
+        let metadata = StreamMetadata { max_count: Some(5), ..Default::default() };
+        set_stream_metadata(&client, "foo", ExpectedVersion::NoStream, &metadata, None).wait().unwrap();
+
+        match client.requests.borrow()[0].message {
+            RawMessage::WriteEvents(ref req) => {
+                assert_eq!(req.event_stream_id, "$$foo");
+                assert_eq!(req.expected_version, -1);
+                assert_eq!(req.events[0].event_type, METADATA_EVENT_TYPE);
+                assert_eq!(StreamMetadata::from_json(&req.events[0].data).unwrap(), metadata);
+            },
+            ref x => panic!("unexpected request: {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! Scriptable `Service` for unit testing code which uses a `Service<Request = Package, Response =
+//! Package>` such as `EventStoreClient`, without any sockets.
+//!
+//! `MockService` answers requests with the responses of the first matching rule, echoing the
+//! correlation id of the request. Rules match by the `Kind` of the message and optionally the
+//! stream id, and can be limited to a number of uses. Every request is recorded for later
+//! assertions. Requests not matching any rule fail with an `io::Error`.
+//!
+//! # Example
+//!
+//! ```
+//! extern crate futures;
+//! extern crate tokio_service;
+//! extern crate eventstore_tcp;
+//!
+//! use std::convert::TryFrom;
+//! use futures::Future;
+//! use tokio_service::Service;
+//!
+//! use eventstore_tcp::{AdaptedMessage, Builder, ExpectedVersion, StreamVersion};
+//! use eventstore_tcp::adapted::WriteEventsCompleted;
+//! use eventstore_tcp::mock::{MockService, Kind};
+//!
+//! fn main() {
+//!     let mock = MockService::new();
+//!     mock.on(Kind::WriteEvents).stream("orders-1").reply(AdaptedMessage::WriteEventsCompleted(Ok(WriteEventsCompleted {
+//!         event_numbers: StreamVersion::try_from(0).unwrap()..StreamVersion::try_from(1).unwrap(),
+//!         prepare_position: None,
+//!         commit_position: None,
+//!         message: None,
+//!         current_version: None,
+//!     })));
+//!
+//!     let req = Builder::write_events()
+//!         .stream_id("orders-1")
+//!         .expected_version(ExpectedVersion::NoStream)
+//!         .new_event()
+//!             .event_type("OrderPlaced")
+//!             .data(&b"{}"[..])
+//!         .done()
+//!         .build_package(None, None);
+//!
+//!     mock.call(req).wait().unwrap();
+//!
+//!     mock.assert_written("orders-1", 1, "OrderPlaced");
+//! }
+//! ```
+
+use std::io;
+use std::fmt;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use futures::{future, Future};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_service::Service;
+
+use adapted::AdaptedMessage;
+use package::Package;
+use raw::RawMessage;
+
+/// The kind of a request message, ignoring the direction of reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// `HeartbeatRequest`
+    HeartbeatRequest,
+    /// `Ping`
+    Ping,
+    /// `WriteEvents`
+    WriteEvents,
+    /// `DeleteStream`
+    DeleteStream,
+    /// `ReadEvent`
+    ReadEvent,
+    /// `ReadStreamEvents` in either direction
+    ReadStreamEvents,
+    /// `ReadAllEvents` in either direction
+    ReadAllEvents,
+    /// `SubscribeToStream`
+    SubscribeToStream,
+    /// `UnsubscribeFromStream`
+    UnsubscribeFromStream,
+    /// `Authenticate`
+    Authenticate,
+    /// `IdentifyClient`
+    IdentifyClient,
+    /// Any other message, by the discriminator
+    Other(u8),
+}
+
+impl Kind {
+    /// The kind of the given message.
+    pub fn of(msg: &RawMessage) -> Kind {
+        match *msg {
+            RawMessage::HeartbeatRequest => Kind::HeartbeatRequest,
+            RawMessage::Ping => Kind::Ping,
+            RawMessage::WriteEvents(_) => Kind::WriteEvents,
+            RawMessage::DeleteStream(_) => Kind::DeleteStream,
+            RawMessage::ReadEvent(_) => Kind::ReadEvent,
+            RawMessage::ReadStreamEvents(..) => Kind::ReadStreamEvents,
+            RawMessage::ReadAllEvents(..) => Kind::ReadAllEvents,
+            RawMessage::SubscribeToStream(_) => Kind::SubscribeToStream,
+            RawMessage::UnsubscribeFromStream(_) => Kind::UnsubscribeFromStream,
+            RawMessage::Authenticate => Kind::Authenticate,
+            RawMessage::IdentifyClient(_) => Kind::IdentifyClient,
+            ref other => Kind::Other(other.discriminator()),
+        }
+    }
+}
+
+/// The stream id of the request, if the message has one.
+pub fn stream_id<'a>(msg: &'a RawMessage) -> Option<&'a str> {
+    match *msg {
+        RawMessage::WriteEvents(ref x) => Some(&*x.event_stream_id),
+        RawMessage::DeleteStream(ref x) => Some(&*x.event_stream_id),
+        RawMessage::ReadEvent(ref x) => Some(&*x.event_stream_id),
+        RawMessage::ReadStreamEvents(_, ref x) => Some(&*x.event_stream_id),
+        RawMessage::SubscribeToStream(ref x) => Some(&*x.event_stream_id),
+        _ => None,
+    }
+}
+
+#[derive(Clone)]
+enum Response {
+    Message(AdaptedMessage<'static>),
+    Error(io::ErrorKind, String),
+}
+
+struct Rule {
+    kind: Kind,
+    stream: Option<String>,
+    response: Response,
+    delay: Option<(Duration, Handle)>,
+    /// Remaining uses, `None` for unlimited
+    times: Option<usize>,
+}
+
+impl Rule {
+    fn matches(&self, msg: &RawMessage) -> bool {
+        self.times != Some(0)
+            && self.kind == Kind::of(msg)
+            && self.stream.as_ref().map_or(true, |s| stream_id(msg) == Some(s.as_str()))
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    rules: Vec<Rule>,
+    requests: Vec<Package>,
+}
+
+/// Scriptable mock implementation of `Service`, see the module documentation. Clones share the
+/// rules and the recorded requests, so a clone can be given to the code under test.
+#[derive(Clone, Default)]
+pub struct MockService {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl MockService {
+    /// Creates a mock without any rules.
+    pub fn new() -> Self {
+        MockService::default()
+    }
+
+    /// Starts a rule for requests of the given kind. The rule is added once completed with
+    /// `RuleBuilder::reply` or `RuleBuilder::fail`. Rules are matched in the order they were added.
+    pub fn on(&self, kind: Kind) -> RuleBuilder {
+        RuleBuilder {
+            mock: self,
+            rule: Rule {
+                kind: kind,
+                stream: None,
+                response: Response::Error(io::ErrorKind::Other, String::new()),
+                delay: None,
+                times: None,
+            },
+        }
+    }
+
+    /// All of the requests received so far, in order.
+    pub fn requests(&self) -> Vec<Package> {
+        self.inner.borrow().requests.clone()
+    }
+
+    /// The requests of the given kind received so far, in order.
+    pub fn requests_of(&self, kind: Kind) -> Vec<Package> {
+        self.inner.borrow().requests.iter()
+            .filter(|x| Kind::of(&x.message) == kind)
+            .cloned()
+            .collect()
+    }
+
+    /// Panics unless `times` requests of the kind were received, to the stream if given.
+    pub fn assert_requested(&self, kind: Kind, stream: Option<&str>, times: usize) {
+        let count = self.inner.borrow().requests.iter()
+            .filter(|x| Kind::of(&x.message) == kind)
+            .filter(|x| stream.map_or(true, |s| stream_id(&x.message) == Some(s)))
+            .count();
+
+        assert!(count == times,
+                "expected {} {:?} requests{}, got {}\n{}",
+                times, kind, stream.map(|s| format!(" to {:?}", s)).unwrap_or_default(), count, self.summary());
+    }
+
+    /// Panics unless a `WriteEvents` to the stream with exactly `events` events, all of the
+    /// given type, was received.
+    pub fn assert_written(&self, stream: &str, events: usize, event_type: &str) {
+        let found = self.inner.borrow().requests.iter().any(|x| match x.message {
+            RawMessage::WriteEvents(ref w) => {
+                w.event_stream_id == stream
+                    && w.events.len() == events
+                    && w.events.iter().all(|e| e.event_type == event_type)
+            },
+            _ => false,
+        });
+
+        assert!(found,
+                "expected a WriteEvents to {:?} with {} events of type {:?}\n{}",
+                stream, events, event_type, self.summary());
+    }
+
+    /// Panics if any rule limited with `RuleBuilder::times` has uses left.
+    pub fn assert_all_used(&self) {
+        let inner = self.inner.borrow();
+        let unused = inner.rules.iter()
+            .filter(|r| r.times.map_or(false, |x| x > 0))
+            .map(|r| format!("{:?}{}", r.kind, r.stream.as_ref().map(|s| format!(" to {:?}", s)).unwrap_or_default()))
+            .collect::<Vec<_>>();
+
+        assert!(unused.is_empty(), "rules left unused: {}", unused.join(", "));
+    }
+
+    fn summary(&self) -> String {
+        let inner = self.inner.borrow();
+        let mut s = format!("received {} requests:", inner.requests.len());
+        for req in &inner.requests {
+            s.push_str(&format!("\n  {}", Summary(&req.message)));
+        }
+        s
+    }
+}
+
+struct Summary<'a, 'b: 'a>(&'a RawMessage<'b>);
+
+impl<'a, 'b> fmt::Display for Summary<'a, 'b> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{:?}", Kind::of(self.0))?;
+        if let Some(stream) = stream_id(self.0) {
+            write!(fmt, " to {:?}", stream)?;
+        }
+        if let RawMessage::WriteEvents(ref w) = *self.0 {
+            let types = w.events.iter().map(|e| &*e.event_type).collect::<Vec<_>>();
+            write!(fmt, " with {} events {:?}", w.events.len(), types)?;
+        }
+        Ok(())
+    }
+}
+
+/// Builder for a rule of `MockService`, see `MockService::on`.
+pub struct RuleBuilder<'a> {
+    mock: &'a MockService,
+    rule: Rule,
+}
+
+impl<'a> RuleBuilder<'a> {
+    /// Only match requests to the given stream.
+    pub fn stream<S: Into<String>>(mut self, stream: S) -> Self {
+        self.rule.stream = Some(stream.into());
+        self
+    }
+
+    /// Only use the rule for the given number of requests. By default rules are used for any
+    /// number of requests.
+    pub fn times(mut self, times: usize) -> Self {
+        self.rule.times = Some(times);
+        self
+    }
+
+    /// Delay the response by the given duration, using a timeout on the given reactor.
+    pub fn delay(mut self, delay: Duration, handle: &Handle) -> Self {
+        self.rule.delay = Some((delay, handle.clone()));
+        self
+    }
+
+    /// Completes the rule, answering with the given message.
+    pub fn reply(mut self, response: AdaptedMessage<'static>) {
+        self.rule.response = Response::Message(response);
+        self.add()
+    }
+
+    /// Completes the rule, failing the request with an `io::Error` of the given kind, like a
+    /// failed connection would.
+    pub fn fail(mut self, kind: io::ErrorKind, message: &str) {
+        self.rule.response = Response::Error(kind, message.to_owned());
+        self.add()
+    }
+
+    fn add(self) {
+        self.mock.inner.borrow_mut().rules.push(self.rule);
+    }
+}
+
+impl Service for MockService {
+    type Request = Package;
+    type Response = Package;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = Package, Error = io::Error>>;
+
+    fn call(&self, req: Package) -> Self::Future {
+        let mut inner = self.inner.borrow_mut();
+        inner.requests.push(req.clone());
+
+        let (response, delay) = match inner.rules.iter_mut().find(|r| r.matches(&req.message)) {
+            Some(rule) => {
+                rule.times = rule.times.map(|x| x - 1);
+                (rule.response.clone(), rule.delay.clone())
+            },
+            None => {
+                let msg = format!("no rule for {}", Summary(&req.message));
+                return Box::new(future::err(io::Error::new(io::ErrorKind::Other, msg)));
+            }
+        };
+
+        let correlation_id = req.correlation_id;
+        let result = match response {
+            Response::Message(msg) => Ok(Package {
+                authentication: None,
+                correlation_id: correlation_id,
+                message: msg.as_raw().into_owned(),
+            }),
+            Response::Error(kind, msg) => Err(io::Error::new(kind, msg)),
+        };
+
+        match delay {
+            Some((delay, handle)) => match Timeout::new(delay, &handle) {
+                Ok(timeout) => Box::new(timeout.and_then(move |_| result)),
+                Err(e) => Box::new(future::err(e)),
+            },
+            None => Box::new(future::result(result)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::{Duration, Instant};
+
+    use futures::Future;
+    use tokio_core::reactor::Core;
+    use tokio_service::Service;
+
+    use super::{MockService, Kind};
+    use adapted::{AdaptedMessage, ReadEventError};
+    use builder::Builder;
+    use fixtures::write;
+    use package::Package;
+    use EventNumber;
+
+    fn read(stream: &'static str) -> Package {
+        Builder::read_event()
+            .stream_id(stream)
+            .event_number(EventNumber::First)
+            .build_package(None, None)
+    }
+
+    #[test]
+    fn matches_by_kind_and_stream() {
+        let mock = MockService::new();
+        mock.on(Kind::ReadEvent).stream("foo").times(1).reply(AdaptedMessage::ReadEventCompleted(Err(ReadEventError::NotFound)));
+        mock.on(Kind::ReadEvent).reply(AdaptedMessage::ReadEventCompleted(Err(ReadEventError::NoStream)));
+
+        let req = read("foo");
+        let resp = mock.call(req.clone()).wait().unwrap();
+        assert_eq!(resp.correlation_id, req.correlation_id);
+        assert_eq!(resp.message.try_adapt().unwrap(), AdaptedMessage::ReadEventCompleted(Err(ReadEventError::NotFound)));
+
+        // the first rule has been used up
+        let resp = mock.call(read("foo")).wait().unwrap();
+        assert_eq!(resp.message.try_adapt().unwrap(), AdaptedMessage::ReadEventCompleted(Err(ReadEventError::NoStream)));
+
+        let err = mock.call(write()).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+
+        mock.assert_requested(Kind::ReadEvent, Some("foo"), 2);
+        mock.assert_written("foo", 1, "bar");
+        mock.assert_all_used();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a WriteEvents to \"foo\" with 2 events")]
+    fn assert_written_explains() {
+        let mock = MockService::new();
+        mock.on(Kind::WriteEvents).fail(io::ErrorKind::BrokenPipe, "connection lost");
+
+        let err = mock.call(write()).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+        mock.assert_written("foo", 2, "bar");
+    }
+
+    #[test]
+    fn delays_responses() {
+        let mut core = Core::new().unwrap();
+        let mock = MockService::new();
+        mock.on(Kind::Ping).delay(Duration::from_millis(50), &core.handle()).reply(AdaptedMessage::Pong);
+
+        let started = Instant::now();
+        let resp = core.run(mock.call(Builder::ping().build_package(None, None))).unwrap();
+
+        assert_eq!(resp.message, ::raw::RawMessage::Pong);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+}
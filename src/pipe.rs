@@ -0,0 +1,146 @@
+//! In-memory duplex pipe which can be used as a transport instead of a socket, for example with
+//! `EventStoreClient::from_transport`. Useful for testing against an in-process fake server
+//! without binding any ports.
+//!
+//! Both ends of the pipe must be used on the same thread, which is not a problem with
+//! `tokio_core::reactor::Core`.
+
+use std::io;
+use std::cmp;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use futures::{Async, Poll};
+use futures::task::{self, Task};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Buffer of bytes travelling in a single direction.
+#[derive(Default)]
+struct Buffer {
+    data: VecDeque<u8>,
+    /// The writing end has been shut down or dropped
+    writer_closed: bool,
+    /// The reading end has been dropped
+    reader_closed: bool,
+    /// Task waiting for more bytes
+    reader: Option<Task>,
+}
+
+impl Buffer {
+    fn notify_reader(&mut self) {
+        if let Some(task) = self.reader.take() {
+            task.notify();
+        }
+    }
+}
+
+/// One end of the in-memory pipe created with `duplex`. Implements `AsyncRead` and `AsyncWrite`.
+pub struct PipeStream {
+    read: Rc<RefCell<Buffer>>,
+    write: Rc<RefCell<Buffer>>,
+}
+
+/// Creates a connected pair of in-memory streams. Bytes written to one end can be read from the
+/// other end. Dropping or shutting down one end will be seen as an end-of-file by the other.
+pub fn duplex() -> (PipeStream, PipeStream) {
+    let a = Rc::new(RefCell::new(Buffer::default()));
+    let b = Rc::new(RefCell::new(Buffer::default()));
+
+    let first = PipeStream { read: a.clone(), write: b.clone() };
+    let second = PipeStream { read: b, write: a };
+
+    (first, second)
+}
+
+impl io::Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut inbound = self.read.borrow_mut();
+
+        if inbound.data.is_empty() {
+            if inbound.writer_closed {
+                return Ok(0);
+            }
+
+            inbound.reader = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let len = cmp::min(buf.len(), inbound.data.len());
+        for (dst, src) in buf.iter_mut().zip(inbound.data.drain(..len)) {
+            *dst = src;
+        }
+
+        Ok(len)
+    }
+}
+
+impl io::Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut outbound = self.write.borrow_mut();
+
+        if outbound.reader_closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        if outbound.writer_closed {
+            return Err(io::Error::new(io::ErrorKind::Other, "write after shutdown"));
+        }
+
+        outbound.data.extend(buf.iter().cloned());
+        outbound.notify_reader();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for PipeStream {}
+
+impl AsyncWrite for PipeStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        let mut outbound = self.write.borrow_mut();
+        outbound.writer_closed = true;
+        outbound.notify_reader();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for PipeStream {
+    fn drop(&mut self) {
+        {
+            let mut outbound = self.write.borrow_mut();
+            outbound.writer_closed = true;
+            outbound.notify_reader();
+        }
+
+        self.read.borrow_mut().reader_closed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::Future;
+    use tokio_core::reactor::Core;
+    use tokio_io::io::{read_exact, write_all, read_to_end};
+    use super::duplex;
+
+    #[test]
+    fn bytes_flow_both_ways() {
+        let mut core = Core::new().unwrap();
+        let (a, b) = duplex();
+
+        let job = write_all(a, b"ping")
+            .and_then(|(a, _)| read_exact(b, [0u8; 4]).map(move |(b, buf)| (a, b, buf)))
+            .and_then(|(a, b, buf)| {
+                assert_eq!(&buf, b"ping");
+                write_all(b, b"pong").map(move |_| a)
+            })
+            .and_then(|a| read_to_end(a, Vec::new()));
+
+        let (_, buf) = core.run(job).unwrap();
+        assert_eq!(&buf[..], b"pong");
+    }
+}
@@ -0,0 +1,115 @@
+use std::i32;
+use std::i64;
+use std::str::FromStr;
+
+use raw::RawMessage;
+use {Error, ErrorKind};
+
+/// `ProtocolVersion` selects the range of event numbers the server understands. EventStore 3.9
+/// uses 32-bit event numbers while EventStore 4 and newer use 64-bit event numbers. The messages
+/// are encoded the same way, so only the range of the values differs.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ProtocolVersion {
+    /// EventStore 3.9 and earlier, with 32-bit event numbers
+    V3,
+    /// EventStore 4 and newer, with 64-bit event numbers and `IdentifyClient`
+    V4,
+}
+
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        ProtocolVersion::V3
+    }
+}
+
+impl ProtocolVersion {
+    /// Returns the largest event number the server can store, which is also the version the
+    /// server uses for hard deleted streams.
+    pub fn max_event_number(&self) -> i64 {
+        match *self {
+            ProtocolVersion::V3 => i32::MAX as i64,
+            ProtocolVersion::V4 => i64::MAX,
+        }
+    }
+
+    /// Checks that every event number in the message can be represented in this version of the
+    /// protocol. Sending larger event numbers to an EventStore 3.9 server would silently truncate
+    /// them.
+    pub fn check(&self, msg: &RawMessage) -> Result<(), Error> {
+        if *self == ProtocolVersion::V4 {
+            return Ok(());
+        }
+
+        let numbers = match *msg {
+            RawMessage::WriteEvents(ref x) => vec![x.expected_version],
+            RawMessage::WriteEventsCompleted(ref x) => vec![x.first_event_number, x.last_event_number],
+            RawMessage::DeleteStream(ref x) => vec![x.expected_version],
+            RawMessage::ReadEvent(ref x) => vec![x.event_number],
+            RawMessage::ReadEventCompleted(ref x) => {
+                let mut numbers = vec![x.event.event.event_number];
+                numbers.extend(x.event.link.as_ref().map(|link| link.event_number));
+                numbers
+            },
+            RawMessage::ReadStreamEvents(_, ref x) => vec![x.from_event_number],
+            RawMessage::ReadStreamEventsCompleted(_, ref x) => {
+                let mut numbers = vec![x.next_event_number, x.last_event_number];
+                for event in &x.events {
+                    numbers.push(event.event.event_number);
+                    numbers.extend(event.link.as_ref().map(|link| link.event_number));
+                }
+                numbers
+            },
+            _ => return Ok(()),
+        };
+
+        match numbers.into_iter().find(|n| *n < i32::MIN as i64 || *n > i32::MAX as i64) {
+            Some(n) => Err(ErrorKind::UnsupportedEventNumber(n, *self).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for ProtocolVersion {
+    type Err = Error;
+
+    /// Parses `3` or `4`, optionally prefixed with `v`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_left_matches(|c| c == 'v' || c == 'V') {
+            "3" => Ok(ProtocolVersion::V3),
+            "4" => Ok(ProtocolVersion::V4),
+            _ => Err(ErrorKind::UnsupportedProtocolVersion(s.to_owned()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::i32;
+    use std::convert::TryFrom;
+    use builder::Builder;
+    use StreamVersion;
+    use super::ProtocolVersion;
+
+    #[test]
+    fn v3_rejects_wide_event_numbers() {
+        let msg = |n: i64| Builder::read_event()
+            .stream_id("foo")
+            .event_number(StreamVersion::try_from(n).unwrap())
+            .build_package(None, None)
+            .message;
+
+        let fits = msg(i32::MAX as i64);
+        let wide = msg(i32::MAX as i64 + 1);
+
+        assert!(ProtocolVersion::V3.check(&fits).is_ok());
+        assert!(ProtocolVersion::V3.check(&wide).is_err());
+        assert!(ProtocolVersion::V4.check(&wide).is_ok());
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("3".parse::<ProtocolVersion>().unwrap(), ProtocolVersion::V3);
+        assert_eq!("v4".parse::<ProtocolVersion>().unwrap(), ProtocolVersion::V4);
+        assert!("5".parse::<ProtocolVersion>().is_err());
+    }
+}
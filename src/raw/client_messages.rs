@@ -102,7 +102,7 @@ impl<'a> MessageWrite for NewEvent<'a> {
 #[derive(IntoOwned, Borrowed, Debug, Default, PartialEq, Clone)]
 pub struct EventRecord<'a> {
     pub event_stream_id: Cow<'a, str>,
-    pub event_number: i32,
+    pub event_number: i64,
     pub event_id: Cow<'a, [u8]>,
     pub event_type: Cow<'a, str>,
     pub data_content_type: i32,
@@ -119,7 +119,7 @@ impl<'a> EventRecord<'a> {
         while !r.is_eof() {
             match r.next_tag(bytes) {
                 Ok(10) => msg.event_stream_id = r.read_string(bytes).map(Cow::Borrowed)?,
-                Ok(16) => msg.event_number = r.read_int32(bytes)?,
+                Ok(16) => msg.event_number = r.read_int64(bytes)?,
                 Ok(26) => msg.event_id = r.read_bytes(bytes).map(Cow::Borrowed)?,
                 Ok(34) => msg.event_type = r.read_string(bytes).map(Cow::Borrowed)?,
                 Ok(40) => msg.data_content_type = r.read_int32(bytes)?,
@@ -153,7 +153,7 @@ impl<'a> MessageWrite for EventRecord<'a> {
 
     fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
         w.write_with_tag(10, |w| w.write_string(&**&self.event_stream_id))?;
-        w.write_with_tag(16, |w| w.write_int32(*&self.event_number))?;
+        w.write_with_tag(16, |w| w.write_int64(*&self.event_number))?;
         w.write_with_tag(26, |w| w.write_bytes(&**&self.event_id))?;
         w.write_with_tag(34, |w| w.write_string(&**&self.event_type))?;
         w.write_with_tag(40, |w| w.write_int32(*&self.data_content_type))?;
@@ -247,7 +247,7 @@ impl<'a> MessageWrite for ResolvedEvent<'a> {
 #[derive(IntoOwned, Borrowed, Debug, Default, PartialEq, Clone)]
 pub struct WriteEvents<'a> {
     pub event_stream_id: Cow<'a, str>,
-    pub expected_version: i32,
+    pub expected_version: i64,
     pub events: Vec<NewEvent<'a>>,
     pub require_master: bool,
 }
@@ -258,7 +258,7 @@ impl<'a> WriteEvents<'a> {
         while !r.is_eof() {
             match r.next_tag(bytes) {
                 Ok(10) => msg.event_stream_id = r.read_string(bytes).map(Cow::Borrowed)?,
-                Ok(16) => msg.expected_version = r.read_int32(bytes)?,
+                Ok(16) => msg.expected_version = r.read_int64(bytes)?,
                 Ok(26) => msg.events.push(r.read_message(bytes, NewEvent::from_reader)?),
                 Ok(32) => msg.require_master = r.read_bool(bytes)?,
                 Ok(t) => { r.read_unknown(bytes, t)?; }
@@ -280,7 +280,7 @@ impl<'a> MessageWrite for WriteEvents<'a> {
 
     fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
         w.write_with_tag(10, |w| w.write_string(&**&self.event_stream_id))?;
-        w.write_with_tag(16, |w| w.write_int32(*&self.expected_version))?;
+        w.write_with_tag(16, |w| w.write_int64(*&self.expected_version))?;
         for s in &self.events { w.write_with_tag(26, |w| w.write_message(s))?; }
         w.write_with_tag(32, |w| w.write_bool(*&self.require_master))?;
         Ok(())
@@ -291,10 +291,11 @@ impl<'a> MessageWrite for WriteEvents<'a> {
 pub struct WriteEventsCompleted<'a> {
     pub result: Option<OperationResult>,
     pub message: Option<Cow<'a, str>>,
-    pub first_event_number: i32,
-    pub last_event_number: i32,
+    pub first_event_number: i64,
+    pub last_event_number: i64,
     pub prepare_position: Option<i64>,
     pub commit_position: Option<i64>,
+    pub current_version: Option<i64>,
 }
 
 impl<'a> WriteEventsCompleted<'a> {
@@ -304,10 +305,11 @@ impl<'a> WriteEventsCompleted<'a> {
             match r.next_tag(bytes) {
                 Ok(8) => msg.result = Some(r.read_enum(bytes)?),
                 Ok(18) => msg.message = Some(r.read_string(bytes).map(Cow::Borrowed)?),
-                Ok(24) => msg.first_event_number = r.read_int32(bytes)?,
-                Ok(32) => msg.last_event_number = r.read_int32(bytes)?,
+                Ok(24) => msg.first_event_number = r.read_int64(bytes)?,
+                Ok(32) => msg.last_event_number = r.read_int64(bytes)?,
                 Ok(40) => msg.prepare_position = Some(r.read_int64(bytes)?),
                 Ok(48) => msg.commit_position = Some(r.read_int64(bytes)?),
+                Ok(56) => msg.current_version = Some(r.read_int64(bytes)?),
                 Ok(t) => { r.read_unknown(bytes, t)?; }
                 Err(e) => return Err(e),
             }
@@ -325,15 +327,17 @@ impl<'a> MessageWrite for WriteEventsCompleted<'a> {
         + 1 + sizeof_varint(*(&self.last_event_number) as u64)
         + self.prepare_position.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
         + self.commit_position.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
+        + self.current_version.as_ref().map_or(0, |m| 1 + sizeof_varint(*(m) as u64))
     }
 
     fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
         if let Some(ref s) = self.result { w.write_with_tag(8, |w| w.write_enum(*s as i32))?; }
         if let Some(ref s) = self.message { w.write_with_tag(18, |w| w.write_string(&**s))?; }
-        w.write_with_tag(24, |w| w.write_int32(*&self.first_event_number))?;
-        w.write_with_tag(32, |w| w.write_int32(*&self.last_event_number))?;
+        w.write_with_tag(24, |w| w.write_int64(*&self.first_event_number))?;
+        w.write_with_tag(32, |w| w.write_int64(*&self.last_event_number))?;
         if let Some(ref s) = self.prepare_position { w.write_with_tag(40, |w| w.write_int64(*s))?; }
         if let Some(ref s) = self.commit_position { w.write_with_tag(48, |w| w.write_int64(*s))?; }
+        if let Some(ref s) = self.current_version { w.write_with_tag(56, |w| w.write_int64(*s))?; }
         Ok(())
     }
 }
@@ -341,7 +345,7 @@ impl<'a> MessageWrite for WriteEventsCompleted<'a> {
 #[derive(IntoOwned, Borrowed, Debug, Default, PartialEq, Clone)]
 pub struct DeleteStream<'a> {
     pub event_stream_id: Cow<'a, str>,
-    pub expected_version: i32,
+    pub expected_version: i64,
     pub require_master: bool,
     pub hard_delete: Option<bool>,
 }
@@ -352,7 +356,7 @@ impl<'a> DeleteStream<'a> {
         while !r.is_eof() {
             match r.next_tag(bytes) {
                 Ok(10) => msg.event_stream_id = r.read_string(bytes).map(Cow::Borrowed)?,
-                Ok(16) => msg.expected_version = r.read_int32(bytes)?,
+                Ok(16) => msg.expected_version = r.read_int64(bytes)?,
                 Ok(24) => msg.require_master = r.read_bool(bytes)?,
                 Ok(32) => msg.hard_delete = Some(r.read_bool(bytes)?),
                 Ok(t) => { r.read_unknown(bytes, t)?; }
@@ -374,7 +378,7 @@ impl<'a> MessageWrite for DeleteStream<'a> {
 
     fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
         w.write_with_tag(10, |w| w.write_string(&**&self.event_stream_id))?;
-        w.write_with_tag(16, |w| w.write_int32(*&self.expected_version))?;
+        w.write_with_tag(16, |w| w.write_int64(*&self.expected_version))?;
         w.write_with_tag(24, |w| w.write_bool(*&self.require_master))?;
         if let Some(ref s) = self.hard_delete { w.write_with_tag(32, |w| w.write_bool(*s))?; }
         Ok(())
@@ -427,7 +431,7 @@ impl<'a> MessageWrite for DeleteStreamCompleted<'a> {
 #[derive(IntoOwned, Borrowed, Debug, Default, PartialEq, Clone)]
 pub struct TransactionStart<'a> {
     pub event_stream_id: Cow<'a, str>,
-    pub expected_version: i32,
+    pub expected_version: i64,
     pub require_master: bool,
 }
 
@@ -437,7 +441,7 @@ impl<'a> TransactionStart<'a> {
         while !r.is_eof() {
             match r.next_tag(bytes) {
                 Ok(10) => msg.event_stream_id = r.read_string(bytes).map(Cow::Borrowed)?,
-                Ok(16) => msg.expected_version = r.read_int32(bytes)?,
+                Ok(16) => msg.expected_version = r.read_int64(bytes)?,
                 Ok(24) => msg.require_master = r.read_bool(bytes)?,
                 Ok(t) => { r.read_unknown(bytes, t)?; }
                 Err(e) => return Err(e),
@@ -457,7 +461,7 @@ impl<'a> MessageWrite for TransactionStart<'a> {
 
     fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
         w.write_with_tag(10, |w| w.write_string(&**&self.event_stream_id))?;
-        w.write_with_tag(16, |w| w.write_int32(*&self.expected_version))?;
+        w.write_with_tag(16, |w| w.write_int64(*&self.expected_version))?;
         w.write_with_tag(24, |w| w.write_bool(*&self.require_master))?;
         Ok(())
     }
@@ -620,8 +624,8 @@ pub struct TransactionCommitCompleted<'a> {
     pub transaction_id: i64,
     pub result: Option<OperationResult>,
     pub message: Option<Cow<'a, str>>,
-    pub first_event_number: i32,
-    pub last_event_number: i32,
+    pub first_event_number: i64,
+    pub last_event_number: i64,
     pub prepare_position: Option<i64>,
     pub commit_position: Option<i64>,
 }
@@ -634,8 +638,8 @@ impl<'a> TransactionCommitCompleted<'a> {
                 Ok(8) => msg.transaction_id = r.read_int64(bytes)?,
                 Ok(16) => msg.result = Some(r.read_enum(bytes)?),
                 Ok(26) => msg.message = Some(r.read_string(bytes).map(Cow::Borrowed)?),
-                Ok(32) => msg.first_event_number = r.read_int32(bytes)?,
-                Ok(40) => msg.last_event_number = r.read_int32(bytes)?,
+                Ok(32) => msg.first_event_number = r.read_int64(bytes)?,
+                Ok(40) => msg.last_event_number = r.read_int64(bytes)?,
                 Ok(48) => msg.prepare_position = Some(r.read_int64(bytes)?),
                 Ok(56) => msg.commit_position = Some(r.read_int64(bytes)?),
                 Ok(t) => { r.read_unknown(bytes, t)?; }
@@ -662,8 +666,8 @@ impl<'a> MessageWrite for TransactionCommitCompleted<'a> {
         w.write_with_tag(8, |w| w.write_int64(*&self.transaction_id))?;
         if let Some(ref s) = self.result { w.write_with_tag(16, |w| w.write_enum(*s as i32))?; }
         if let Some(ref s) = self.message { w.write_with_tag(26, |w| w.write_string(&**s))?; }
-        w.write_with_tag(32, |w| w.write_int32(*&self.first_event_number))?;
-        w.write_with_tag(40, |w| w.write_int32(*&self.last_event_number))?;
+        w.write_with_tag(32, |w| w.write_int64(*&self.first_event_number))?;
+        w.write_with_tag(40, |w| w.write_int64(*&self.last_event_number))?;
         if let Some(ref s) = self.prepare_position { w.write_with_tag(48, |w| w.write_int64(*s))?; }
         if let Some(ref s) = self.commit_position { w.write_with_tag(56, |w| w.write_int64(*s))?; }
         Ok(())
@@ -673,7 +677,7 @@ impl<'a> MessageWrite for TransactionCommitCompleted<'a> {
 #[derive(IntoOwned, Borrowed, Debug, Default, PartialEq, Clone)]
 pub struct ReadEvent<'a> {
     pub event_stream_id: Cow<'a, str>,
-    pub event_number: i32,
+    pub event_number: i64,
     pub resolve_link_tos: bool,
     pub require_master: bool,
 }
@@ -684,7 +688,7 @@ impl<'a> ReadEvent<'a> {
         while !r.is_eof() {
             match r.next_tag(bytes) {
                 Ok(10) => msg.event_stream_id = r.read_string(bytes).map(Cow::Borrowed)?,
-                Ok(16) => msg.event_number = r.read_int32(bytes)?,
+                Ok(16) => msg.event_number = r.read_int64(bytes)?,
                 Ok(24) => msg.resolve_link_tos = r.read_bool(bytes)?,
                 Ok(32) => msg.require_master = r.read_bool(bytes)?,
                 Ok(t) => { r.read_unknown(bytes, t)?; }
@@ -706,7 +710,7 @@ impl<'a> MessageWrite for ReadEvent<'a> {
 
     fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
         w.write_with_tag(10, |w| w.write_string(&**&self.event_stream_id))?;
-        w.write_with_tag(16, |w| w.write_int32(*&self.event_number))?;
+        w.write_with_tag(16, |w| w.write_int64(*&self.event_number))?;
         w.write_with_tag(24, |w| w.write_bool(*&self.resolve_link_tos))?;
         w.write_with_tag(32, |w| w.write_bool(*&self.require_master))?;
         Ok(())
@@ -790,7 +794,7 @@ impl From<i32> for ReadEventResult {
 #[derive(IntoOwned, Borrowed, Debug, Default, PartialEq, Clone)]
 pub struct ReadStreamEvents<'a> {
     pub event_stream_id: Cow<'a, str>,
-    pub from_event_number: i32,
+    pub from_event_number: i64,
     pub max_count: i32,
     pub resolve_link_tos: bool,
     pub require_master: bool,
@@ -802,7 +806,7 @@ impl<'a> ReadStreamEvents<'a> {
         while !r.is_eof() {
             match r.next_tag(bytes) {
                 Ok(10) => msg.event_stream_id = r.read_string(bytes).map(Cow::Borrowed)?,
-                Ok(16) => msg.from_event_number = r.read_int32(bytes)?,
+                Ok(16) => msg.from_event_number = r.read_int64(bytes)?,
                 Ok(24) => msg.max_count = r.read_int32(bytes)?,
                 Ok(32) => msg.resolve_link_tos = r.read_bool(bytes)?,
                 Ok(40) => msg.require_master = r.read_bool(bytes)?,
@@ -826,7 +830,7 @@ impl<'a> MessageWrite for ReadStreamEvents<'a> {
 
     fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
         w.write_with_tag(10, |w| w.write_string(&**&self.event_stream_id))?;
-        w.write_with_tag(16, |w| w.write_int32(*&self.from_event_number))?;
+        w.write_with_tag(16, |w| w.write_int64(*&self.from_event_number))?;
         w.write_with_tag(24, |w| w.write_int32(*&self.max_count))?;
         w.write_with_tag(32, |w| w.write_bool(*&self.resolve_link_tos))?;
         w.write_with_tag(40, |w| w.write_bool(*&self.require_master))?;
@@ -838,8 +842,8 @@ impl<'a> MessageWrite for ReadStreamEvents<'a> {
 pub struct ReadStreamEventsCompleted<'a> {
     pub events: Vec<ResolvedIndexedEvent<'a>>,
     pub result: Option<mod_ReadStreamEventsCompleted::ReadStreamResult>,
-    pub next_event_number: i32,
-    pub last_event_number: i32,
+    pub next_event_number: i64,
+    pub last_event_number: i64,
     pub is_end_of_stream: bool,
     pub last_commit_position: i64,
     pub error: Option<Cow<'a, str>>,
@@ -852,8 +856,8 @@ impl<'a> ReadStreamEventsCompleted<'a> {
             match r.next_tag(bytes) {
                 Ok(10) => msg.events.push(r.read_message(bytes, ResolvedIndexedEvent::from_reader)?),
                 Ok(16) => msg.result = Some(r.read_enum(bytes)?),
-                Ok(24) => msg.next_event_number = r.read_int32(bytes)?,
-                Ok(32) => msg.last_event_number = r.read_int32(bytes)?,
+                Ok(24) => msg.next_event_number = r.read_int64(bytes)?,
+                Ok(32) => msg.last_event_number = r.read_int64(bytes)?,
                 Ok(40) => msg.is_end_of_stream = r.read_bool(bytes)?,
                 Ok(48) => msg.last_commit_position = r.read_int64(bytes)?,
                 Ok(58) => msg.error = Some(r.read_string(bytes).map(Cow::Borrowed)?),
@@ -880,8 +884,8 @@ impl<'a> MessageWrite for ReadStreamEventsCompleted<'a> {
     fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
         for s in &self.events { w.write_with_tag(10, |w| w.write_message(s))?; }
         if let Some(ref s) = self.result { w.write_with_tag(16, |w| w.write_enum(*s as i32))?; }
-        w.write_with_tag(24, |w| w.write_int32(*&self.next_event_number))?;
-        w.write_with_tag(32, |w| w.write_int32(*&self.last_event_number))?;
+        w.write_with_tag(24, |w| w.write_int64(*&self.next_event_number))?;
+        w.write_with_tag(32, |w| w.write_int64(*&self.last_event_number))?;
         w.write_with_tag(40, |w| w.write_bool(*&self.is_end_of_stream))?;
         w.write_with_tag(48, |w| w.write_int64(*&self.last_commit_position))?;
         if let Some(ref s) = self.error { w.write_with_tag(58, |w| w.write_string(&**s))?; }
@@ -1065,7 +1069,7 @@ pub struct CreatePersistentSubscription<'a> {
     pub subscription_group_name: Cow<'a, str>,
     pub event_stream_id: Cow<'a, str>,
     pub resolve_link_tos: bool,
-    pub start_from: i32,
+    pub start_from: i64,
     pub message_timeout_milliseconds: i32,
     pub record_statistics: bool,
     pub live_buffer_size: i32,
@@ -1088,7 +1092,7 @@ impl<'a> CreatePersistentSubscription<'a> {
                 Ok(10) => msg.subscription_group_name = r.read_string(bytes).map(Cow::Borrowed)?,
                 Ok(18) => msg.event_stream_id = r.read_string(bytes).map(Cow::Borrowed)?,
                 Ok(24) => msg.resolve_link_tos = r.read_bool(bytes)?,
-                Ok(32) => msg.start_from = r.read_int32(bytes)?,
+                Ok(32) => msg.start_from = r.read_int64(bytes)?,
                 Ok(40) => msg.message_timeout_milliseconds = r.read_int32(bytes)?,
                 Ok(48) => msg.record_statistics = r.read_bool(bytes)?,
                 Ok(56) => msg.live_buffer_size = r.read_int32(bytes)?,
@@ -1134,7 +1138,7 @@ impl<'a> MessageWrite for CreatePersistentSubscription<'a> {
         w.write_with_tag(10, |w| w.write_string(&**&self.subscription_group_name))?;
         w.write_with_tag(18, |w| w.write_string(&**&self.event_stream_id))?;
         w.write_with_tag(24, |w| w.write_bool(*&self.resolve_link_tos))?;
-        w.write_with_tag(32, |w| w.write_int32(*&self.start_from))?;
+        w.write_with_tag(32, |w| w.write_int64(*&self.start_from))?;
         w.write_with_tag(40, |w| w.write_int32(*&self.message_timeout_milliseconds))?;
         w.write_with_tag(48, |w| w.write_bool(*&self.record_statistics))?;
         w.write_with_tag(56, |w| w.write_int32(*&self.live_buffer_size))?;
@@ -1191,7 +1195,7 @@ pub struct UpdatePersistentSubscription<'a> {
     pub subscription_group_name: Cow<'a, str>,
     pub event_stream_id: Cow<'a, str>,
     pub resolve_link_tos: bool,
-    pub start_from: i32,
+    pub start_from: i64,
     pub message_timeout_milliseconds: i32,
     pub record_statistics: bool,
     pub live_buffer_size: i32,
@@ -1214,7 +1218,7 @@ impl<'a> UpdatePersistentSubscription<'a> {
                 Ok(10) => msg.subscription_group_name = r.read_string(bytes).map(Cow::Borrowed)?,
                 Ok(18) => msg.event_stream_id = r.read_string(bytes).map(Cow::Borrowed)?,
                 Ok(24) => msg.resolve_link_tos = r.read_bool(bytes)?,
-                Ok(32) => msg.start_from = r.read_int32(bytes)?,
+                Ok(32) => msg.start_from = r.read_int64(bytes)?,
                 Ok(40) => msg.message_timeout_milliseconds = r.read_int32(bytes)?,
                 Ok(48) => msg.record_statistics = r.read_bool(bytes)?,
                 Ok(56) => msg.live_buffer_size = r.read_int32(bytes)?,
@@ -1260,7 +1264,7 @@ impl<'a> MessageWrite for UpdatePersistentSubscription<'a> {
         w.write_with_tag(10, |w| w.write_string(&**&self.subscription_group_name))?;
         w.write_with_tag(18, |w| w.write_string(&**&self.event_stream_id))?;
         w.write_with_tag(24, |w| w.write_bool(*&self.resolve_link_tos))?;
-        w.write_with_tag(32, |w| w.write_int32(*&self.start_from))?;
+        w.write_with_tag(32, |w| w.write_int64(*&self.start_from))?;
         w.write_with_tag(40, |w| w.write_int32(*&self.message_timeout_milliseconds))?;
         w.write_with_tag(48, |w| w.write_bool(*&self.record_statistics))?;
         w.write_with_tag(56, |w| w.write_int32(*&self.live_buffer_size))?;
@@ -1641,7 +1645,7 @@ impl From<i32> for NakAction {
 pub struct PersistentSubscriptionConfirmation<'a> {
     pub last_commit_position: i64,
     pub subscription_id: Cow<'a, str>,
-    pub last_event_number: Option<i32>,
+    pub last_event_number: Option<i64>,
 }
 
 impl<'a> PersistentSubscriptionConfirmation<'a> {
@@ -1651,7 +1655,7 @@ impl<'a> PersistentSubscriptionConfirmation<'a> {
             match r.next_tag(bytes) {
                 Ok(8) => msg.last_commit_position = r.read_int64(bytes)?,
                 Ok(18) => msg.subscription_id = r.read_string(bytes).map(Cow::Borrowed)?,
-                Ok(24) => msg.last_event_number = Some(r.read_int32(bytes)?),
+                Ok(24) => msg.last_event_number = Some(r.read_int64(bytes)?),
                 Ok(t) => { r.read_unknown(bytes, t)?; }
                 Err(e) => return Err(e),
             }
@@ -1671,7 +1675,7 @@ impl<'a> MessageWrite for PersistentSubscriptionConfirmation<'a> {
     fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
         w.write_with_tag(8, |w| w.write_int64(*&self.last_commit_position))?;
         w.write_with_tag(18, |w| w.write_string(&**&self.subscription_id))?;
-        if let Some(ref s) = self.last_event_number { w.write_with_tag(24, |w| w.write_int32(*s))?; }
+        if let Some(ref s) = self.last_event_number { w.write_with_tag(24, |w| w.write_int64(*s))?; }
         Ok(())
     }
 }
@@ -1745,7 +1749,7 @@ impl<'a> MessageWrite for SubscribeToStream<'a> {
 #[derive(IntoOwned, Borrowed, Debug, Default, PartialEq, Clone)]
 pub struct SubscriptionConfirmation {
     pub last_commit_position: i64,
-    pub last_event_number: Option<i32>,
+    pub last_event_number: Option<i64>,
 }
 
 impl SubscriptionConfirmation {
@@ -1754,7 +1758,7 @@ impl SubscriptionConfirmation {
         while !r.is_eof() {
             match r.next_tag(bytes) {
                 Ok(8) => msg.last_commit_position = r.read_int64(bytes)?,
-                Ok(16) => msg.last_event_number = Some(r.read_int32(bytes)?),
+                Ok(16) => msg.last_event_number = Some(r.read_int64(bytes)?),
                 Ok(t) => { r.read_unknown(bytes, t)?; }
                 Err(e) => return Err(e),
             }
@@ -1772,7 +1776,7 @@ impl MessageWrite for SubscriptionConfirmation {
 
     fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
         w.write_with_tag(8, |w| w.write_int64(*&self.last_commit_position))?;
-        if let Some(ref s) = self.last_event_number { w.write_with_tag(16, |w| w.write_int32(*s))?; }
+        if let Some(ref s) = self.last_event_number { w.write_with_tag(16, |w| w.write_int64(*s))?; }
         Ok(())
     }
 }
@@ -2087,3 +2091,50 @@ impl From<i32> for ScavengeResult {
 }
 
 }
+
+#[derive(IntoOwned, Borrowed, Debug, Default, PartialEq, Clone)]
+pub struct IdentifyClient<'a> {
+    pub version: i32,
+    pub connection_name: Option<Cow<'a, str>>,
+}
+
+impl<'a> IdentifyClient<'a> {
+    pub fn from_reader(r: &mut BytesReader, bytes: &'a [u8]) -> Result<Self> {
+        let mut msg = Self::default();
+        while !r.is_eof() {
+            match r.next_tag(bytes) {
+                Ok(8) => msg.version = r.read_int32(bytes)?,
+                Ok(18) => msg.connection_name = Some(r.read_string(bytes).map(Cow::Borrowed)?),
+                Ok(t) => { r.read_unknown(bytes, t)?; }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(msg)
+    }
+}
+
+impl<'a> MessageWrite for IdentifyClient<'a> {
+    fn get_size(&self) -> usize {
+        0
+        + 1 + sizeof_varint(*(&self.version) as u64)
+        + self.connection_name.as_ref().map_or(0, |m| 1 + sizeof_len((m).len()))
+    }
+
+    fn write_message<W: Write>(&self, w: &mut Writer<W>) -> Result<()> {
+        w.write_with_tag(8, |w| w.write_int32(*&self.version))?;
+        if let Some(ref s) = self.connection_name { w.write_with_tag(18, |w| w.write_string(&**s))?; }
+        Ok(())
+    }
+}
+
+#[derive(IntoOwned, Borrowed, Debug, Default, PartialEq, Clone)]
+pub struct ClientIdentified { }
+
+impl ClientIdentified {
+    pub fn from_reader(r: &mut BytesReader, _: &[u8]) -> Result<Self> {
+        r.read_to_end();
+        Ok(Self::default())
+    }
+}
+
+impl MessageWrite for ClientIdentified { }
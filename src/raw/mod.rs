@@ -9,7 +9,7 @@ use std::convert::TryFrom;
 use quick_protobuf;
 
 pub mod client_messages;
-pub use self::client_messages::{EventRecord, WriteEvents, WriteEventsCompleted, ReadEvent, ReadEventCompleted, ReadStreamEvents, ReadStreamEventsCompleted, ReadAllEvents, ReadAllEventsCompleted, NotHandled, DeleteStream, DeleteStreamCompleted, OperationResult};
+pub use self::client_messages::{EventRecord, WriteEvents, WriteEventsCompleted, ReadEvent, ReadEventCompleted, ReadStreamEvents, ReadStreamEventsCompleted, ReadAllEvents, ReadAllEventsCompleted, NotHandled, DeleteStream, DeleteStreamCompleted, OperationResult, IdentifyClient, SubscribeToStream, SubscriptionConfirmation, StreamEventAppeared, UnsubscribeFromStream, SubscriptionDropped};
 
 use adapted;
 
@@ -56,6 +56,17 @@ pub enum RawMessage<'a> {
     /// Response to a read all in given direction
     ReadAllEventsCompleted(ReadDirection, ReadAllEventsCompleted<'a>),
 
+    /// Request to start a volatile subscription to a stream, or to `$all` with an empty stream id
+    SubscribeToStream(SubscribeToStream<'a>),
+    /// Positive response to `SubscribeToStream`
+    SubscriptionConfirmation(SubscriptionConfirmation),
+    /// An event written to the subscribed stream, sent with the correlation id of the subscription
+    StreamEventAppeared(StreamEventAppeared<'a>),
+    /// Request to stop the subscription with the same correlation id
+    UnsubscribeFromStream(UnsubscribeFromStream),
+    /// The subscription has ended, either because of unsubscribing or a failure
+    SubscriptionDropped(SubscriptionDropped),
+
     /// Request was not understood. Please open an issue!
     BadRequest(BadRequestPayload<'a>),
 
@@ -74,6 +85,13 @@ pub enum RawMessage<'a> {
     /// authentication was not accepted. May contain a reason.
     NotAuthenticated(NotAuthenticatedPayload<'a>),
 
+    /// Request to identify the client with a protocol version and a connection name, supported
+    /// by EventStore 4 and newer. Older servers respond with `BadRequest`.
+    IdentifyClient(IdentifyClient<'a>),
+
+    /// Positive response to `IdentifyClient`.
+    ClientIdentified,
+
     /// Placeholder for a discriminator and the undecoded bytes
     Unsupported(u8, Cow<'a, [u8]>),
 }
@@ -203,11 +221,19 @@ impl<'a> RawMessage<'a> {
             0xB8 => decoded!(ReadAllEvents, buf, RawMessage::ReadAllEvents, Backward),
             0xB9 => decoded!(ReadAllEventsCompleted, buf, RawMessage::ReadAllEventsCompleted, Backward),
 
+            0xC0 => decoded!(SubscribeToStream, buf, RawMessage::SubscribeToStream),
+            0xC1 => decoded!(SubscriptionConfirmation, buf, RawMessage::SubscriptionConfirmation),
+            0xC2 => decoded!(StreamEventAppeared, buf, RawMessage::StreamEventAppeared),
+            0xC3 => decoded!(UnsubscribeFromStream, buf, RawMessage::UnsubscribeFromStream),
+            0xC4 => decoded!(SubscriptionDropped, buf, RawMessage::SubscriptionDropped),
+
             0xF0 => Ok(RawMessage::BadRequest(Cow::Borrowed(buf).into())),
             0xF1 => decoded!(NotHandled, buf, RawMessage::NotHandled),
             0xF2 => without_data!(RawMessage::Authenticate, buf),
             0xF3 => without_data!(RawMessage::Authenticated, buf),
             0xF4 => Ok(RawMessage::NotAuthenticated(Cow::Borrowed(buf).into())),
+            0xF5 => decoded!(IdentifyClient, buf, RawMessage::IdentifyClient),
+            0xF6 => without_data!(RawMessage::ClientIdentified, buf),
             x => Ok((x, Cow::Borrowed(buf)).into()),
         }
     }
@@ -234,7 +260,8 @@ impl<'a> RawMessage<'a> {
             Ping |
             Pong |
             Authenticate |
-            Authenticated => Ok(()),
+            Authenticated |
+            ClientIdentified => Ok(()),
 
             WriteEvents(ref x) => encode!(x, w),
             WriteEventsCompleted(ref x) => encode!(x, w),
@@ -251,9 +278,16 @@ impl<'a> RawMessage<'a> {
             ReadAllEvents(_, ref x) => encode!(x, w),
             ReadAllEventsCompleted(_, ref x) => encode!(x, w),
 
+            SubscribeToStream(ref x) => encode!(x, w),
+            SubscriptionConfirmation(ref x) => encode!(x, w),
+            StreamEventAppeared(ref x) => encode!(x, w),
+            UnsubscribeFromStream(ref x) => encode!(x, w),
+            SubscriptionDropped(ref x) => encode!(x, w),
+
             BadRequest(ref x) => w.write_all(x.as_ref()),
             NotHandled(ref x) => encode!(x, w),
             NotAuthenticated(ref x) => w.write_all(x.as_ref()),
+            IdentifyClient(ref x) => encode!(x, w),
             Unsupported(_, ref x) => w.write_all(x),
         }
     }
@@ -289,11 +323,19 @@ impl<'a> RawMessage<'a> {
             ReadAllEvents(ReadDirection::Backward, _) => 0xB8,
             ReadAllEventsCompleted(ReadDirection::Backward, _) => 0xB9,
 
+            SubscribeToStream(_) => 0xC0,
+            SubscriptionConfirmation(_) => 0xC1,
+            StreamEventAppeared(_) => 0xC2,
+            UnsubscribeFromStream(_) => 0xC3,
+            SubscriptionDropped(_) => 0xC4,
+
             BadRequest(_) => 0xf0,
             NotHandled(_) => 0xf1,
             Authenticate => 0xf2,
             Authenticated => 0xf3,
             NotAuthenticated(_) => 0xf4,
+            IdentifyClient(_) => 0xf5,
+            ClientIdentified => 0xf6,
             Unsupported(d, _) => d,
         }
     }
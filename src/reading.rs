@@ -0,0 +1,206 @@
+//! Reading whole streams page by page with `ReadStreamEvents`.
+//!
+//! `read_stream_pages` and `read_stream_events` work with any service (see
+//! [Services](../index.html#services)) and read forwards from the given event number until the
+//! end of the stream at the time of reading. The `_upcasted` variants apply an
+//! `upcast::Upcasters` chain to every page.
+
+use std::io;
+use std::fmt;
+use std::error;
+use std::rc::Rc;
+
+use futures::{future, stream, Future, Stream};
+use tokio_service::Service;
+
+use adapted::{AdaptedMessage, ReadStreamCompleted, ReadStreamError, ResolvedEvent};
+use builder::Builder;
+use package::Package;
+use raw::RawMessage;
+use upcast::{UpcastError, Upcasters};
+use {EventNumber, ReadDirection, UsernamePassword};
+
+/// Failures of reading a stream.
+#[derive(Debug)]
+pub enum ReadError {
+    /// The request could not be sent or the response received.
+    Io(io::Error),
+    /// The server failed the read, for example with `NoStream`.
+    Read(ReadStreamError<'static>),
+    /// The server responded with an unexpected message, such as `NotAuthenticated`.
+    UnexpectedResponse(RawMessage<'static>),
+    /// Upcasting a read event failed.
+    Upcast(UpcastError),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Io(ref e) => write!(f, "{}", e),
+            ReadError::Read(ref e) => match *e {
+                ReadStreamError::NoStream => write!(f, "Stream does not exist"),
+                ReadStreamError::StreamDeleted => write!(f, "Stream has been deleted"),
+                ReadStreamError::NotModified => write!(f, "Stream was not modified"),
+                ReadStreamError::Error(Some(ref msg)) => write!(f, "Reading stream failed: {}", msg),
+                ReadStreamError::Error(None) => write!(f, "Reading stream failed"),
+                ReadStreamError::AccessDenied => write!(f, "Access to stream was denied"),
+            },
+            ReadError::UnexpectedResponse(ref msg) => write!(f, "Unexpected response: {:?}", msg),
+            ReadError::Upcast(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for ReadError {
+    fn description(&self) -> &str {
+        match *self {
+            ReadError::Io(ref e) => e.description(),
+            ReadError::Read(_) => "reading stream failed",
+            ReadError::UnexpectedResponse(_) => "unexpected response",
+            ReadError::Upcast(ref e) => e.description(),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+impl From<UpcastError> for ReadError {
+    fn from(e: UpcastError) -> Self {
+        ReadError::Upcast(e)
+    }
+}
+
+/// Reads a single page of at most `page_size` events forwards from the given event number. A
+/// `page_size` of zero fails with `io::ErrorKind::InvalidInput` as no page could ever advance.
+pub fn read_stream_page<S>(client: &S, stream: &str, from: EventNumber, page_size: u8, credentials: Option<UsernamePassword>) -> Box<dyn Future<Item = ReadStreamCompleted, Error = ReadError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static
+{
+    if page_size == 0 {
+        return Box::new(future::err(ReadError::Io(io::Error::new(io::ErrorKind::InvalidInput, "page size cannot be zero"))));
+    }
+
+    let req = Builder::read_stream_events()
+        .direction(ReadDirection::Forward)
+        .stream_id(stream.to_owned())
+        .from_event_number(from)
+        .max_count(page_size)
+        .build_package(credentials, None);
+
+    Box::new(client.call(req).from_err().and_then(|resp| -> Result<ReadStreamCompleted, ReadError> {
+        match resp.message.try_adapt() {
+            Ok(AdaptedMessage::ReadStreamEventsCompleted(_, Ok(completed))) => Ok(completed),
+            Ok(AdaptedMessage::ReadStreamEventsCompleted(_, Err(e))) => Err(ReadError::Read(e.into_owned())),
+            Ok(other) => Err(ReadError::UnexpectedResponse(other.as_raw().into_owned())),
+            Err((raw, _)) => Err(ReadError::UnexpectedResponse(raw)),
+        }
+    }))
+}
+
+/// Reads the stream forwards from the given event number, yielding every page including the
+/// last one which reached the end of the stream. A missing stream fails with
+/// `ReadError::Read(ReadStreamError::NoStream)`.
+pub fn read_stream_pages<S>(client: &S, stream: &str, from: EventNumber, page_size: u8, credentials: Option<UsernamePassword>) -> Box<dyn Stream<Item = ReadStreamCompleted, Error = ReadError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error> + Clone + 'static,
+          S::Future: 'static
+{
+    let client = client.clone();
+    let stream = stream.to_owned();
+
+    Box::new(stream::unfold(Some(from), move |next| {
+        next.map(|from| {
+            read_stream_page(&client, &stream, from, page_size, credentials.clone()).map(|page| {
+                let next = if page.end_of_stream { None } else { page.next_page };
+                (page, next)
+            })
+        })
+    }))
+}
+
+/// Reads the events of the stream forwards from the given event number, see
+/// `read_stream_pages`.
+pub fn read_stream_events<S>(client: &S, stream: &str, from: EventNumber, page_size: u8, credentials: Option<UsernamePassword>) -> Box<dyn Stream<Item = ResolvedEvent, Error = ReadError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error> + Clone + 'static,
+          S::Future: 'static
+{
+    Box::new(read_stream_pages(client, stream, from, page_size, credentials)
+        .map(|page| stream::iter_ok(page.events))
+        .flatten())
+}
+
+/// Reads the stream like `read_stream_pages`, upcasting the events of every page.
+pub fn read_stream_pages_upcasted<S>(client: &S, stream: &str, from: EventNumber, page_size: u8, credentials: Option<UsernamePassword>, upcasters: Rc<Upcasters>) -> Box<dyn Stream<Item = ReadStreamCompleted, Error = ReadError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error> + Clone + 'static,
+          S::Future: 'static
+{
+    Box::new(read_stream_pages(client, stream, from, page_size, credentials)
+        .and_then(move |page| upcasters.upcast_page(page).map_err(ReadError::from)))
+}
+
+/// Reads the events of the stream like `read_stream_events`, upcasting every event.
+pub fn read_stream_events_upcasted<S>(client: &S, stream: &str, from: EventNumber, page_size: u8, credentials: Option<UsernamePassword>, upcasters: Rc<Upcasters>) -> Box<dyn Stream<Item = ResolvedEvent, Error = ReadError>>
+    where S: Service<Request = Package, Response = Package, Error = io::Error> + Clone + 'static,
+          S::Future: 'static
+{
+    Box::new(read_stream_pages_upcasted(client, stream, from, page_size, credentials, upcasters)
+        .map(|page| stream::iter_ok(page.events))
+        .flatten())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::rc::Rc;
+
+    use futures::Stream;
+    use tokio_core::reactor::Core;
+    use tokio_service::Service;
+
+    use super::{read_stream_events, read_stream_events_upcasted, ReadError};
+    use adapted::ReadStreamError;
+    use builder::Builder;
+    use fixtures::start;
+    use memory::MemoryStore;
+    use upcast::Upcasters;
+    use {EventNumber, ExpectedVersion};
+
+    #[test]
+    fn reads_all_pages() {
+        let mut core = Core::new().unwrap();
+        let client = start(&core, MemoryStore::new());
+
+        let mut builder = Builder::write_events();
+        builder.stream_id("foo").expected_version(ExpectedVersion::NoStream);
+        for _ in 0..7 {
+            builder.new_event().event_type("bar").data(&b"{}"[..]).done();
+        }
+        core.run(client.call(builder.build_package(None, None))).unwrap();
+
+        let events = core.run(read_stream_events(&client, "foo", EventNumber::First, 3, None).collect()).unwrap();
+        let numbers = events.iter().map(|e| u64::from(e.event.number)).collect::<Vec<_>>();
+        assert_eq!(numbers, vec![0, 1, 2, 3, 4, 5, 6]);
+
+        match core.run(read_stream_events(&client, "bar", EventNumber::First, 3, None).collect()) {
+            Err(ReadError::Read(ReadStreamError::NoStream)) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+
+        match core.run(read_stream_events(&client, "foo", EventNumber::First, 0, None).collect()) {
+            Err(ReadError::Io(ref e)) if e.kind() == io::ErrorKind::InvalidInput => {},
+            x => panic!("unexpected {:?}", x),
+        }
+
+        let upcasters = Rc::new(Upcasters::new().register("bar", |mut record| {
+            record.event_type = "baz".into();
+            Ok(vec![record.clone(), record])
+        }));
+        let upcasted = core.run(read_stream_events_upcasted(&client, "foo", EventNumber::First, 3, None, upcasters).collect()).unwrap();
+        assert_eq!(upcasted.len(), 14);
+        assert!(upcasted.iter().all(|e| e.event.event_type == "baz"));
+        assert_eq!(u64::from(upcasted[13].event.number), 6);
+    }
+}
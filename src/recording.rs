@@ -0,0 +1,460 @@
+//! Recording sessions against a real server and replaying them in tests.
+//!
+//! `Recorder` wraps a `Service` such as `EventStoreClient` and writes every request and its
+//! response or error to a writer, along with the time of the request and the latency of the
+//! response. `Replay` serves a recording back as a `Service`: requests are matched to the
+//! recorded ones ignoring the correlation ids and the event ids of `WriteEvents`, which are
+//! usually random, and answered with the recorded response carrying the correlation id of the
+//! new request.
+//!
+//! Recordings contain the credentials of the authenticated requests as they were sent.
+//!
+//! # Format
+//!
+//! A recording starts with `MAGIC`, followed by the exchanges in the order the responses
+//! completed. Each exchange is:
+//!
+//!  * the time since the recording started and the latency of the response, as little-endian
+//!    `u64` microseconds
+//!  * the request frame, exactly as `PackageCodec` writes it on the wire
+//!  * `0` followed by the response frame, or `1` followed by the kind of the error as a `u8` (see
+//!    `ERROR_KINDS`), a little-endian `u32` length and the UTF-8 message of the error
+//!
+//! Frames and error messages longer than `codec::MAX_FRAME_LEN` are rejected, as is a recording
+//! ending in the middle of an exchange.
+
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt, LittleEndian};
+use bytes::BytesMut;
+use futures::{future, Future};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::codec::{Decoder, Encoder};
+use tokio_service::Service;
+
+use codec::{PackageCodec, MAX_FRAME_LEN};
+use package::Package;
+use raw::RawMessage;
+
+/// The bytes every recording starts with, including the version of the format.
+pub const MAGIC: &'static [u8] = b"ESTCPREC\x01";
+
+/// The kinds of errors kept in recordings, written as the index in this list. Other kinds are
+/// recorded as `io::ErrorKind::Other`.
+pub const ERROR_KINDS: &'static [io::ErrorKind] = &[
+    io::ErrorKind::Other,
+    io::ErrorKind::NotFound,
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::ConnectionRefused,
+    io::ErrorKind::ConnectionReset,
+    io::ErrorKind::ConnectionAborted,
+    io::ErrorKind::NotConnected,
+    io::ErrorKind::AddrInUse,
+    io::ErrorKind::AddrNotAvailable,
+    io::ErrorKind::BrokenPipe,
+    io::ErrorKind::AlreadyExists,
+    io::ErrorKind::WouldBlock,
+    io::ErrorKind::InvalidInput,
+    io::ErrorKind::InvalidData,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::WriteZero,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::UnexpectedEof,
+];
+
+/// A recorded request with the outcome.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exchange {
+    /// Time of the request since the start of the recording
+    pub at: Duration,
+    /// Time it took for the response to complete
+    pub latency: Duration,
+    /// The request as it was sent
+    pub request: Package,
+    /// The response, or the kind and the message of the error
+    pub response: Result<Package, (io::ErrorKind, String)>,
+}
+
+fn micros(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000 + (d.subsec_nanos() / 1_000) as u64
+}
+
+fn from_micros(us: u64) -> Duration {
+    Duration::new(us / 1_000_000, (us % 1_000_000) as u32 * 1_000)
+}
+
+fn write_package<W: Write>(w: &mut W, package: &Package) -> io::Result<()> {
+    let mut buf = BytesMut::new();
+    PackageCodec.encode(package.clone(), &mut buf)?;
+    w.write_all(&buf)
+}
+
+/// Reads a length prefix, failing on lengths over `MAX_FRAME_LEN`.
+fn read_len<R: Read>(r: &mut R) -> io::Result<usize> {
+    let len = r.read_u32::<LittleEndian>()? as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("length is too large: {}", len)));
+    }
+    Ok(len)
+}
+
+/// Reads a single frame written by `PackageCodec`.
+fn read_package<R: Read>(r: &mut R) -> io::Result<Package> {
+    let len = read_len(r)?;
+    let mut frame = vec![0u8; 4 + len];
+    (&mut frame[..4]).write_u32::<LittleEndian>(len as u32)?;
+    r.read_exact(&mut frame[4..])?;
+
+    let mut buf = BytesMut::from(frame);
+    match PackageCodec.decode(&mut buf)? {
+        Some(package) => Ok(package),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "truncated frame")),
+    }
+}
+
+/// Fills the buffer unless the input ends first, returning the number of bytes read.
+fn read_until_eof<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+impl Exchange {
+    /// Writes the exchange in the recording format.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u64::<LittleEndian>(micros(self.at))?;
+        w.write_u64::<LittleEndian>(micros(self.latency))?;
+        write_package(w, &self.request)?;
+
+        match self.response {
+            Ok(ref package) => {
+                w.write_u8(0)?;
+                write_package(w, package)
+            },
+            Err((kind, ref msg)) => {
+                let kind = ERROR_KINDS.iter().position(|k| *k == kind).unwrap_or(0);
+                w.write_u8(1)?;
+                w.write_u8(kind as u8)?;
+                w.write_u32::<LittleEndian>(msg.len() as u32)?;
+                w.write_all(msg.as_bytes())
+            },
+        }
+    }
+
+    /// Reads an exchange written with `write_to`, `None` at the end of the input. An input ending
+    /// in the middle of an exchange fails with `io::ErrorKind::UnexpectedEof`.
+    pub fn read_from<R: Read>(r: &mut R) -> io::Result<Option<Exchange>> {
+        let mut at = [0u8; 8];
+        match read_until_eof(r, &mut at)? {
+            0 => return Ok(None),
+            8 => {},
+            _ => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated exchange")),
+        }
+        let at = LittleEndian::read_u64(&at);
+        let latency = r.read_u64::<LittleEndian>()?;
+        let request = read_package(r)?;
+
+        let response = match r.read_u8()? {
+            0 => Ok(read_package(r)?),
+            1 => {
+                let kind = ERROR_KINDS.get(r.read_u8()? as usize).cloned().unwrap_or(io::ErrorKind::Other);
+                let len = read_len(r)?;
+                let mut msg = vec![0u8; len];
+                r.read_exact(&mut msg)?;
+                Err((kind, String::from_utf8(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?))
+            },
+            x => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid outcome: {}", x))),
+        };
+
+        Ok(Some(Exchange {
+            at: from_micros(at),
+            latency: from_micros(latency),
+            request: request,
+            response: response,
+        }))
+    }
+}
+
+/// `Service` recording the requests and responses of the wrapped service, see the module
+/// documentation.
+pub struct Recorder<S, W> {
+    inner: S,
+    writer: Rc<RefCell<W>>,
+    started: Instant,
+}
+
+impl<S> Recorder<S, io::BufWriter<File>> {
+    /// Records to a new file at the path, replacing any existing file.
+    pub fn create<P: AsRef<Path>>(inner: S, path: P) -> io::Result<Self> {
+        Recorder::new(inner, io::BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<S, W: Write> Recorder<S, W> {
+    /// Starts the recording by writing `MAGIC` to the writer.
+    pub fn new(inner: S, mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        Ok(Recorder {
+            inner: inner,
+            writer: Rc::new(RefCell::new(writer)),
+            started: Instant::now(),
+        })
+    }
+
+    /// Flushes the writer, which should be done before the recording is dropped.
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.borrow_mut().flush()
+    }
+}
+
+impl<S, W> Service for Recorder<S, W>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static,
+          W: Write + 'static,
+{
+    type Request = Package;
+    type Response = Package;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = Package, Error = io::Error>>;
+
+    /// Calls the wrapped service. Failing to write the exchange fails the request.
+    fn call(&self, req: Package) -> Self::Future {
+        let at = self.started.elapsed();
+        let sent = Instant::now();
+        let writer = self.writer.clone();
+        let request = req.clone();
+
+        Box::new(self.inner.call(req).then(move |res| {
+            let exchange = Exchange {
+                at: at,
+                latency: sent.elapsed(),
+                request: request,
+                response: match res {
+                    Ok(ref package) => Ok(package.clone()),
+                    Err(ref e) => Err((e.kind(), e.to_string())),
+                },
+            };
+
+            exchange.write_to(&mut *writer.borrow_mut())?;
+            res
+        }))
+    }
+}
+
+/// The request with the parts which differ between sessions cleared.
+fn normalized(req: &Package) -> (Option<::UsernamePassword>, RawMessage<'static>) {
+    let mut message = req.message.clone();
+
+    if let RawMessage::WriteEvents(ref mut w) = message {
+        for event in &mut w.events {
+            event.event_id = Cow::Borrowed(&[]);
+        }
+    }
+
+    (req.authentication.clone(), message)
+}
+
+struct Recorded {
+    key: (Option<::UsernamePassword>, RawMessage<'static>),
+    exchange: Exchange,
+    replayed: bool,
+}
+
+/// `Service` answering with the responses of a recording, see the module documentation.
+pub struct Replay {
+    recorded: RefCell<Vec<Recorded>>,
+    latency: Option<Handle>,
+}
+
+impl Replay {
+    /// Replays the given exchanges.
+    pub fn new(exchanges: Vec<Exchange>) -> Self {
+        let recorded = exchanges.into_iter()
+            .map(|x| Recorded { key: normalized(&x.request), exchange: x, replayed: false })
+            .collect();
+
+        Replay {
+            recorded: RefCell::new(recorded),
+            latency: None,
+        }
+    }
+
+    /// Reads a recording written by `Recorder`.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut magic = vec![0u8; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a recording"));
+        }
+
+        let mut exchanges = Vec::new();
+        while let Some(exchange) = Exchange::read_from(&mut reader)? {
+            exchanges.push(exchange);
+        }
+
+        Ok(Replay::new(exchanges))
+    }
+
+    /// Reads the recording from the file at the path.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Replay::from_reader(io::BufReader::new(File::open(path)?))
+    }
+
+    /// Delays the responses by the recorded latencies, using timeouts on the given reactor. By
+    /// default the responses are immediate.
+    pub fn with_latency(mut self, handle: &Handle) -> Self {
+        self.latency = Some(handle.clone());
+        self
+    }
+
+    /// The number of recorded exchanges which have not yet been replayed.
+    pub fn remaining(&self) -> usize {
+        self.recorded.borrow().iter().filter(|x| !x.replayed).count()
+    }
+}
+
+impl Service for Replay {
+    type Request = Package;
+    type Response = Package;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = Package, Error = io::Error>>;
+
+    /// Answers with the first recorded exchange matching the request which has not been
+    /// replayed yet. Requests not in the recording fail.
+    fn call(&self, req: Package) -> Self::Future {
+        let key = normalized(&req);
+        let mut recorded = self.recorded.borrow_mut();
+
+        let exchange = match recorded.iter_mut().find(|x| !x.replayed && x.key == key) {
+            Some(x) => {
+                x.replayed = true;
+                &x.exchange
+            },
+            None => {
+                let msg = format!("request not in the recording: {:?}", req.message);
+                return Box::new(future::err(io::Error::new(io::ErrorKind::Other, msg)));
+            }
+        };
+
+        let result = match exchange.response {
+            Ok(ref package) => Ok(Package { correlation_id: req.correlation_id, ..package.clone() }),
+            Err((kind, ref msg)) => Err(io::Error::new(kind, msg.clone())),
+        };
+
+        match self.latency {
+            Some(ref handle) => match Timeout::new(exchange.latency, handle) {
+                Ok(timeout) => Box::new(timeout.and_then(move |_| result)),
+                Err(e) => Box::new(future::err(e)),
+            },
+            None => Box::new(future::result(result)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    use futures::Future;
+    use tokio_service::Service;
+
+    use byteorder::{WriteBytesExt, LittleEndian};
+
+    use super::{Recorder, Replay, MAGIC};
+    use adapted::{AdaptedMessage, ReadEventError};
+    use builder::Builder;
+    use fixtures::write;
+    use mock::{MockService, Kind};
+    use package::Package;
+    use {EventNumber, UsernamePassword};
+
+    /// Writer which can be read while the recorder is alive.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The recorded requests include the credentials.
+    fn authenticated(req: Package) -> Package {
+        Package { authentication: Some(UsernamePassword::new("admin", "changeit")), ..req }
+    }
+
+    fn read() -> Package {
+        Builder::read_event()
+            .stream_id("foo")
+            .event_number(EventNumber::First)
+            .build_package(None, None)
+    }
+
+    #[test]
+    fn replays_ignoring_ids() {
+        let mock = MockService::new();
+        mock.on(Kind::WriteEvents).fail(io::ErrorKind::BrokenPipe, "connection lost");
+        mock.on(Kind::ReadEvent).reply(AdaptedMessage::ReadEventCompleted(Err(ReadEventError::NotFound)));
+
+        let buf = Shared::default();
+        let recorder = Recorder::new(mock, buf.clone()).unwrap();
+        recorder.call(authenticated(write())).wait().unwrap_err();
+        recorder.call(read()).wait().unwrap();
+        recorder.flush().unwrap();
+
+        let replay = Replay::from_reader(&buf.0.borrow()[..]).unwrap();
+        assert_eq!(replay.remaining(), 2);
+
+        let req = read();
+        let resp = replay.call(req.clone()).wait().unwrap();
+        assert_eq!(resp.correlation_id, req.correlation_id);
+        assert_eq!(resp.message.try_adapt().unwrap(), AdaptedMessage::ReadEventCompleted(Err(ReadEventError::NotFound)));
+
+        // new event and correlation ids still match the recorded write
+        let err = replay.call(authenticated(write())).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(err.to_string(), "connection lost");
+
+        assert_eq!(replay.remaining(), 0);
+        replay.call(read()).wait().unwrap_err();
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_recordings() {
+        let buf = Shared::default();
+        let recorder = Recorder::new(MockService::new(), buf.clone()).unwrap();
+        recorder.call(read()).wait().unwrap_err();
+
+        let recording = buf.0.borrow().clone();
+        assert!(Replay::from_reader(&recording[..]).is_ok());
+
+        for len in MAGIC.len() + 1..recording.len() {
+            let err = Replay::from_reader(&recording[..len]).err().expect("truncated recording was read");
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+
+        // the length of the request frame follows the two timestamps
+        let mut oversized = recording.clone();
+        (&mut oversized[MAGIC.len() + 16..]).write_u32::<LittleEndian>(u32::max_value()).unwrap();
+        assert_eq!(Replay::from_reader(&oversized[..]).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
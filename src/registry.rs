@@ -0,0 +1,232 @@
+//! Mapping event types to Rust types, enabled with the `serialization` feature.
+//!
+//! A `Registry` is built for an enum of the events of a stream, registering a variant for every
+//! event type. It then decodes read events into the enum based on their `event_type`, and
+//! appends values of the enum as new events with the event type and codec of their variant.
+//! Events of unregistered types can be decoded into a fallback variant.
+//!
+//! # Example
+//!
+//! ```rust
+//! extern crate eventstore_tcp;
+//!
+//! use eventstore_tcp::{Builder, ExpectedVersion};
+//! use eventstore_tcp::registry::Registry;
+//!
+//! #[derive(Debug, PartialEq)]
+//! enum OrderEvent {
+//!     Placed(u32),
+//!     Shipped(String),
+//!     Unknown(String),
+//! }
+//!
+//! fn main() {
+//!     let registry = Registry::new()
+//!         .register("OrderPlaced", OrderEvent::Placed, |e| match *e { OrderEvent::Placed(ref x) => Some(x), _ => None })
+//!         .register("OrderShipped", OrderEvent::Shipped, |e| match *e { OrderEvent::Shipped(ref x) => Some(x), _ => None })
+//!         .fallback(|record| OrderEvent::Unknown(record.event_type.clone().into_owned()));
+//!
+//!     let mut builder = Builder::write_events();
+//!     builder.stream_id("orders-1").expected_version(ExpectedVersion::NoStream);
+//!     registry.append(&OrderEvent::Placed(42), &mut builder).unwrap();
+//!     registry.append(&OrderEvent::Shipped("today".into()), &mut builder).unwrap();
+//! }
+//! ```
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use adapted::RecordedEvent;
+use builder::WriteEventsBuilder;
+use raw::client_messages::{EventRecord, ResolvedIndexedEvent};
+use serialization::{Codec, CodecError, DecodeError, EncodeError, Json};
+use ContentType;
+
+type Decoder<E> = Box<dyn Fn(&EventRecord) -> Result<E, DecodeError>>;
+type Encoder<E> = Box<dyn Fn(&E) -> Option<Result<(ContentType, Vec<u8>), EncodeError>>>;
+
+struct Registration<E> {
+    event_type: &'static str,
+    decode: Decoder<E>,
+    encode: Encoder<E>,
+}
+
+/// Decodes events into and encodes events from the enum `E`, see the module documentation.
+pub struct Registry<E> {
+    registrations: Vec<Registration<E>>,
+    fallback: Option<Box<dyn Fn(&EventRecord) -> E>>,
+}
+
+impl<E: 'static> Default for Registry<E> {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+impl<E: 'static> Registry<E> {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Registry {
+            registrations: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    /// Registers a variant for the event type using JSON, see `register_with`.
+    pub fn register<T, W, U>(self, event_type: &'static str, wrap: W, unwrap: U) -> Self
+        where T: Serialize + DeserializeOwned + 'static,
+              W: Fn(T) -> E + 'static,
+              U: Fn(&E) -> Option<&T> + 'static,
+    {
+        self.register_with(Json, event_type, wrap, unwrap)
+    }
+
+    /// Registers a variant for the event type. Events of the type are decoded with the codec into
+    /// `T` and wrapped into the variant with `wrap`, usually the constructor of the variant.
+    /// Values for which `unwrap` returns the contents are encoded as events of the type.
+    pub fn register_with<C, T, W, U>(mut self, codec: C, event_type: &'static str, wrap: W, unwrap: U) -> Self
+        where C: Codec + Clone + 'static,
+              T: Serialize + DeserializeOwned + 'static,
+              W: Fn(T) -> E + 'static,
+              U: Fn(&E) -> Option<&T> + 'static,
+    {
+        let decoding = codec.clone();
+        let decode = move |record: &EventRecord| record.decode_data_with(&decoding).map(&wrap);
+        let encode = move |value: &E| unwrap(value).map(|inner| {
+            codec.encode(inner)
+                .map(|bytes| (codec.content_type(), bytes))
+                .map_err(EncodeError::from)
+        });
+
+        self.registrations.push(Registration {
+            event_type: event_type,
+            decode: Box::new(decode),
+            encode: Box::new(encode),
+        });
+        self
+    }
+
+    /// Decodes events of unregistered types with the given function instead of failing.
+    pub fn fallback<F: Fn(&EventRecord) -> E + 'static>(mut self, f: F) -> Self {
+        self.fallback = Some(Box::new(f));
+        self
+    }
+
+    /// The registered event types.
+    pub fn event_types(&self) -> Vec<&'static str> {
+        self.registrations.iter().map(|r| r.event_type).collect()
+    }
+
+    /// Decodes the event as the variant registered for its event type.
+    pub fn decode_record(&self, record: &EventRecord) -> Result<E, DecodeError> {
+        match self.registrations.iter().find(|r| r.event_type == record.event_type) {
+            Some(registration) => (registration.decode)(record),
+            None => match self.fallback {
+                Some(ref fallback) => Ok(fallback(record)),
+                None => Err(DecodeError::unknown_event_type(record)),
+            },
+        }
+    }
+
+    /// Decodes the event, see `decode_record`.
+    pub fn decode(&self, event: &RecordedEvent) -> Result<E, DecodeError> {
+        self.decode_record(&event.as_raw())
+    }
+
+    /// Decodes the resolved event of a read, see `decode_record`.
+    pub fn decode_resolved(&self, event: &ResolvedIndexedEvent) -> Result<E, DecodeError> {
+        self.decode_record(&event.event)
+    }
+
+    /// Adds the value as a new event with the event type and the codec of its variant.
+    pub fn append(&self, value: &E, events: &mut WriteEventsBuilder) -> Result<(), EncodeError> {
+        for registration in &self.registrations {
+            if let Some(encoded) = (registration.encode)(value) {
+                let (content_type, bytes) = encoded?;
+                events.new_event()
+                    .event_type(registration.event_type)
+                    .data(bytes)
+                    .data_content_type(content_type)
+                    .done();
+                return Ok(());
+            }
+        }
+
+        Err(EncodeError::from(CodecError::from("no event type registered for the value")))
+    }
+
+    /// Decodes the events, failing on the first event which cannot be decoded.
+    pub fn decode_all<'a, I>(&self, events: I) -> Result<Vec<E>, DecodeError>
+        where I: IntoIterator<Item = &'a RecordedEvent>
+    {
+        events.into_iter().map(|e| self.decode(e)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::Registry;
+    use adapted::RecordedEvent;
+    use builder::Builder;
+    use fixtures::recorded_from_write;
+    use {ContentType, ExpectedVersion};
+
+    #[derive(Debug, PartialEq)]
+    enum OrderEvent {
+        Placed((String, u32)),
+        Shipped(String),
+        Unknown(String),
+    }
+
+    fn registry() -> Registry<OrderEvent> {
+        Registry::new()
+            .register("OrderPlaced", OrderEvent::Placed, |e| match *e { OrderEvent::Placed(ref x) => Some(x), _ => None })
+            .register("OrderShipped", OrderEvent::Shipped, |e| match *e { OrderEvent::Shipped(ref x) => Some(x), _ => None })
+    }
+
+    /// Appends the values and reads the events back as the server would return them.
+    fn round_trip(registry: &Registry<OrderEvent>, values: &[OrderEvent]) -> Vec<RecordedEvent> {
+        let mut builder = Builder::write_events();
+        builder.stream_id("orders-1").expected_version(ExpectedVersion::Any);
+        for value in values {
+            registry.append(value, &mut builder).unwrap();
+        }
+
+        recorded_from_write(&mut builder).iter()
+            .map(|record| RecordedEvent::try_from(record).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn encodes_and_decodes_variants() {
+        let registry = registry();
+        let values = vec![OrderEvent::Placed(("book".into(), 2)), OrderEvent::Shipped("today".into())];
+
+        let events = round_trip(&registry, &values);
+        assert_eq!(events[0].event_type, "OrderPlaced");
+        assert_eq!(events[1].event_type, "OrderShipped");
+        assert_eq!(events[0].data_content_type, ContentType::Json);
+
+        assert_eq!(registry.decode_all(&events).unwrap(), values);
+
+        // variants without a registration cannot be written
+        let mut builder = Builder::write_events();
+        assert!(registry.append(&OrderEvent::Unknown("x".into()), &mut builder).is_err());
+    }
+
+    #[test]
+    fn unknown_types_use_fallback() {
+        let events = round_trip(&registry(), &[OrderEvent::Shipped("today".into())]);
+        let strict = Registry::new()
+            .register("OrderPlaced", OrderEvent::Placed, |e| match *e { OrderEvent::Placed(ref x) => Some(x), _ => None });
+
+        let err = strict.decode(&events[0]).unwrap_err();
+        assert_eq!(err.event_type, "OrderShipped");
+        assert!(err.is_unknown_event_type());
+
+        let lenient = strict.fallback(|record| OrderEvent::Unknown(record.event_type.clone().into_owned()));
+        assert_eq!(lenient.decode(&events[0]).unwrap(), OrderEvent::Unknown("OrderShipped".into()));
+    }
+}
@@ -0,0 +1,331 @@
+//! Retrying requests which failed in a transient way.
+//!
+//! `Retrying` wraps a `Service<Request = Package, Response = Package>` such as
+//! `EventStoreClient` and resends the package after a delay when the attempt failed in a way
+//! which might be resolved by retrying, until the `RetryPolicy` allows no more attempts.
+//!
+//! Every retry is sent with a new correlation id, as a timed out attempt may still be waiting
+//! for its response in the connection and a late response must not be mistaken for the response
+//! to the retry. The response is returned with the correlation id of the original request. The
+//! message is resent as is, so the event ids of a `WriteEvents` stay the same and the server will
+//! not append the events twice if an earlier attempt did succeed after all.
+//!
+//! The following are considered transient, see `is_transient`:
+//!
+//!  * `WriteEventsCompleted` with `PrepareTimeout`, `CommitTimeout` or `ForwardTimeout`, see
+//!    `WriteEventsFailure::is_transient`
+//!  * `NotHandled` with `TooBusy` or `NotReady`
+//!  * `ReadStreamEventsCompleted` with `ReadStreamError::Error`
+//!  * errors of the connection, such as the connection being lost, refused or timing out
+
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{future, Future};
+use futures::future::Loop;
+use rand::{self, Rng};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_service::Service;
+use uuid::Uuid;
+
+use adapted::WriteEventsFailure;
+use package::Package;
+use raw::{OperationResult, RawMessage};
+use raw::client_messages::mod_NotHandled::NotHandledReason;
+use raw::client_messages::mod_ReadStreamEventsCompleted::ReadStreamResult;
+
+/// How many times and how often `Retrying` retries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts including the first one. Defaults to 5.
+    pub max_attempts: usize,
+    /// Delay before the first retry. Defaults to 100 milliseconds.
+    pub initial_delay: Duration,
+    /// Factor by which the delay grows after every retry. Defaults to 2.
+    pub multiplier: f64,
+    /// Upper bound for the delay. Defaults to 5 seconds.
+    pub max_delay: Duration,
+    /// Fraction of the delay, between 0 and 1, which is randomly subtracted from it to keep
+    /// clients failing at the same time from retrying at the same time. Defaults to 0.5.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(100),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(5),
+            jitter: 0.5,
+        }
+    }
+}
+
+fn as_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+fn from_secs(secs: f64) -> Duration {
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1e9).round() as u32)
+}
+
+impl RetryPolicy {
+    /// The delay before the given retry, starting from 1, with the jitter applied.
+    pub fn delay(&self, retry: usize) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::max_value() as usize) as i32;
+        let delay = (as_secs(self.initial_delay) * self.multiplier.powi(exponent))
+            .min(as_secs(self.max_delay));
+        let jitter = self.jitter.max(0.0).min(1.0) * rand::thread_rng().next_f64();
+        from_secs(delay * (1.0 - jitter))
+    }
+}
+
+/// Returns `true` if the outcome of a request is one of the transient failures listed in the
+/// module documentation.
+pub fn is_transient(result: &Result<Package, io::Error>) -> bool {
+    let resp = match *result {
+        Ok(ref resp) => resp,
+        Err(ref e) => return is_connection_error(e),
+    };
+
+    match resp.message {
+        RawMessage::WriteEventsCompleted(ref x) => match x.result {
+            Some(OperationResult::Success) | Some(OperationResult::InvalidTransaction) | None => false,
+            Some(res) => WriteEventsFailure::from(res).is_transient(),
+        },
+        RawMessage::NotHandled(ref x) => match x.reason {
+            Some(NotHandledReason::TooBusy) | Some(NotHandledReason::NotReady) => true,
+            _ => false,
+        },
+        RawMessage::ReadStreamEventsCompleted(_, ref x) => x.result == Some(ReadStreamResult::Error),
+        _ => false,
+    }
+}
+
+fn is_connection_error(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::BrokenPipe |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::ConnectionAborted |
+        io::ErrorKind::ConnectionRefused |
+        io::ErrorKind::NotConnected |
+        io::ErrorKind::UnexpectedEof |
+        io::ErrorKind::TimedOut => true,
+        _ => false,
+    }
+}
+
+/// An attempt of a request given to the observer of `Retrying`.
+#[derive(Debug)]
+pub struct Attempt<'a> {
+    /// The request being sent
+    pub request: &'a Package,
+    /// Number of the attempt, starting from 1
+    pub number: usize,
+    /// Outcome of the attempt
+    pub result: &'a Result<Package, io::Error>,
+    /// Delay before the next attempt, `None` if the outcome is final
+    pub retry_in: Option<Duration>,
+}
+
+type Observer = dyn Fn(&Attempt);
+
+/// `Service` retrying the requests of the wrapped service, see the module documentation.
+pub struct Retrying<S> {
+    inner: Rc<S>,
+    policy: RetryPolicy,
+    handle: Handle,
+    observer: Option<Rc<Observer>>,
+}
+
+impl<S> Retrying<S> {
+    /// Retries according to the policy, using timeouts on the given reactor for the delays.
+    pub fn new(inner: S, policy: RetryPolicy, handle: &Handle) -> Self {
+        Retrying {
+            inner: Rc::new(inner),
+            policy: policy,
+            handle: handle.clone(),
+            observer: None,
+        }
+    }
+
+    /// Calls the given function after every attempt, for example to log the retries or to
+    /// collect metrics.
+    pub fn on_attempt<F: Fn(&Attempt) + 'static>(mut self, observer: F) -> Self {
+        self.observer = Some(Rc::new(observer));
+        self
+    }
+}
+
+impl<S> Service for Retrying<S>
+    where S: Service<Request = Package, Response = Package, Error = io::Error> + 'static,
+          S::Future: 'static,
+{
+    type Request = Package;
+    type Response = Package;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = Package, Error = io::Error>>;
+
+    /// Sends the request until it succeeds, fails in a way which is not transient or the policy
+    /// allows no more attempts. The outcome of the last attempt is returned.
+    fn call(&self, req: Package) -> Self::Future {
+        let inner = self.inner.clone();
+        let policy = self.policy.clone();
+        let handle = self.handle.clone();
+        let observer = self.observer.clone();
+        let original_id = req.correlation_id;
+
+        let ret = future::loop_fn(1, move |attempt| {
+            let mut req = req.clone();
+            if attempt > 1 {
+                req.correlation_id = Uuid::new_v4();
+            }
+
+            let policy = policy.clone();
+            let handle = handle.clone();
+            let observer = observer.clone();
+
+            inner.call(req.clone()).then(move |res| -> Box<dyn Future<Item = Loop<Package, usize>, Error = io::Error>> {
+                let retry_in = if attempt < policy.max_attempts && is_transient(&res) {
+                    Some(policy.delay(attempt))
+                } else {
+                    None
+                };
+
+                if let Some(ref observer) = observer {
+                    observer(&Attempt { request: &req, number: attempt, result: &res, retry_in: retry_in });
+                }
+
+                match retry_in {
+                    Some(delay) => {
+                        let next = future::result(Timeout::new(delay, &handle))
+                            .flatten()
+                            .map(move |_| Loop::Continue(attempt + 1));
+                        Box::new(next)
+                    },
+                    None => Box::new(future::result(res.map(Loop::Break))),
+                }
+            })
+        });
+
+        Box::new(ret.map(move |mut resp| {
+            resp.correlation_id = original_id;
+            resp
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use tokio_core::reactor::Core;
+    use tokio_service::Service;
+
+    use super::{Retrying, RetryPolicy};
+    use adapted::{AdaptedMessage, NotHandledInfo, ReadEventError, WriteEventsFailure};
+    use builder::Builder;
+    use fixtures::write;
+    use mock::{MockService, Kind};
+    use package::Package;
+    use raw::RawMessage;
+    use raw::client_messages::mod_NotHandled::NotHandledReason;
+    use EventNumber;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(1),
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn retries_transient_failures_with_same_events() {
+        let mut core = Core::new().unwrap();
+        let mock = MockService::new();
+        mock.on(Kind::WriteEvents).times(1).fail(io::ErrorKind::ConnectionReset, "reset");
+        mock.on(Kind::WriteEvents).times(1).reply(AdaptedMessage::NotHandled(NotHandledInfo {
+            reason: NotHandledReason::TooBusy,
+            additional_info: None,
+        }));
+        mock.on(Kind::WriteEvents).reply(AdaptedMessage::WriteEventsCompleted(Err(WriteEventsFailure::CommitTimeout.into())));
+
+        let attempts = Rc::new(RefCell::new(Vec::new()));
+        let observed = attempts.clone();
+        let retrying = Retrying::new(mock.clone(), policy(), &core.handle())
+            .on_attempt(move |attempt| observed.borrow_mut().push((attempt.number, attempt.retry_in.is_some())));
+
+        let req = write();
+        let resp = core.run(retrying.call(req.clone())).unwrap();
+
+        // the last outcome is returned when the attempts run out
+        assert_eq!(resp.message.try_adapt().unwrap(), AdaptedMessage::WriteEventsCompleted(Err(WriteEventsFailure::CommitTimeout.into())));
+        assert_eq!(*attempts.borrow(), vec![(1, true), (2, true), (3, false)]);
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 3);
+
+        // every attempt has a correlation id of its own but the same events
+        let event_ids = |package: &Package| match package.message {
+            RawMessage::WriteEvents(ref w) => w.events.iter().map(|e| e.event_id.clone().into_owned()).collect::<Vec<_>>(),
+            ref x => panic!("unexpected {:?}", x),
+        };
+        assert_eq!(requests[0].correlation_id, req.correlation_id);
+        assert_ne!(requests[1].correlation_id, requests[0].correlation_id);
+        assert_ne!(requests[2].correlation_id, requests[1].correlation_id);
+        assert!(requests.iter().all(|x| event_ids(x) == event_ids(&req)));
+
+        // while the response keeps the correlation id of the original request
+        assert_eq!(resp.correlation_id, req.correlation_id);
+    }
+
+    #[test]
+    fn does_not_retry_other_failures() {
+        let mut core = Core::new().unwrap();
+        let mock = MockService::new();
+        mock.on(Kind::WriteEvents).reply(AdaptedMessage::WriteEventsCompleted(Err(WriteEventsFailure::WrongExpectedVersion.into())));
+        mock.on(Kind::ReadEvent).reply(AdaptedMessage::ReadEventCompleted(Err(ReadEventError::NotFound)));
+
+        let retrying = Retrying::new(mock.clone(), policy(), &core.handle());
+
+        core.run(retrying.call(write())).unwrap();
+        let read = Builder::read_event()
+            .stream_id("foo")
+            .event_number(EventNumber::First)
+            .build_package(None, None);
+        let resp = core.run(retrying.call(read)).unwrap();
+        match resp.message {
+            RawMessage::ReadEventCompleted(_) => {},
+            x => panic!("unexpected {:?}", x),
+        }
+
+        assert_eq!(mock.requests().len(), 2);
+    }
+
+    #[test]
+    fn delays_grow_up_to_max() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(350),
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(350));
+
+        let jittered = RetryPolicy { jitter: 0.5, ..policy };
+        for _ in 0..100 {
+            let delay = jittered.delay(2);
+            assert!(delay > Duration::from_millis(99) && delay <= Duration::from_millis(200), "{:?}", delay);
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Routing of requests between the master and follower nodes of a cluster.
+//!
+//! `RoutingClient` keeps a connection to the master and to any number of followers. Writes,
+//! deletes and reads with `require_master` set are sent to the master, other reads are spread
+//! across the followers according to the `ReadPolicy`.
+//!
+//! As followers may lag behind the master, reads following a write can miss the write. With
+//! `RoutingSettings::read_your_writes` the reads of a handle are pinned to the master for the
+//! given time after a write made through the same handle. Every clone of `RoutingClient` is a
+//! separate handle sharing the connections.
+
+use std::io;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use futures::{future, Future};
+use tokio_core::reactor::Handle;
+use tokio_service::Service;
+use rand::{self, Rng};
+
+use client::EventStoreClient;
+use cluster::{self, ClusterSettings, GossipSource, HttpGossip, MemberInfo, NodePreference, VNodeState};
+use package::Package;
+use raw::RawMessage;
+
+/// How reads not requiring master are spread across the followers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadPolicy {
+    /// Each read goes to the next follower.
+    RoundRobin,
+    /// Each read goes to a randomly chosen follower.
+    Random,
+    /// All reads go to the master, followers are not used.
+    MasterOnly,
+}
+
+/// Settings for `RoutingClient`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutingSettings {
+    /// How the reads are spread across followers. Defaults to `ReadPolicy::RoundRobin`.
+    pub policy: ReadPolicy,
+    /// When `Some`, reads are sent to the master for the given time after a write through the
+    /// same handle. Defaults to `None`.
+    pub read_your_writes: Option<Duration>,
+}
+
+impl Default for RoutingSettings {
+    fn default() -> Self {
+        RoutingSettings {
+            policy: ReadPolicy::RoundRobin,
+            read_your_writes: None,
+        }
+    }
+}
+
+/// Where a request is sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Route {
+    Master,
+    Follower(usize),
+}
+
+struct Nodes<S> {
+    master: S,
+    followers: Vec<S>,
+    next: Cell<usize>,
+}
+
+/// `tokio_service::Service` sending requests to the master or to the followers depending on the
+/// request.
+pub struct RoutingClient<S> {
+    nodes: Rc<Nodes<S>>,
+    settings: RoutingSettings,
+    last_write: Rc<Cell<Option<Instant>>>,
+}
+
+impl<S> Clone for RoutingClient<S> {
+    /// Creates a new handle sharing the connections. The new handle starts with the write history
+    /// of this handle but further writes are tracked separately.
+    fn clone(&self) -> Self {
+        RoutingClient {
+            nodes: self.nodes.clone(),
+            settings: self.settings,
+            last_write: Rc::new(Cell::new(self.last_write.get())),
+        }
+    }
+}
+
+impl<S> RoutingClient<S> {
+    /// Creates a client routing between the given connections.
+    pub fn new(master: S, followers: Vec<S>, settings: RoutingSettings) -> Self {
+        RoutingClient {
+            nodes: Rc::new(Nodes {
+                master: master,
+                followers: followers,
+                next: Cell::new(0),
+            }),
+            settings: settings,
+            last_write: Rc::new(Cell::new(None)),
+        }
+    }
+
+    /// Number of the follower connections.
+    pub fn followers(&self) -> usize {
+        self.nodes.followers.len()
+    }
+
+    /// The time until which the reads of this handle go to the master, when
+    /// `RoutingSettings::read_your_writes` is set and a write has been made through this handle.
+    pub fn pinned_until(&self) -> Option<Instant> {
+        match (self.settings.read_your_writes, self.last_write.get()) {
+            (Some(window), Some(at)) => Some(at + window),
+            _ => None,
+        }
+    }
+
+    fn route(&self, req: &Package, now: Instant) -> Route {
+        let followers = self.nodes.followers.len();
+
+        if followers == 0 || self.settings.policy == ReadPolicy::MasterOnly || !is_follower_read(&req.message) {
+            return Route::Master;
+        }
+
+        if self.pinned_until().map_or(false, |until| now < until) {
+            return Route::Master;
+        }
+
+        let index = match self.settings.policy {
+            ReadPolicy::RoundRobin => {
+                let next = self.nodes.next.get();
+                self.nodes.next.set(next.wrapping_add(1));
+                next % followers
+            },
+            _ => rand::thread_rng().gen_range(0, followers),
+        };
+
+        Route::Follower(index)
+    }
+}
+
+impl RoutingClient<EventStoreClient> {
+    /// Discovers the cluster through the HTTP gossip of the seeds and connects to the master and
+    /// up to `followers` followers. Clones are used when there are not enough followers.
+    ///
+    /// Only failing to connect to the master fails the connect. Followers which cannot be
+    /// connected to are logged and left out; if none can be connected to, all requests go to the
+    /// master.
+    pub fn connect(cluster: &ClusterSettings, followers: usize, settings: RoutingSettings, handle: &Handle) -> Box<dyn Future<Item = Self, Error = io::Error>> {
+        RoutingClient::connect_with_gossip_source(HttpGossip::new(handle), cluster, followers, settings, handle)
+    }
+
+    /// Same as `connect` but uses a custom source of gossip.
+    pub fn connect_with_gossip_source<G: GossipSource + 'static>(source: G, cluster: &ClusterSettings, followers: usize, settings: RoutingSettings, handle: &Handle) -> Box<dyn Future<Item = Self, Error = io::Error>> {
+        let mut master_settings = cluster.clone();
+        master_settings.preference = NodePreference::Master;
+
+        let source: Rc<dyn GossipSource> = Rc::new(source);
+        let template = cluster.connection.clone();
+        let handle = handle.clone();
+
+        let members = cluster::discover(source.clone(), &master_settings, &handle)
+            .and_then({
+                let cluster = cluster.clone();
+                let handle = handle.clone();
+                move |master| {
+                    cluster::fetch_members(source, &cluster.seeds, cluster.gossip_timeout, &handle)
+                        .map(move |members| (master, choose_followers(members, followers)))
+                }
+            });
+
+        let ret = members.and_then(move |(master, followers)| {
+            let master = EventStoreClient::connect_with_settings(&master.connection_settings(&template), &handle);
+            let followers = future::join_all(followers.into_iter()
+                .map(|member| {
+                    let address = format!("{}:{}", member.external_tcp_ip, member.external_tcp_port);
+                    EventStoreClient::connect_with_settings(&member.connection_settings(&template), &handle)
+                        .then(move |res| -> Result<Option<EventStoreClient>, io::Error> {
+                            match res {
+                                Ok(client) => Ok(Some(client)),
+                                Err(e) => {
+                                    warn!("leaving out follower {} which failed to connect: {}", address, e);
+                                    Ok(None)
+                                },
+                            }
+                        })
+                })
+                .collect::<Vec<_>>());
+
+            master.join(followers).map(move |(master, followers)| {
+                let followers = followers.into_iter().filter_map(|x| x).collect();
+                RoutingClient::new(master, followers, settings)
+            })
+        });
+
+        Box::new(ret)
+    }
+}
+
+/// Live followers in random order, followed by clones.
+fn choose_followers(members: Vec<MemberInfo>, max: usize) -> Vec<MemberInfo> {
+    let mut rng = rand::thread_rng();
+    let mut chosen = Vec::new();
+
+    for state in &[VNodeState::Slave, VNodeState::Clone] {
+        let mut found = members.iter()
+            .filter(|m| m.is_alive && m.state == *state)
+            .cloned()
+            .collect::<Vec<_>>();
+        rng.shuffle(&mut found);
+        chosen.extend(found);
+    }
+
+    chosen.truncate(max);
+    chosen
+}
+
+/// Reads which do not require master.
+fn is_follower_read(msg: &RawMessage) -> bool {
+    match *msg {
+        RawMessage::ReadEvent(ref x) => !x.require_master,
+        RawMessage::ReadStreamEvents(_, ref x) => !x.require_master,
+        RawMessage::ReadAllEvents(_, ref x) => !x.require_master,
+        _ => false,
+    }
+}
+
+/// Requests after which the reads are pinned to master in the read-your-writes mode.
+fn is_write(msg: &RawMessage) -> bool {
+    match *msg {
+        RawMessage::WriteEvents(_) | RawMessage::DeleteStream(_) => true,
+        _ => false,
+    }
+}
+
+impl<S> Service for RoutingClient<S>
+    where S: Service<Request = Package, Response = Package, Error = io::Error>,
+          S::Future: 'static
+{
+    type Request = Package;
+    type Response = Package;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = Package, Error = io::Error>>;
+
+    fn call(&self, req: Package) -> Self::Future {
+        if !is_write(&req.message) {
+            return match self.route(&req, Instant::now()) {
+                Route::Master => Box::new(self.nodes.master.call(req)),
+                Route::Follower(index) => Box::new(self.nodes.followers[index].call(req)),
+            };
+        }
+
+        // pin from the moment of sending, as the write may become visible before the response
+        self.last_write.set(Some(Instant::now()));
+        let last_write = self.last_write.clone();
+
+        Box::new(self.nodes.master.call(req).then(move |res| {
+            last_write.set(Some(Instant::now()));
+            res
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use std::time::Duration;
+
+    use futures::{future, Future};
+    use tokio_service::Service;
+
+    use super::{Route, RoutingClient, RoutingSettings, ReadPolicy};
+    use builder::Builder;
+    use fixtures::write;
+    use package::Package;
+    use raw::RawMessage;
+    use EventNumber;
+
+    struct Node {
+        name: &'static str,
+        calls: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl Service for Node {
+        type Request = Package;
+        type Response = Package;
+        type Error = io::Error;
+        type Future = future::FutureResult<Package, io::Error>;
+
+        fn call(&self, req: Package) -> Self::Future {
+            self.calls.borrow_mut().push(self.name);
+            future::ok(Package { message: RawMessage::Pong, ..req })
+        }
+    }
+
+    fn client(settings: RoutingSettings) -> (RoutingClient<Node>, Rc<RefCell<Vec<&'static str>>>) {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let node = |name| Node { name: name, calls: calls.clone() };
+        let client = RoutingClient::new(node("master"), vec![node("f1"), node("f2")], settings);
+        (client, calls)
+    }
+
+    fn read(require_master: bool) -> Package {
+        Builder::read_event()
+            .stream_id("foo")
+            .event_number(EventNumber::First)
+            .require_master(require_master)
+            .build_package(None, None)
+    }
+
+    #[test]
+    fn routes_by_request() {
+        let (client, calls) = client(RoutingSettings::default());
+
+        client.call(read(false)).wait().unwrap();
+        client.call(read(false)).wait().unwrap();
+        client.call(read(false)).wait().unwrap();
+        client.call(read(true)).wait().unwrap();
+        client.call(write()).wait().unwrap();
+        client.call(Builder::ping().build_package(None, None)).wait().unwrap();
+
+        assert_eq!(*calls.borrow(), vec!["f1", "f2", "f1", "master", "master", "master"]);
+    }
+
+    #[test]
+    fn master_only_policy() {
+        let (client, calls) = client(RoutingSettings { policy: ReadPolicy::MasterOnly, read_your_writes: None });
+
+        client.call(read(false)).wait().unwrap();
+        assert_eq!(*calls.borrow(), vec!["master"]);
+    }
+
+    #[test]
+    fn read_your_writes_pins_reads_of_the_same_handle() {
+        let settings = RoutingSettings {
+            policy: ReadPolicy::RoundRobin,
+            read_your_writes: Some(Duration::from_secs(60)),
+        };
+        let (client, calls) = client(settings);
+        let other = client.clone();
+
+        client.call(write()).wait().unwrap();
+        client.call(read(false)).wait().unwrap();
+        other.call(read(false)).wait().unwrap();
+
+        assert_eq!(*calls.borrow(), vec!["master", "master", "f1"]);
+        assert_eq!(other.pinned_until(), None);
+
+        let until = client.pinned_until().unwrap();
+        assert_eq!(client.route(&read(false), until - Duration::from_millis(1)), Route::Master);
+        assert_eq!(client.route(&read(false), until), Route::Follower(1));
+    }
+}
@@ -0,0 +1,381 @@
+//! Serializing event data and metadata with serde, enabled with the `serialization` feature.
+//!
+//! `NewEventBuilder::json_data` and `NewEventBuilder::json_metadata` serialize any
+//! `T: Serialize` as JSON and set the content type to `ContentType::Json`, and
+//! `RecordedEvent::decode_data` and `RecordedEvent::decode_metadata` deserialize them back. Other
+//! formats, such as MessagePack or bincode, can be used by implementing `Codec` and using the
+//! `_with` variants of the methods.
+//!
+//! # Example
+//!
+//! ```rust
+//! extern crate eventstore_tcp;
+//!
+//! use std::collections::BTreeMap;
+//! use eventstore_tcp::{Builder, ExpectedVersion};
+//!
+//! fn main() {
+//!     let mut placed = BTreeMap::new();
+//!     placed.insert("order", 42);
+//!
+//!     let mut builder = Builder::write_events();
+//!     builder.stream_id("orders-42").expected_version(ExpectedVersion::NoStream);
+//!     builder.new_event()
+//!         .event_type("OrderPlaced")
+//!         .json_data(&placed).unwrap()
+//!         .done();
+//!
+//!     let package = builder.build_package(None, None);
+//! }
+//! ```
+
+use std::fmt;
+use std::error;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use adapted::RecordedEvent;
+use builder::{NewEventBuilder, WriteEventsBuilder};
+use raw::client_messages::EventRecord;
+use ContentType;
+
+/// Boxed error of a `Codec`.
+pub type CodecError = Box<dyn error::Error + Send + Sync>;
+
+/// Format for serializing event data and metadata.
+pub trait Codec {
+    /// The content type set on the events written with this codec.
+    fn content_type(&self) -> ContentType;
+
+    /// Serializes the value.
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError>;
+
+    /// Deserializes a value.
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+/// `Codec` for JSON, usable with the projections of the server.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl Codec for Json {
+    fn content_type(&self) -> ContentType {
+        ContentType::Json
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Serializing the data or metadata of a new event failed.
+#[derive(Debug)]
+pub struct EncodeError(CodecError);
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Serializing event failed: {}", self.0)
+    }
+}
+
+impl error::Error for EncodeError {
+    fn description(&self) -> &str {
+        "serializing event failed"
+    }
+
+    fn cause(&self) -> Option<&dyn error::Error> {
+        Some(&*self.0)
+    }
+}
+
+impl From<CodecError> for EncodeError {
+    fn from(e: CodecError) -> Self {
+        EncodeError(e)
+    }
+}
+
+/// Which part of an event failed to deserialize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    /// The data of the event
+    Data,
+    /// The metadata of the event
+    Metadata,
+}
+
+/// Why decoding an event failed.
+#[derive(Debug)]
+pub enum DecodeErrorKind {
+    /// The event type is not known to the decoder, the payload was not looked at.
+    UnknownEventType,
+    /// The part failed to deserialize with the error of the codec, or with `None` when decoding
+    /// metadata of an event without any.
+    Payload(Part, Option<CodecError>),
+}
+
+/// Decoding an event failed.
+#[derive(Debug)]
+pub struct DecodeError {
+    /// The stream of the event
+    pub stream_id: String,
+    /// The event number of the event, as on the wire
+    pub event_number: i64,
+    /// The type of the event
+    pub event_type: String,
+    /// What failed
+    pub kind: DecodeErrorKind,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            DecodeErrorKind::UnknownEventType =>
+                write!(f, "Unknown type {:?} of event {}@{}", self.event_type, self.stream_id, self.event_number),
+            DecodeErrorKind::Payload(part, ref cause) => {
+                write!(f, "Deserializing {:?} of event {}@{} of type {:?} failed", part, self.stream_id, self.event_number, self.event_type)?;
+                match *cause {
+                    Some(ref e) => write!(f, ": {}", e),
+                    None => write!(f, ": no metadata"),
+                }
+            },
+        }
+    }
+}
+
+impl error::Error for DecodeError {
+    fn description(&self) -> &str {
+        match self.kind {
+            DecodeErrorKind::UnknownEventType => "unknown event type",
+            DecodeErrorKind::Payload(..) => "deserializing event failed",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn error::Error> {
+        match self.kind {
+            DecodeErrorKind::Payload(_, Some(ref e)) => Some(&**e as &dyn error::Error),
+            _ => None,
+        }
+    }
+}
+
+impl DecodeError {
+    /// Error for an event whose type is not known to the decoder.
+    pub fn unknown_event_type(record: &EventRecord) -> Self {
+        DecodeError {
+            stream_id: record.event_stream_id.clone().into_owned(),
+            event_number: record.event_number,
+            event_type: record.event_type.clone().into_owned(),
+            kind: DecodeErrorKind::UnknownEventType,
+        }
+    }
+
+    /// `true` if the event type was not known, as opposed to the payload failing to decode.
+    pub fn is_unknown_event_type(&self) -> bool {
+        match self.kind {
+            DecodeErrorKind::UnknownEventType => true,
+            DecodeErrorKind::Payload(..) => false,
+        }
+    }
+}
+
+/// Values written as events of a known type and read back from them, usually implemented with
+/// `#[derive(EventStoreEvent)]` of the `eventstore-tcp-derive` crate.
+pub trait Event: Sized {
+    /// The event type the value is written as.
+    fn event_type(&self) -> &'static str;
+
+    /// Adds the value as a new event to the builder.
+    fn append_to(&self, events: &mut WriteEventsBuilder) -> Result<(), EncodeError>;
+
+    /// Decodes the event, failing if it is not of a known type.
+    fn decode(record: &EventRecord) -> Result<Self, DecodeError>;
+
+    /// Decodes the event, see `decode`.
+    fn decode_recorded(event: &RecordedEvent) -> Result<Self, DecodeError> {
+        Self::decode(&event.as_raw())
+    }
+}
+
+fn decode<C: Codec, T: DeserializeOwned>(codec: &C, stream_id: &str, event_number: i64, event_type: &str, part: Part, bytes: Option<&[u8]>) -> Result<T, DecodeError> {
+    let cause = match bytes.map(|bytes| codec.decode(bytes)) {
+        Some(Ok(value)) => return Ok(value),
+        Some(Err(e)) => Some(e),
+        None => None,
+    };
+
+    Err(DecodeError {
+        stream_id: stream_id.to_owned(),
+        event_number: event_number,
+        event_type: event_type.to_owned(),
+        kind: DecodeErrorKind::Payload(part, cause),
+    })
+}
+
+impl<'a> NewEventBuilder<'a> {
+    /// Serializes the value as the data of the event with the codec, setting the content type of
+    /// the codec.
+    pub fn data_with<C: Codec, T: Serialize>(self, codec: &C, value: &T) -> Result<Self, EncodeError> {
+        let bytes = codec.encode(value).map_err(EncodeError)?;
+        Ok(self.data(bytes).data_content_type(codec.content_type()))
+    }
+
+    /// Serializes the value as the metadata of the event with the codec, setting the content
+    /// type of the codec.
+    pub fn metadata_with<C: Codec, T: Serialize>(self, codec: &C, value: &T) -> Result<Self, EncodeError> {
+        let bytes = codec.encode(value).map_err(EncodeError)?;
+        Ok(self.metadata(bytes).metadata_content_type(codec.content_type()))
+    }
+
+    /// Serializes the value as the JSON data of the event.
+    pub fn json_data<T: Serialize>(self, value: &T) -> Result<Self, EncodeError> {
+        self.data_with(&Json, value)
+    }
+
+    /// Serializes the value as the JSON metadata of the event.
+    pub fn json_metadata<T: Serialize>(self, value: &T) -> Result<Self, EncodeError> {
+        self.metadata_with(&Json, value)
+    }
+}
+
+impl RecordedEvent {
+    /// Deserializes the data of the event with the codec.
+    pub fn decode_data_with<C: Codec, T: DeserializeOwned>(&self, codec: &C) -> Result<T, DecodeError> {
+        decode(codec, &self.stream_id, self.number.into(), &self.event_type, Part::Data, Some(&self.data[..]))
+    }
+
+    /// Deserializes the metadata of the event with the codec. Fails if the event has no
+    /// metadata.
+    pub fn decode_metadata_with<C: Codec, T: DeserializeOwned>(&self, codec: &C) -> Result<T, DecodeError> {
+        decode(codec, &self.stream_id, self.number.into(), &self.event_type, Part::Metadata, self.metadata.as_ref().map(|x| &x[..]))
+    }
+
+    /// Deserializes the JSON data of the event.
+    pub fn decode_data<T: DeserializeOwned>(&self) -> Result<T, DecodeError> {
+        self.decode_data_with(&Json)
+    }
+
+    /// Deserializes the JSON metadata of the event.
+    pub fn decode_metadata<T: DeserializeOwned>(&self) -> Result<T, DecodeError> {
+        self.decode_metadata_with(&Json)
+    }
+}
+
+impl<'a> EventRecord<'a> {
+    /// Deserializes the data of the event with the codec.
+    pub fn decode_data_with<C: Codec, T: DeserializeOwned>(&self, codec: &C) -> Result<T, DecodeError> {
+        decode(codec, &self.event_stream_id, self.event_number, &self.event_type, Part::Data, Some(&self.data[..]))
+    }
+
+    /// Deserializes the metadata of the event with the codec. Fails if the event has no
+    /// metadata.
+    pub fn decode_metadata_with<C: Codec, T: DeserializeOwned>(&self, codec: &C) -> Result<T, DecodeError> {
+        decode(codec, &self.event_stream_id, self.event_number, &self.event_type, Part::Metadata, self.metadata.as_ref().map(|x| &x[..]))
+    }
+
+    /// Deserializes the JSON data of the event.
+    pub fn decode_data<T: DeserializeOwned>(&self) -> Result<T, DecodeError> {
+        self.decode_data_with(&Json)
+    }
+
+    /// Deserializes the JSON metadata of the event.
+    pub fn decode_metadata<T: DeserializeOwned>(&self) -> Result<T, DecodeError> {
+        self.decode_metadata_with(&Json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::convert::TryFrom;
+
+    use serde::Serialize;
+    use serde::de::DeserializeOwned;
+    use serde_json;
+
+    use super::{Codec, CodecError, DecodeErrorKind, Part};
+    use adapted::RecordedEvent;
+    use builder::Builder;
+    use fixtures::recorded_from_write;
+    use {ContentType, ExpectedVersion};
+
+    /// Stands in for a binary format such as bincode.
+    struct Reversed;
+
+    impl Codec for Reversed {
+        fn content_type(&self) -> ContentType {
+            ContentType::Bytes
+        }
+
+        fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+            let mut bytes = serde_json::to_vec(value)?;
+            bytes.reverse();
+            Ok(bytes)
+        }
+
+        fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+            let mut bytes = bytes.to_vec();
+            bytes.reverse();
+            Ok(serde_json::from_slice(&bytes)?)
+        }
+    }
+
+    /// Writes the event and reads it back as the server would return it.
+    fn round_trip<F>(f: F) -> RecordedEvent
+        where F: FnOnce(::builder::NewEventBuilder) -> ::builder::NewEventBuilder
+    {
+        let mut builder = Builder::write_events();
+        builder.stream_id("orders-1").expected_version(ExpectedVersion::Any);
+        f(builder.new_event().event_type("OrderPlaced")).done();
+
+        RecordedEvent::try_from(&recorded_from_write(&mut builder)[0]).unwrap()
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut data = BTreeMap::new();
+        data.insert("order".to_owned(), 42u32);
+
+        let event = round_trip(|e| e.json_data(&data).unwrap().json_metadata(&("user", 1)).unwrap());
+
+        assert_eq!(event.data_content_type, ContentType::Json);
+        assert_eq!(event.metadata_content_type, ContentType::Json);
+        assert_eq!(&event.data[..], &b"{\"order\":42}"[..]);
+        assert_eq!(event.decode_data::<BTreeMap<String, u32>>().unwrap(), data);
+        assert_eq!(event.decode_metadata::<(String, u32)>().unwrap(), ("user".to_owned(), 1));
+        assert_eq!(event.as_raw().decode_data::<BTreeMap<String, u32>>().unwrap(), data);
+    }
+
+    #[test]
+    fn custom_codec() {
+        let event = round_trip(|e| e.data_with(&Reversed, &vec![1, 2, 3]).unwrap());
+
+        assert_eq!(event.data_content_type, ContentType::Bytes);
+        assert_eq!(&event.data[..], &b"]3,2,1["[..]);
+        assert_eq!(event.decode_data_with::<_, Vec<u8>>(&Reversed).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn decode_errors_describe_the_event() {
+        let event = round_trip(|e| e.data(&b"not json"[..]));
+
+        let err = event.decode_data::<u32>().unwrap_err();
+        assert!(!err.is_unknown_event_type());
+        match err.kind {
+            DecodeErrorKind::Payload(Part::Data, Some(_)) => {},
+            ref x => panic!("unexpected {:?}", x),
+        }
+        assert!(err.to_string().starts_with("Deserializing Data of event orders-1@0 of type \"OrderPlaced\" failed: "), "{}", err);
+
+        let err = event.decode_metadata::<u32>().unwrap_err();
+        match err.kind {
+            DecodeErrorKind::Payload(Part::Metadata, None) => {},
+            ref x => panic!("unexpected {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,538 @@
+//! Server side of the protocol, for implementing EventStore compatible servers such as test
+//! doubles.
+//!
+//! `Server` accepts connections, decodes the requests into `AdaptedMessage` values and dispatches
+//! them to the `Handler` methods. The responses are written with the correlation id of the
+//! request. Heartbeats and pings are answered without involving the handler.
+//!
+//! Requests are handled concurrently and the responses are written in the order they complete.
+//! Any number of unsolicited packages, such as events appearing in a subscription, can be sent
+//! to the client with the `Pusher` of the connection.
+//!
+//! # Example
+//!
+//! ```no_run
+//! extern crate futures;
+//! extern crate tokio_core;
+//! extern crate eventstore_tcp;
+//!
+//! use futures::future;
+//! use tokio_core::reactor::Core;
+//!
+//! use eventstore_tcp::server::{Server, Handler, Context, HandlerFuture, Reply};
+//! use eventstore_tcp::adapted::ReadEventError;
+//! use eventstore_tcp::adapted::ResolvedEvent;
+//! use eventstore_tcp::raw::ReadEvent;
+//!
+//! /// Server without any streams.
+//! struct Empty;
+//!
+//! impl Handler for Empty {
+//!     fn read_event(&self, _: &Context, _: ReadEvent<'static>) -> HandlerFuture<Result<ResolvedEvent, ReadEventError<'static>>> {
+//!         Box::new(future::ok(Reply::Completed(Err(ReadEventError::NoStream))))
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let mut core = Core::new().unwrap();
+//!     let server = Server::new(Empty, &core.handle());
+//!     let listening = server.listen(&"127.0.0.1:1113".parse().unwrap()).unwrap();
+//!     core.run(listening).unwrap();
+//! }
+//! ```
+
+use std::io;
+use std::rc::Rc;
+use std::borrow::Cow;
+use std::net::SocketAddr;
+
+use futures::{future, Async, Future, Poll, Stream};
+use futures::sync::mpsc;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use uuid::Uuid;
+
+use adapted::{AdaptedMessage, NotHandledInfo, ReadAllCompleted, ReadAllError, ReadEventError, ReadStreamCompleted, ReadStreamError, ResolvedEvent, WriteEventsCompleted, WriteEventsFailure};
+use codec::PackageCodec;
+use package::Package;
+use raw::{self, RawMessage};
+use raw::client_messages::mod_NotHandled::NotHandledReason;
+use raw::client_messages::mod_SubscriptionDropped::SubscriptionDropReason;
+use {ReadDirection, UsernamePassword};
+
+/// Maximum number of requests of a single connection being handled at the same time. Further
+/// requests are not read before some of the earlier ones complete.
+pub const MAX_IN_FLIGHT: usize = 128;
+
+/// Outcome of a request given to a `Handler`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply<T> {
+    /// The request was handled, respond with the given result
+    Completed(T),
+    /// Respond with `NotHandled`, for example to simulate a follower node answering `NotMaster`
+    NotHandled(NotHandledInfo<'static>),
+    /// Respond with `NotAuthenticated`, as servers do for requests with unknown credentials
+    NotAuthenticated,
+    /// Respond with `BadRequest` and the given reason
+    BadRequest(String),
+}
+
+impl<T> Reply<T> {
+    /// `NotHandled` reply for the given reason without any additional info.
+    pub fn not_handled(reason: NotHandledReason) -> Self {
+        Reply::NotHandled(NotHandledInfo { reason: reason, additional_info: None })
+    }
+
+    fn into_message<F>(self, f: F) -> RawMessage<'static>
+        where F: FnOnce(T) -> RawMessage<'static>
+    {
+        match self {
+            Reply::Completed(x) => f(x),
+            Reply::NotHandled(info) => AdaptedMessage::NotHandled(info).as_raw().into_owned(),
+            Reply::NotAuthenticated => not_authenticated(),
+            Reply::BadRequest(reason) => bad_request(&reason),
+        }
+    }
+}
+
+/// Future returned by the `Handler` methods. Failing the future closes the connection, failures
+/// of the operations should be replied instead.
+pub type HandlerFuture<T> = Box<dyn Future<Item = Reply<T>, Error = io::Error>>;
+
+fn not_ready<T: 'static>() -> HandlerFuture<T> {
+    Box::new(future::ok(Reply::not_handled(NotHandledReason::NotReady)))
+}
+
+/// Information about the request being handled.
+#[derive(Clone)]
+pub struct Context {
+    correlation_id: Uuid,
+    authentication: Option<UsernamePassword>,
+    pusher: Pusher,
+}
+
+impl Context {
+    /// The correlation id of the request, which is also used for the response.
+    pub fn correlation_id(&self) -> &Uuid {
+        &self.correlation_id
+    }
+
+    /// The credentials sent with the request, if any.
+    pub fn authentication(&self) -> Option<&UsernamePassword> {
+        self.authentication.as_ref()
+    }
+
+    /// The `Pusher` of the connection the request was received from.
+    pub fn pusher(&self) -> &Pusher {
+        &self.pusher
+    }
+}
+
+/// Handle for sending unsolicited packages to a connected client. Cloning the `Pusher` does not
+/// keep the connection open.
+#[derive(Clone)]
+pub struct Pusher {
+    tx: mpsc::UnboundedSender<Package>,
+}
+
+impl Pusher {
+    /// Sends the message to the client with the given correlation id, usually the one of the
+    /// request starting a subscription. Fails when the connection has been closed.
+    pub fn push(&self, correlation_id: Uuid, message: RawMessage<'static>) -> io::Result<()> {
+        let package = Package {
+            authentication: None,
+            correlation_id: correlation_id,
+            message: message,
+        };
+
+        self.tx.unbounded_send(package)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "connection has been closed"))
+    }
+}
+
+/// Handles the requests of the clients, one method per operation. The default implementations
+/// reply with `NotHandled(NotReady)` for operations and `BadRequest` for anything else.
+///
+/// A single handler is shared by all the connections of a `Server`, so any state must be kept
+/// behind a `RefCell` or similar.
+pub trait Handler {
+    /// Called for every new connection before any requests are handled.
+    fn connected(&self, _pusher: &Pusher) {}
+
+    /// Handles `WriteEvents`.
+    fn write_events(&self, _ctx: &Context, _req: raw::WriteEvents<'static>) -> HandlerFuture<Result<WriteEventsCompleted, WriteEventsFailure>> {
+        not_ready()
+    }
+
+    /// Handles `DeleteStream`, which is not yet adapted and uses the raw messages.
+    fn delete_stream(&self, _ctx: &Context, _req: raw::DeleteStream<'static>) -> HandlerFuture<raw::DeleteStreamCompleted<'static>> {
+        not_ready()
+    }
+
+    /// Handles `ReadEvent`.
+    fn read_event(&self, _ctx: &Context, _req: raw::ReadEvent<'static>) -> HandlerFuture<Result<ResolvedEvent, ReadEventError<'static>>> {
+        not_ready()
+    }
+
+    /// Handles `ReadStreamEvents` in the given direction.
+    fn read_stream_events(&self, _ctx: &Context, _dir: ReadDirection, _req: raw::ReadStreamEvents<'static>) -> HandlerFuture<Result<ReadStreamCompleted, ReadStreamError<'static>>> {
+        not_ready()
+    }
+
+    /// Handles `ReadAllEvents` in the given direction.
+    fn read_all_events(&self, _ctx: &Context, _dir: ReadDirection, _req: raw::ReadAllEvents) -> HandlerFuture<Result<ReadAllCompleted, ReadAllError<'static>>> {
+        not_ready()
+    }
+
+    /// Handles `SubscribeToStream`, replying either `SubscriptionConfirmation` or
+    /// `SubscriptionDropped`. The events are sent with `ctx.pusher()` using the correlation id of
+    /// the request. The reply is sent through the same channel, so any events pushed after the
+    /// returned future has completed are received after the confirmation.
+    fn subscribe_to_stream(&self, _ctx: &Context, _req: raw::SubscribeToStream<'static>) -> HandlerFuture<Result<raw::SubscriptionConfirmation, SubscriptionDropReason>> {
+        not_ready()
+    }
+
+    /// Handles `UnsubscribeFromStream` for the subscription with the same correlation id,
+    /// replying `SubscriptionDropped(Unsubscribed)` through the pushed packages.
+    fn unsubscribe_from_stream(&self, _ctx: &Context) -> HandlerFuture<()> {
+        Box::new(future::ok(Reply::Completed(())))
+    }
+
+    /// Handles `Authenticate`, replying `Authenticated` for `true`. The default accepts any
+    /// credentials.
+    fn authenticate(&self, ctx: &Context) -> HandlerFuture<bool> {
+        Box::new(future::ok(Reply::Completed(ctx.authentication().is_some())))
+    }
+
+    /// Handles `IdentifyClient`. The default acknowledges the identification.
+    fn identify_client(&self, _ctx: &Context, _req: raw::IdentifyClient<'static>) -> HandlerFuture<()> {
+        Box::new(future::ok(Reply::Completed(())))
+    }
+
+    /// Handles any other message, such as ones only available as `RawMessage::Unsupported`.
+    /// Returning `None` sends no response.
+    fn other(&self, _ctx: &Context, _req: RawMessage<'static>) -> Box<dyn Future<Item = Option<RawMessage<'static>>, Error = io::Error>> {
+        Box::new(future::ok(Some(bad_request("unsupported request"))))
+    }
+}
+
+fn bad_request(msg: &str) -> RawMessage<'static> {
+    RawMessage::BadRequest(Cow::Owned(msg.as_bytes().to_vec()).into())
+}
+
+fn not_authenticated() -> RawMessage<'static> {
+    RawMessage::NotAuthenticated(Cow::Borrowed(&b"Not Authenticated"[..]).into())
+}
+
+fn dropped(reason: SubscriptionDropReason) -> RawMessage<'static> {
+    RawMessage::SubscriptionDropped(raw::SubscriptionDropped { reason: reason })
+}
+
+/// Accepts connections and serves them with the `Handler`.
+pub struct Server<H> {
+    handler: Rc<H>,
+    handle: Handle,
+}
+
+impl<H> Clone for Server<H> {
+    fn clone(&self) -> Self {
+        Server {
+            handler: self.handler.clone(),
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl<H: Handler + 'static> Server<H> {
+    /// Creates a server using the given handler for all of the connections.
+    pub fn new(handler: H, handle: &Handle) -> Self {
+        Server {
+            handler: Rc::new(handler),
+            handle: handle.clone(),
+        }
+    }
+
+    /// The handler shared by all of the connections.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Binds to the given address. The returned `Listening` future accepts connections until it
+    /// is dropped or accepting fails, serving each connection in a separate task.
+    pub fn listen(&self, addr: &SocketAddr) -> io::Result<Listening> {
+        let listener = TcpListener::bind(addr, &self.handle)?;
+        let local_addr = listener.local_addr()?;
+        let server = self.clone();
+
+        let accepting = listener.incoming().for_each(move |(stream, _)| {
+            stream.set_nodelay(true)?;
+            let connection = server.serve(stream);
+            server.handle.spawn(connection.map_err(|_| ()));
+            Ok(())
+        });
+
+        Ok(Listening {
+            local_addr: local_addr,
+            inner: Box::new(accepting),
+        })
+    }
+
+    /// Serves a single already established connection, such as one end of `pipe::duplex`. The
+    /// returned future completes when the client closes the connection.
+    pub fn serve<T>(&self, io: T) -> Box<dyn Future<Item = (), Error = io::Error>>
+        where T: AsyncRead + AsyncWrite + 'static
+    {
+        let (tx, rx) = mpsc::unbounded();
+        let pusher = Pusher { tx: tx };
+        self.handler.connected(&pusher);
+
+        let (sink, stream) = io.framed(PackageCodec).split();
+        let handler = self.handler.clone();
+
+        let responses = stream
+            .map(move |req| dispatch(&*handler, req, pusher.clone()))
+            .buffer_unordered(MAX_IN_FLIGHT)
+            .filter_map(|x| x);
+
+        let outgoing = Outgoing {
+            responses: responses,
+            pushes: rx,
+            pushes_done: false,
+        };
+
+        Box::new(outgoing.forward(sink).map(|_| ()))
+    }
+}
+
+/// Future of a listening `Server`, see `Server::listen`.
+pub struct Listening {
+    local_addr: SocketAddr,
+    inner: Box<dyn Future<Item = (), Error = io::Error>>,
+}
+
+impl Listening {
+    /// The address the server is listening on, useful when binding to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Future for Listening {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        self.inner.poll()
+    }
+}
+
+/// Responses merged with the pushed packages. Ends with the responses, that is, when the client
+/// closes the connection, regardless of any `Pusher` still around.
+struct Outgoing<S> {
+    responses: S,
+    pushes: mpsc::UnboundedReceiver<Package>,
+    pushes_done: bool,
+}
+
+impl<S: Stream<Item = Package, Error = io::Error>> Stream for Outgoing<S> {
+    type Item = Package;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Package>, io::Error> {
+        if !self.pushes_done {
+            match self.pushes.poll() {
+                Ok(Async::Ready(Some(package))) => return Ok(Async::Ready(Some(package))),
+                Ok(Async::Ready(None)) | Err(()) => self.pushes_done = true,
+                Ok(Async::NotReady) => {},
+            }
+        }
+
+        self.responses.poll()
+    }
+}
+
+/// Answers a single request, `None` if no response should be sent.
+fn dispatch<H: Handler>(handler: &H, req: Package, pusher: Pusher) -> Box<dyn Future<Item = Option<Package>, Error = io::Error>> {
+    let ctx = Context {
+        correlation_id: req.correlation_id,
+        authentication: req.authentication,
+        pusher: pusher,
+    };
+
+    let correlation_id = ctx.correlation_id;
+
+    fn reply<T: 'static, F>(f: HandlerFuture<T>, into: F) -> Box<dyn Future<Item = Option<RawMessage<'static>>, Error = io::Error>>
+        where F: FnOnce(T) -> AdaptedMessage<'static> + 'static
+    {
+        Box::new(f.map(move |reply| Some(reply.into_message(|x| into(x).as_raw().into_owned()))))
+    }
+
+    /// Sends the response with the pushed packages to keep it ordered with the events of a
+    /// subscription.
+    fn pushed<F>(pusher: Pusher, correlation_id: Uuid, f: F) -> Box<dyn Future<Item = Option<RawMessage<'static>>, Error = io::Error>>
+        where F: Future<Item = RawMessage<'static>, Error = io::Error> + 'static
+    {
+        Box::new(f.and_then(move |msg| pusher.push(correlation_id, msg)).map(|_| None))
+    }
+
+    fn immediate(msg: RawMessage<'static>) -> Box<dyn Future<Item = Option<RawMessage<'static>>, Error = io::Error>> {
+        Box::new(future::ok(Some(msg)))
+    }
+
+    let response: Box<dyn Future<Item = Option<RawMessage<'static>>, Error = io::Error>> = match req.message.try_adapt() {
+        Ok(AdaptedMessage::HeartbeatRequest) => immediate(RawMessage::HeartbeatResponse),
+        Ok(AdaptedMessage::HeartbeatResponse) => Box::new(future::ok(None)),
+        Ok(AdaptedMessage::Ping) => immediate(RawMessage::Pong),
+        Ok(AdaptedMessage::WriteEvents(x)) =>
+            reply(handler.write_events(&ctx, x), |res| AdaptedMessage::WriteEventsCompleted(res.map_err(Into::into))),
+        Ok(AdaptedMessage::ReadEvent(x)) =>
+            reply(handler.read_event(&ctx, x), AdaptedMessage::ReadEventCompleted),
+        Ok(AdaptedMessage::ReadStreamEvents(dir, x)) =>
+            reply(handler.read_stream_events(&ctx, dir, x), move |res| AdaptedMessage::ReadStreamEventsCompleted(dir, res)),
+        Ok(AdaptedMessage::ReadAllEvents(dir, x)) =>
+            reply(handler.read_all_events(&ctx, dir, x), move |res| AdaptedMessage::ReadAllEventsCompleted(dir, res.map_err(Into::into))),
+        Ok(AdaptedMessage::Authenticate) => {
+            Box::new(handler.authenticate(&ctx).map(|reply| Some(reply.into_message(|ok| {
+                if ok {
+                    RawMessage::Authenticated
+                } else {
+                    not_authenticated()
+                }
+            }))))
+        },
+        Ok(AdaptedMessage::IdentifyClient(x)) =>
+            Box::new(handler.identify_client(&ctx, x).map(|reply| Some(reply.into_message(|_| RawMessage::ClientIdentified)))),
+        Ok(other) => immediate(bad_request(&format!("unexpected message 0x{:02x}", other.as_raw().discriminator()))),
+        Err((RawMessage::DeleteStream(x), _)) =>
+            Box::new(handler.delete_stream(&ctx, x).map(|reply| Some(reply.into_message(RawMessage::DeleteStreamCompleted)))),
+        Err((RawMessage::SubscribeToStream(x), _)) => {
+            let pusher = ctx.pusher.clone();
+            pushed(pusher, correlation_id, handler.subscribe_to_stream(&ctx, x).map(|reply| reply.into_message(|res| match res {
+                Ok(confirmation) => RawMessage::SubscriptionConfirmation(confirmation),
+                Err(reason) => dropped(reason),
+            })))
+        },
+        Err((RawMessage::UnsubscribeFromStream(_), _)) => {
+            let pusher = ctx.pusher.clone();
+            pushed(pusher, correlation_id, handler.unsubscribe_from_stream(&ctx).map(|reply| reply.into_message(|_| dropped(SubscriptionDropReason::Unsubscribed))))
+        },
+        Err((raw, _)) => handler.other(&ctx, raw),
+    };
+
+    Box::new(response.map(move |msg| msg.map(|msg| Package {
+        authentication: None,
+        correlation_id: correlation_id,
+        message: msg,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::cell::RefCell;
+
+    use futures::{future, Future, Stream, Sink};
+    use futures::stream::iter_ok;
+    use tokio_core::reactor::Core;
+    use tokio_io::AsyncRead;
+    use uuid::Uuid;
+
+    use super::{Server, Handler, Context, HandlerFuture, Reply, Pusher};
+    use adapted::ReadEventError;
+    use adapted::ResolvedEvent;
+    use builder::Builder;
+    use codec::PackageCodec;
+    use package::Package;
+    use pipe;
+    use raw::{ReadEvent, RawMessage};
+    use raw::client_messages::mod_NotHandled::NotHandledReason;
+    use EventNumber;
+
+    #[derive(Default)]
+    struct Follower {
+        pushers: RefCell<Vec<Pusher>>,
+    }
+
+    impl Handler for Follower {
+        fn connected(&self, pusher: &Pusher) {
+            self.pushers.borrow_mut().push(pusher.clone());
+        }
+
+        fn read_event(&self, ctx: &Context, req: ReadEvent<'static>) -> HandlerFuture<Result<ResolvedEvent, ReadEventError<'static>>> {
+            assert!(ctx.authentication().is_none());
+            let reply = if req.require_master {
+                Reply::not_handled(NotHandledReason::NotMaster)
+            } else {
+                Reply::Completed(Err(ReadEventError::NotFound))
+            };
+            Box::new(future::ok(reply))
+        }
+    }
+
+    fn read(require_master: bool) -> Package {
+        Builder::read_event()
+            .stream_id("foo")
+            .event_number(EventNumber::First)
+            .require_master(require_master)
+            .build_package(None, None)
+    }
+
+    fn exchange(requests: Vec<Package>, answers: usize) -> Vec<Package> {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let server = Server::new(Follower::default(), &handle);
+
+        let (client_io, server_io) = pipe::duplex();
+        handle.spawn(server.serve(server_io).map_err(|e| panic!("server failed: {}", e)));
+
+        let (sink, stream) = client_io.framed(PackageCodec).split();
+        let sent = sink.send_all(iter_ok::<_, io::Error>(requests));
+        let received = stream.take(answers as u64).collect();
+
+        let (_, received) = core.run(sent.join(received)).unwrap();
+        received
+    }
+
+    #[test]
+    fn answers_with_correlation_ids() {
+        let requests = vec![read(false), read(true), Builder::ping().build_package(None, None)];
+        let ids = requests.iter().map(|x| x.correlation_id).collect::<Vec<_>>();
+
+        let mut received = exchange(requests, 3);
+        received.sort_by_key(|x| ids.iter().position(|id| *id == x.correlation_id).unwrap());
+
+        match received[0].message {
+            RawMessage::ReadEventCompleted(ref x) => assert_eq!(x.result, Some(::raw::client_messages::mod_ReadEventCompleted::ReadEventResult::NotFound)),
+            ref x => panic!("unexpected {:?}", x),
+        }
+        match received[1].message {
+            RawMessage::NotHandled(ref x) => assert_eq!(x.reason, Some(NotHandledReason::NotMaster)),
+            ref x => panic!("unexpected {:?}", x),
+        }
+        assert_eq!(received[2].message, RawMessage::Pong);
+    }
+
+    #[test]
+    fn pushes_unsolicited_packages() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let server = Server::new(Follower::default(), &handle);
+
+        let (client_io, server_io) = pipe::duplex();
+        handle.spawn(server.serve(server_io).map_err(|e| panic!("server failed: {}", e)));
+        let (_sink, stream) = client_io.framed(PackageCodec).split();
+
+        let id = Uuid::new_v4();
+        {
+            let pushers = server.handler().pushers.borrow();
+            pushers[0].push(id, RawMessage::HeartbeatRequest).unwrap();
+        }
+
+        let (pushed, _) = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+        let pushed = pushed.unwrap();
+
+        assert_eq!(pushed.correlation_id, id);
+        assert_eq!(pushed.message, RawMessage::HeartbeatRequest);
+    }
+}
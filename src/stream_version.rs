@@ -2,44 +2,108 @@ use std::convert::TryFrom;
 use {Error, ErrorKind};
 
 /// `StreamVersion` represents the valid values for a stream version which is the same as the
-/// event number of the latest event. As such, values are non-negative integers up to
-/// `i32::max_value`. Negative values of `i32` have special meaning in the protocol, and are
-/// restricted from being used with this type.
+/// event number of the latest event. As such, values are non-negative integers up to and including
+/// `i64::max_value`, which EventStore 4 uses as the version of hard deleted streams (EventStore
+/// 3.9 uses `i32::max_value`, see `ProtocolVersion`). Negative values have special meaning in the
+/// protocol, and are restricted from being used with this type.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub struct StreamVersion(u32);
+pub struct StreamVersion(u64);
 
-impl From<StreamVersion> for u32 {
+impl From<StreamVersion> for u64 {
     fn from(stream_version: StreamVersion) -> Self {
         stream_version.0
     }
 }
 
+impl From<StreamVersion> for i64 {
+    fn from(stream_version: StreamVersion) -> Self {
+        stream_version.0 as i64
+    }
+}
+
 impl From<StreamVersion> for i32 {
+    /// Returns the wire representation of EventStore 3.9. Kept for compatibility, prefer `i64`.
+    /// Versions over `i32::max_value()`, such as the version of hard deleted streams in
+    /// EventStore 4, saturate to `i32::max_value()`, the version of hard deleted streams in 3.9.
+    fn from(stream_version: StreamVersion) -> Self {
+        if stream_version.0 > i32::max_value() as u64 {
+            i32::max_value()
+        } else {
+            stream_version.0 as i32
+        }
+    }
+}
+
+impl From<StreamVersion> for u32 {
+    /// Kept for compatibility, prefer `u64`. Saturates like the conversion into `i32`.
     fn from(stream_version: StreamVersion) -> Self {
-        stream_version.0 as i32
+        i32::from(stream_version) as u32
     }
 }
 
-impl TryFrom<u32> for StreamVersion {
+impl TryFrom<u64> for StreamVersion {
     type Error = Error;
 
-    fn try_from(ver: u32) -> Result<Self, Self::Error> {
-        if ver < i32::max_value() as u32 {
+    fn try_from(ver: u64) -> Result<Self, Self::Error> {
+        if ver <= i64::max_value() as u64 {
             Ok(StreamVersion(ver))
         } else {
-            Err(ErrorKind::InvalidStreamVersion(ver as i32).into())
+            Err(ErrorKind::InvalidStreamVersion(ver as i64).into())
         }
     }
 }
 
-impl TryFrom<i32> for StreamVersion {
+impl TryFrom<i64> for StreamVersion {
     type Error = Error;
 
-    fn try_from(ver: i32) -> Result<Self, Self::Error> {
+    fn try_from(ver: i64) -> Result<Self, Self::Error> {
         if ver >= 0 {
-            Ok(StreamVersion(ver as u32))
+            Ok(StreamVersion(ver as u64))
         } else {
             Err(ErrorKind::InvalidStreamVersion(ver).into())
         }
     }
 }
+
+impl From<u32> for StreamVersion {
+    fn from(ver: u32) -> Self {
+        StreamVersion(ver as u64)
+    }
+}
+
+impl TryFrom<i32> for StreamVersion {
+    type Error = Error;
+
+    fn try_from(ver: i32) -> Result<Self, Self::Error> {
+        StreamVersion::try_from(ver as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::StreamVersion;
+
+    #[test]
+    fn stream_version_accepts_the_deleted_stream_markers() {
+        let v3 = StreamVersion::try_from(i32::max_value()).unwrap();
+        assert_eq!(StreamVersion::from(i32::max_value() as u32), v3);
+        assert_eq!(i64::from(v3), i32::max_value() as i64);
+
+        let v4 = StreamVersion::try_from(i64::max_value()).unwrap();
+        assert_eq!(i64::from(v4), i64::max_value());
+        assert!(StreamVersion::try_from(i64::max_value() as u64 + 1).is_err());
+        assert!(StreamVersion::try_from(-1).is_err());
+    }
+
+    #[test]
+    fn narrowing_conversions_saturate() {
+        let v3 = StreamVersion::try_from(i32::max_value()).unwrap();
+        let v4 = StreamVersion::try_from(i64::max_value()).unwrap();
+
+        assert_eq!(i32::from(StreamVersion::from(5u32)), 5);
+        assert_eq!(u32::from(StreamVersion::from(5u32)), 5);
+        assert_eq!(i32::from(v4), i32::from(v3));
+        assert_eq!(u32::from(v4), i32::max_value() as u32);
+    }
+}
@@ -0,0 +1,289 @@
+//! TLS support for connecting to the secure TCP interface of EventStore, available with the `tls`
+//! feature. The implementation uses `rustls`.
+//!
+//! TLS is enabled by setting `ConnectionSettings::tls`, after which
+//! `EventStoreClient::connect_with_settings` will perform the handshake before sending any
+//! requests. `handshake` can also be used directly on any transport before handing it to
+//! `EventStoreClient::from_transport`.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use eventstore_tcp::ConnectionSettings;
+//! use eventstore_tcp::tls::{TlsSettings, PemSource};
+//!
+//! let mut settings = ConnectionSettings::new("eventstore.internal", 1115);
+//! settings.tls = Some(TlsSettings {
+//!     trust_roots: Some(PemSource::File("/etc/eventstore/ca.pem".into())),
+//!     ..Default::default()
+//! });
+//! ```
+
+use std::io::{self, Read};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use futures::{future, Future};
+use tokio_io::{AsyncRead, AsyncWrite};
+use rustls::{ClientConfig, ClientSession, PrivateKey};
+use rustls::internal::pemfile;
+use tokio_rustls::ClientConfigExt;
+use webpki::DNSNameRef;
+use webpki_roots;
+
+pub use tokio_rustls::TlsStream;
+
+/// Where to read PEM encoded certificates or keys from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PemSource {
+    /// Read from a file when connecting.
+    File(PathBuf),
+    /// Already loaded PEM contents.
+    Bytes(Vec<u8>),
+}
+
+impl PemSource {
+    fn read(&self) -> io::Result<Vec<u8>> {
+        match *self {
+            PemSource::File(ref path) => {
+                let mut buf = Vec::new();
+                File::open(path)?.read_to_end(&mut buf)?;
+                Ok(buf)
+            },
+            PemSource::Bytes(ref bytes) => Ok(bytes.clone()),
+        }
+    }
+}
+
+/// Certificate chain and the private key presented to servers requiring client authentication.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertificate {
+    /// The certificate chain, starting from the client certificate.
+    pub certificates: PemSource,
+    /// PKCS#8 or RSA private key of the client certificate.
+    pub private_key: PemSource,
+}
+
+/// Settings for securing the connection with TLS.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TlsSettings {
+    /// Name the server certificate is verified against. `None` uses the host of the
+    /// `ConnectionSettings`. This must be given when connecting to an IP address as only DNS
+    /// names can be verified.
+    pub server_name: Option<String>,
+    /// Certificates of the trusted certificate authorities. `None` trusts the Mozilla root
+    /// certificates bundled in `webpki-roots`, which is not useful with self-signed certificates.
+    pub trust_roots: Option<PemSource>,
+    /// Client certificate for servers requiring client authentication. Defaults to `None`.
+    pub client_certificate: Option<ClientCertificate>,
+}
+
+impl TlsSettings {
+    /// Builds the `rustls` configuration, reading any files referred to.
+    pub fn client_config(&self) -> io::Result<Arc<ClientConfig>> {
+        let mut config = ClientConfig::new();
+
+        match self.trust_roots {
+            Some(ref pem) => {
+                let pem = pem.read()?;
+                let added = config.root_store.add_pem_file(&mut &pem[..])
+                    .map(|(added, _)| added)
+                    .unwrap_or(0);
+
+                if added == 0 {
+                    return Err(invalid_data("no trust roots found"));
+                }
+            },
+            None => config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS),
+        }
+
+        if let Some(ref client) = self.client_certificate {
+            let certificates = pemfile::certs(&mut &client.certificates.read()?[..])
+                .map_err(|_| invalid_data("invalid client certificate"))?;
+
+            if certificates.is_empty() {
+                return Err(invalid_data("no client certificates found"));
+            }
+
+            let key = private_key(&client.private_key.read()?)?;
+            config.set_single_client_cert(certificates, key);
+        }
+
+        Ok(Arc::new(config))
+    }
+}
+
+/// Performs the TLS handshake over the given transport. The server certificate is verified to be
+/// issued for `TlsSettings::server_name` or `host` when it is not set.
+pub fn handshake<S>(stream: S, settings: &TlsSettings, host: &str) -> Box<dyn Future<Item = TlsStream<S, ClientSession>, Error = io::Error>>
+    where S: AsyncRead + AsyncWrite + 'static
+{
+    let config = match settings.client_config() {
+        Ok(config) => config,
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    let server_name = settings.server_name.as_ref().map(String::as_str).unwrap_or(host);
+
+    let domain = match DNSNameRef::try_from_ascii_str(server_name) {
+        Ok(domain) => domain,
+        Err(_) => {
+            let msg = format!("invalid server name for TLS: {}", server_name);
+            return Box::new(future::err(io::Error::new(io::ErrorKind::InvalidInput, msg)));
+        }
+    };
+
+    Box::new(config.connect_async(domain, stream))
+}
+
+fn private_key(pem: &[u8]) -> io::Result<PrivateKey> {
+    let mut keys = pemfile::pkcs8_private_keys(&mut &pem[..])
+        .map_err(|_| invalid_data("invalid private key"))?;
+
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut &pem[..])
+            .map_err(|_| invalid_data("invalid private key"))?;
+    }
+
+    keys.into_iter().next().ok_or_else(|| invalid_data("no private key found"))
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::{Future, Stream, Sink};
+    use tokio_core::net::TcpListener;
+    use tokio_core::reactor::{Core, Handle};
+    use tokio_io::AsyncRead;
+    use tokio_service::Service;
+    use rustls::{ServerConfig, NoClientAuth, AllowAnyAuthenticatedClient, RootCertStore};
+    use rustls::internal::pemfile;
+    use tokio_rustls::ServerConfigExt;
+
+    use super::{TlsSettings, PemSource, ClientCertificate, private_key};
+    use client::EventStoreClient;
+    use codec::PackageCodec;
+    use package::Package;
+    use raw::RawMessage;
+    use builder::Builder;
+    use settings::ConnectionSettings;
+
+    const CA: &'static [u8] = include_bytes!("../tests/tls/ca.pem");
+    const SERVER_CERT: &'static [u8] = include_bytes!("../tests/tls/server.pem");
+    const SERVER_KEY: &'static [u8] = include_bytes!("../tests/tls/server.key");
+    const CLIENT_CERT: &'static [u8] = include_bytes!("../tests/tls/client.pem");
+    const CLIENT_KEY: &'static [u8] = include_bytes!("../tests/tls/client.key");
+
+    /// Stand-in for a TLS terminating EventStore: answers a single ping on a single connection.
+    fn serve_one_ping(config: ServerConfig, handle: &Handle) -> u16 {
+        let mut config = config;
+        let certs = pemfile::certs(&mut &SERVER_CERT[..]).unwrap();
+        config.set_single_cert(certs, private_key(SERVER_KEY).unwrap());
+        let config = Arc::new(config);
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), handle).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = listener.incoming()
+            .take(1)
+            .for_each(move |(stream, _)| {
+                config.accept_async(stream).and_then(|stream| {
+                    let (sink, stream) = stream.framed(PackageCodec).split();
+
+                    stream.take(1)
+                        .map(|req: Package| Package {
+                            authentication: None,
+                            correlation_id: req.correlation_id,
+                            message: RawMessage::Pong,
+                        })
+                        .forward(sink)
+                        .map(|_| ())
+                })
+            })
+            .or_else(|_| Ok(()));
+
+        handle.spawn(server);
+        port
+    }
+
+    fn settings(port: u16, tls: TlsSettings) -> ConnectionSettings {
+        let mut settings = ConnectionSettings::new("localhost", port);
+        settings.heartbeat_interval = None;
+        settings.max_reconnections = 0;
+        settings.connection_timeout = Duration::from_secs(5);
+        settings.tls = Some(tls);
+        settings
+    }
+
+    fn ping(core: &mut Core, settings: &ConnectionSettings) -> ::std::io::Result<Package> {
+        let handle = core.handle();
+        let job = EventStoreClient::connect_with_settings(settings, &handle)
+            .and_then(|client| client.call(Builder::ping().build_package(None, None)));
+        core.run(job)
+    }
+
+    #[test]
+    fn ping_over_tls() {
+        let mut core = Core::new().unwrap();
+        let port = serve_one_ping(ServerConfig::new(NoClientAuth::new()), &core.handle());
+
+        let tls = TlsSettings {
+            trust_roots: Some(PemSource::Bytes(CA.to_vec())),
+            ..Default::default()
+        };
+
+        let resp = ping(&mut core, &settings(port, tls)).unwrap();
+        assert_eq!(resp.message, RawMessage::Pong);
+    }
+
+    #[test]
+    fn ping_with_client_certificate() {
+        let mut core = Core::new().unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add_pem_file(&mut &CA[..]).unwrap();
+        let port = serve_one_ping(ServerConfig::new(AllowAnyAuthenticatedClient::new(roots)), &core.handle());
+
+        let tls = TlsSettings {
+            trust_roots: Some(PemSource::Bytes(CA.to_vec())),
+            client_certificate: Some(ClientCertificate {
+                certificates: PemSource::Bytes(CLIENT_CERT.to_vec()),
+                private_key: PemSource::Bytes(CLIENT_KEY.to_vec()),
+            }),
+            ..Default::default()
+        };
+
+        let resp = ping(&mut core, &settings(port, tls)).unwrap();
+        assert_eq!(resp.message, RawMessage::Pong);
+    }
+
+    #[test]
+    fn server_name_is_verified() {
+        let mut core = Core::new().unwrap();
+        let port = serve_one_ping(ServerConfig::new(NoClientAuth::new()), &core.handle());
+
+        let tls = TlsSettings {
+            server_name: Some("eventstore.example.com".into()),
+            trust_roots: Some(PemSource::Bytes(CA.to_vec())),
+            ..Default::default()
+        };
+
+        assert!(ping(&mut core, &settings(port, tls)).is_err());
+    }
+
+    #[test]
+    fn untrusted_server_is_rejected() {
+        let mut core = Core::new().unwrap();
+        let port = serve_one_ping(ServerConfig::new(NoClientAuth::new()), &core.handle());
+
+        // the bundled webpki roots do not include the test certificate authority
+        assert!(ping(&mut core, &settings(port, TlsSettings::default())).is_err());
+    }
+}
@@ -0,0 +1,443 @@
+//! Upgrading events written with old schemas while reading.
+//!
+//! An `Upcasters` chain holds transforms registered for an event type, and optionally for a
+//! schema version stored in the JSON metadata of the event under `SCHEMA_VERSION_KEY`. A
+//! transform takes the raw `EventRecord` and returns the records it is replaced with: the same
+//! record with rewritten data or type, several records to split the event, or none to drop it.
+//! The returned records go through the chain again, so upcasters for `OrderPlaced.v1` to
+//! `OrderPlaced.v2` and `OrderPlaced.v2` to `OrderPlaced.v3` compose.
+//!
+//! The chain is applied to pages by `reading::read_stream_pages_upcasted` and
+//! `reading::read_stream_events_upcasted`, by `aggregate::Repository::with_upcasters`, and to
+//! any other stream of events, such as a subscription, with `upcast_stream`. As upcasting
+//! happens before the events are returned, typed decoding only needs to know the latest schema.
+//!
+//! # Example
+//!
+//! ```rust
+//! extern crate eventstore_tcp;
+//!
+//! use eventstore_tcp::EventRecord;
+//! use eventstore_tcp::upcast::Upcasters;
+//!
+//! fn main() {
+//!     let upcasters = Upcasters::new()
+//!         .rename("OrderCreated", "OrderPlaced.v1")
+//!         .map_data("OrderPlaced.v1", "OrderPlaced.v2", |data| {
+//!             // v2 wrapped the order number into an object
+//!             Ok(format!("{{\"order\":{}}}", String::from_utf8(data.to_vec())?).into_bytes())
+//!         });
+//!
+//!     let old = EventRecord {
+//!         event_stream_id: "orders-1".into(),
+//!         event_type: "OrderCreated".into(),
+//!         data: (&b"42"[..]).into(),
+//!         ..EventRecord::default()
+//!     };
+//!
+//!     let upcasted = upcasters.upcast(old).unwrap();
+//!     assert_eq!(upcasted[0].event_type, "OrderPlaced.v2");
+//!     assert_eq!(&upcasted[0].data[..], &b"{\"order\":42}"[..]);
+//! }
+//! ```
+
+use std::fmt;
+use std::error;
+use std::str;
+use std::mem;
+use std::rc::Rc;
+use std::convert::TryFrom;
+
+use futures::{stream, Stream};
+use json;
+
+use adapted::{ReadStreamCompleted, RecordedEvent, ResolvedEvent};
+use raw::client_messages::EventRecord;
+
+/// The key of the schema version in the JSON metadata of an event, used by upcasters registered
+/// with `Upcasters::register_version`.
+pub const SCHEMA_VERSION_KEY: &'static str = "schemaVersion";
+
+/// Upcasting steps allowed for a single event before the upcasters are assumed to loop.
+const MAX_STEPS: usize = 64;
+
+/// The failure of a transform.
+pub type Cause = Box<dyn error::Error + Send + Sync>;
+
+type Transform = Box<dyn Fn(EventRecord<'static>) -> Result<Vec<EventRecord<'static>>, Cause>>;
+
+struct Upcaster {
+    event_type: String,
+    version: Option<u64>,
+    transform: Transform,
+}
+
+/// Upcasting an event failed.
+#[derive(Debug)]
+pub struct UpcastError {
+    /// The stream of the event
+    pub stream_id: String,
+    /// The event number of the event, as on the wire
+    pub event_number: i64,
+    /// The type of the event given to the failed transform
+    pub event_type: String,
+    /// The failure of the transform
+    pub cause: Cause,
+}
+
+impl UpcastError {
+    fn new(record: &EventRecord, cause: Cause) -> Self {
+        UpcastError {
+            stream_id: record.event_stream_id.clone().into_owned(),
+            event_number: record.event_number,
+            event_type: record.event_type.clone().into_owned(),
+            cause: cause,
+        }
+    }
+}
+
+impl fmt::Display for UpcastError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Upcasting event {}@{} of type {:?} failed: {}", self.stream_id, self.event_number, self.event_type, self.cause)
+    }
+}
+
+impl error::Error for UpcastError {
+    fn description(&self) -> &str {
+        "upcasting event failed"
+    }
+
+    fn cause(&self) -> Option<&dyn error::Error> {
+        Some(&*self.cause)
+    }
+}
+
+/// A chain of upcasters, see the module documentation.
+pub struct Upcasters {
+    upcasters: Vec<Upcaster>,
+}
+
+impl Default for Upcasters {
+    fn default() -> Self {
+        Upcasters::new()
+    }
+}
+
+impl Upcasters {
+    /// Creates a chain which returns every event as is.
+    pub fn new() -> Self {
+        Upcasters {
+            upcasters: Vec::new(),
+        }
+    }
+
+    /// Registers a transform for events of the type. The event is replaced with the returned
+    /// records, which are upcasted again.
+    pub fn register<F>(mut self, event_type: &str, transform: F) -> Self
+        where F: Fn(EventRecord<'static>) -> Result<Vec<EventRecord<'static>>, Cause> + 'static
+    {
+        self.upcasters.push(Upcaster {
+            event_type: event_type.to_owned(),
+            version: None,
+            transform: Box::new(transform),
+        });
+        self
+    }
+
+    /// Registers a transform for events of the type with the given schema version in their
+    /// metadata. These are preferred over transforms registered with `register` for the type.
+    pub fn register_version<F>(mut self, event_type: &str, version: u64, transform: F) -> Self
+        where F: Fn(EventRecord<'static>) -> Result<Vec<EventRecord<'static>>, Cause> + 'static
+    {
+        self.upcasters.push(Upcaster {
+            event_type: event_type.to_owned(),
+            version: Some(version),
+            transform: Box::new(transform),
+        });
+        self
+    }
+
+    /// Renames events of the type `from` to `to`.
+    pub fn rename(self, from: &str, to: &str) -> Self {
+        let to = to.to_owned();
+        self.register(from, move |mut record| {
+            record.event_type = to.clone().into();
+            Ok(vec![record])
+        })
+    }
+
+    /// Rewrites the data of events of the type `from` with the function and renames them to
+    /// `to`, which can be the same type.
+    pub fn map_data<F>(self, from: &str, to: &str, f: F) -> Self
+        where F: Fn(&[u8]) -> Result<Vec<u8>, Cause> + 'static
+    {
+        let to = to.to_owned();
+        self.register(from, move |mut record| {
+            record.data = f(&record.data)?.into();
+            record.event_type = to.clone().into();
+            Ok(vec![record])
+        })
+    }
+
+    /// `true` if no upcasters have been registered.
+    pub fn is_empty(&self) -> bool {
+        self.upcasters.is_empty()
+    }
+
+    fn find(&self, record: &EventRecord) -> Option<&Upcaster> {
+        let matching = || self.upcasters.iter().filter(move |u| u.event_type == record.event_type);
+
+        // the metadata is only parsed if there are upcasters for specific versions of the type
+        if matching().any(|u| u.version.is_some()) {
+            let version = schema_version(record);
+            if let Some(upcaster) = matching().find(|u| u.version.is_some() && u.version == version) {
+                return Some(upcaster);
+            }
+        }
+
+        matching().find(|u| u.version.is_none())
+    }
+
+    /// Upcasts the record until no upcaster applies to the resulting records.
+    pub fn upcast(&self, record: EventRecord<'static>) -> Result<Vec<EventRecord<'static>>, UpcastError> {
+        let mut done = Vec::new();
+        let mut pending = vec![(record, 0)];
+
+        while let Some((record, steps)) = pending.pop() {
+            let upcaster = match self.find(&record) {
+                Some(upcaster) => upcaster,
+                None => {
+                    done.push(record);
+                    continue;
+                },
+            };
+
+            if steps == MAX_STEPS {
+                return Err(UpcastError::new(&record, Cause::from("too many upcasting steps, the upcasters might loop")));
+            }
+
+            // the record is moved into the transform, keep its identity for errors
+            let stream_id = record.event_stream_id.clone();
+            let event_number = record.event_number;
+            let event_type = record.event_type.clone();
+            let produced = (upcaster.transform)(record)
+                .map_err(|cause| UpcastError {
+                    stream_id: stream_id.into_owned(),
+                    event_number: event_number,
+                    event_type: event_type.into_owned(),
+                    cause: cause,
+                })?;
+
+            // in reverse so that the first produced record is upcasted first
+            pending.extend(produced.into_iter().rev().map(|record| (record, steps + 1)));
+        }
+
+        Ok(done)
+    }
+
+    /// Upcasts the read event, keeping the link and the position for every resulting event.
+    pub fn upcast_event(&self, event: ResolvedEvent) -> Result<Vec<ResolvedEvent>, UpcastError> {
+        if self.find(&event.event.as_raw()).is_none() {
+            return Ok(vec![event]);
+        }
+
+        let ResolvedEvent { event, link, position } = event;
+        let records = self.upcast(event.as_raw().into_owned())?;
+
+        let upcasted = records.iter().map(|record| {
+            RecordedEvent::try_from(record)
+                .map(|event| ResolvedEvent { event: event, link: link.clone(), position: position })
+                .map_err(|e| UpcastError::new(record, Cause::from(e.to_string())))
+        }).collect();
+
+        upcasted
+    }
+
+    /// Upcasts the read events, see `upcast_event`.
+    pub fn upcast_events(&self, events: Vec<ResolvedEvent>) -> Result<Vec<ResolvedEvent>, UpcastError> {
+        let mut upcasted = Vec::with_capacity(events.len());
+        for event in events {
+            upcasted.extend(self.upcast_event(event)?);
+        }
+        Ok(upcasted)
+    }
+
+    /// Upcasts the events of the page, see `upcast_event`.
+    pub fn upcast_page(&self, mut page: ReadStreamCompleted) -> Result<ReadStreamCompleted, UpcastError> {
+        if self.is_empty() {
+            return Ok(page);
+        }
+
+        let events = mem::replace(&mut page.events, Vec::new());
+        page.events = self.upcast_events(events)?;
+        Ok(page)
+    }
+}
+
+/// Upcasts every event of the stream, such as a subscription, see `Upcasters::upcast_event`.
+pub fn upcast_stream<S>(upcasters: Rc<Upcasters>, events: S) -> Box<dyn Stream<Item = ResolvedEvent, Error = S::Error>>
+    where S: Stream<Item = ResolvedEvent> + 'static,
+          S::Error: From<UpcastError> + 'static,
+{
+    Box::new(events
+        .and_then(move |event| upcasters.upcast_event(event).map_err(S::Error::from))
+        .map(stream::iter_ok)
+        .flatten())
+}
+
+/// The schema version in the JSON metadata of the event, if any.
+pub fn schema_version(record: &EventRecord) -> Option<u64> {
+    record.metadata.as_ref()
+        .and_then(|metadata| str::from_utf8(metadata).ok())
+        .and_then(|metadata| json::parse(metadata).ok())
+        .and_then(|metadata| metadata[SCHEMA_VERSION_KEY].as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::convert::TryFrom;
+    use std::rc::Rc;
+
+    use futures::{Future, Sink, Stream};
+    use tokio_core::reactor::Core;
+    use tokio_io::AsyncRead;
+    use tokio_service::Service;
+    use uuid::Uuid;
+
+    use super::{upcast_stream, Cause, Upcasters};
+    use adapted::ResolvedEvent;
+    use builder::Builder;
+    use codec::PackageCodec;
+    use fixtures::start;
+    use memory::MemoryStore;
+    use package::Package;
+    use pipe;
+    use raw::{self, RawMessage};
+    use raw::client_messages::EventRecord;
+    use reading::ReadError;
+    use server::Server;
+    use ExpectedVersion;
+
+    fn record(event_type: &str, data: &str, metadata: Option<&str>) -> EventRecord<'static> {
+        EventRecord {
+            event_stream_id: "orders-1".into(),
+            event_number: 3,
+            event_id: Cow::Owned(vec![0; 16]),
+            event_type: event_type.to_owned().into(),
+            data: data.as_bytes().to_vec().into(),
+            metadata: metadata.map(|m| m.as_bytes().to_vec().into()),
+            ..EventRecord::default()
+        }
+    }
+
+    fn types(records: &[EventRecord]) -> Vec<String> {
+        records.iter().map(|r| r.event_type.clone().into_owned()).collect()
+    }
+
+    #[test]
+    fn chains_renames_and_splits() {
+        let upcasters = Upcasters::new()
+            .rename("OrderCreated", "OrderPlaced.v1")
+            .map_data("OrderPlaced.v1", "OrderPlaced.v2", |data| Ok([&b"["[..], data, &b"]"[..]].concat()))
+            .register("OrderPlacedAndPaid", |r| {
+                let mut paid = r.clone();
+                paid.event_type = "OrderPaid".into();
+                let mut placed = r;
+                placed.event_type = "OrderCreated".into();
+                Ok(vec![placed, paid])
+            })
+            .register("OrderAudited", |_| Ok(vec![]));
+
+        let upcasted = upcasters.upcast(record("OrderCreated", "1", None)).unwrap();
+        assert_eq!(types(&upcasted), vec!["OrderPlaced.v2"]);
+        assert_eq!(&upcasted[0].data[..], &b"[1]"[..]);
+
+        let split = upcasters.upcast(record("OrderPlacedAndPaid", "2", None)).unwrap();
+        assert_eq!(types(&split), vec!["OrderPlaced.v2", "OrderPaid"]);
+        assert_eq!(split[1].event_number, 3);
+
+        assert!(upcasters.upcast(record("OrderAudited", "{}", None)).unwrap().is_empty());
+        assert_eq!(types(&upcasters.upcast(record("OrderShipped", "{}", None)).unwrap()), vec!["OrderShipped"]);
+    }
+
+    #[test]
+    fn prefers_schema_version() {
+        let upcasters = Upcasters::new()
+            .map_data("OrderPlaced", "OrderPlaced.v2", |_| Ok(b"unversioned".to_vec()))
+            .register_version("OrderPlaced", 1, |mut r| {
+                r.event_type = "OrderPlaced.v2".into();
+                r.data = (&b"v1"[..]).into();
+                Ok(vec![r])
+            });
+
+        let v1 = upcasters.upcast(record("OrderPlaced", "", Some(r#"{"schemaVersion":1}"#))).unwrap();
+        assert_eq!(&v1[0].data[..], &b"v1"[..]);
+
+        let other = upcasters.upcast(record("OrderPlaced", "", Some(r#"{"schemaVersion":2}"#))).unwrap();
+        assert_eq!(&other[0].data[..], &b"unversioned"[..]);
+        assert_eq!(types(&other), vec!["OrderPlaced.v2"]);
+    }
+
+    #[test]
+    fn failures_and_loops() {
+        let upcasters = Upcasters::new()
+            .register("Broken", |_| Err(Cause::from("cannot upgrade")))
+            .rename("Ping", "Pong")
+            .rename("Pong", "Ping");
+
+        let err = upcasters.upcast(record("Broken", "", None)).unwrap_err();
+        assert_eq!(err.event_type, "Broken");
+        assert_eq!(err.cause.to_string(), "cannot upgrade");
+
+        assert!(upcasters.upcast(record("Ping", "", None)).is_err());
+    }
+
+    #[test]
+    fn upcasts_subscription() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let store = MemoryStore::new();
+        let client = start(&core, store.clone());
+
+        let (sub_io, server_io) = pipe::duplex();
+        handle.spawn(Server::new(store, &handle).serve(server_io).map_err(|e| panic!("server failed: {}", e)));
+        let (sink, stream) = sub_io.framed(PackageCodec).split();
+
+        let subscribe = Package {
+            authentication: None,
+            correlation_id: Uuid::new_v4(),
+            message: RawMessage::SubscribeToStream(raw::SubscribeToStream {
+                event_stream_id: "orders-1".into(),
+                resolve_link_tos: false,
+            }),
+        };
+
+        let _sink = core.run(sink.send(subscribe)).unwrap();
+        let (_, stream) = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+
+        let mut builder = Builder::write_events();
+        builder.stream_id("orders-1").expected_version(ExpectedVersion::Any);
+        for ty in &["OrderCreated", "OrderAudited", "OrderShipped"] {
+            builder.new_event().event_type(*ty).data(&b"{}"[..]).done();
+        }
+        core.run(client.call(builder.build_package(None, None))).unwrap();
+
+        let events = stream
+            .map(|package| match package.message {
+                RawMessage::StreamEventAppeared(ref x) => ResolvedEvent::try_from(&x.event).unwrap(),
+                ref x => panic!("unexpected {:?}", x),
+            })
+            .map_err(ReadError::Io);
+
+        let upcasters = Upcasters::new()
+            .rename("OrderCreated", "OrderPlaced")
+            .register("OrderAudited", |_| Ok(vec![]));
+
+        let upcasted = core.run(upcast_stream(Rc::new(upcasters), events).take(2).collect()).unwrap();
+        let types = upcasted.iter().map(|e| e.event.event_type.as_str()).collect::<Vec<_>>();
+        let numbers = upcasted.iter().map(|e| u64::from(e.event.number)).collect::<Vec<_>>();
+        assert_eq!(types, vec!["OrderPlaced", "OrderShipped"]);
+        assert_eq!(numbers, vec![0, 2]);
+    }
+}
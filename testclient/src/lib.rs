@@ -6,13 +6,12 @@ extern crate tokio_service;
 extern crate json;
 extern crate eventstore_tcp;
 
-use std::net::SocketAddr;
 use std::io;
 use std::time::Duration;
 use futures::Future;
 use tokio_core::reactor::Core;
 
-use eventstore_tcp::{EventStoreClient, UsernamePassword};
+use eventstore_tcp::{EventStoreClient, UsernamePassword, ConnectionSettings};
 
 mod ping;
 pub use ping::Ping;
@@ -27,7 +26,7 @@ mod delete;
 pub use delete::Delete;
 
 pub struct Config {
-    pub addr: SocketAddr,
+    pub settings: ConnectionSettings,
     pub credentials: Option<UsernamePassword>,
     pub verbose: bool,
 }
@@ -45,7 +44,7 @@ impl Runner {
 
         c.init();
 
-        let job = EventStoreClient::connect(&self.0.addr, &handle)
+        let job = EventStoreClient::connect_with_settings(&self.0.settings, &handle)
             .and_then(|client| c.execute(&self.0, client));
 
         core.run(job)
@@ -5,9 +5,8 @@ use std::convert::TryFrom;
 use futures::Future;
 use tokio_service::Service;
 use json;
-use eventstore_tcp::{EventStoreClient, Builder, ReadDirection, UsernamePassword, Package, AdaptedMessage, EventNumber, LogPosition, StreamVersion};
-use eventstore_tcp::adapted::{ReadEventError, ReadAllError, ReadAllCompleted, ReadStreamError, ReadStreamCompleted};
-use eventstore_tcp::raw::{EventRecord};
+use eventstore_tcp::{EventStoreClient, Builder, ReadDirection, UsernamePassword, Package, AdaptedMessage, EventNumber, LogPosition, StreamVersion, ContentType};
+use eventstore_tcp::adapted::{ReadEventError, ReadAllError, ReadAllCompleted, ReadStreamError, ReadStreamCompleted, RecordedEvent};
 use {Config, Command};
 
 #[derive(Debug, Clone)]
@@ -53,7 +52,7 @@ impl<'a> From<&'a str> for Position {
                         Position::Log(commit, prepare)
                     },
                     _ => {
-                        let num = x.parse::<u32>().expect("Failed to parse position as u32");
+                        let num = x.parse::<u64>().expect("Failed to parse position as u64");
                         Position::Exact(
                             StreamVersion::try_from(num).expect("Position overflow"))
                     }
@@ -131,10 +130,16 @@ impl From<json::Error> for ByteParsingError {
     fn from(e: json::Error) -> Self { ByteParsingError::Json(e) }
 }
 
-struct JsonWrapper<'a, 'b: 'a>(&'a EventRecord<'b>);
+struct JsonWrapper<'a>(&'a RecordedEvent);
+
+impl<'a> JsonWrapper<'a> {
+    /// JSON payloads are parsed, other payloads are written out as a hex string.
+    fn convert_bytes(content_type: ContentType, bytes: &[u8]) -> Result<json::JsonValue, ByteParsingError> {
+        if !content_type.is_json() {
+            let hex = bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+            return Ok(hex.into());
+        }
 
-impl<'a, 'b: 'a> JsonWrapper<'b, 'a> {
-    fn parse_bytes(bytes: &'a [u8]) -> Result<json::JsonValue, ByteParsingError> {
         str::from_utf8(bytes)
             .map_err(ByteParsingError::from)
             .and_then(|s| if s.len() > 0 { Ok(json::parse(s)?) } else { Ok(json::JsonValue::new_object()) })
@@ -143,12 +148,11 @@ impl<'a, 'b: 'a> JsonWrapper<'b, 'a> {
     fn as_json(&self) -> Result<json::JsonValue, ByteParsingError> {
         let ref event = self.0;
 
-        let (data, metadata) = Self::parse_bytes(&*event.data)
-            .and_then(|data|
-                event.metadata.as_ref()
-                  .map(|x| Self::parse_bytes(x))
-                  .unwrap_or(Ok("".to_string().into()))
-                  .map(move |metadata| (data, metadata)))?;
+        let data = Self::convert_bytes(event.data_content_type, &event.data)?;
+        let metadata = match event.metadata {
+            Some(ref x) => Self::convert_bytes(event.metadata_content_type, x)?,
+            None => "".to_string().into(),
+        };
 
         let mut obj = json::object::Object::new();
         obj.insert("data", data);
@@ -171,7 +175,7 @@ impl<'a> From<ReadAllError<'a>> for ReadFailure<'a> { fn from(e: ReadAllError<'a
 
 impl OutputMode {
 
-    fn format_event<'a, Out: io::Write, ErrOut: io::Write>(&self, verbose: bool, event: EventRecord<'a>, out: &mut Out, err: &mut ErrOut) -> io::Result<()> {
+    fn format_event<Out: io::Write, ErrOut: io::Write>(&self, verbose: bool, event: RecordedEvent, out: &mut Out, err: &mut ErrOut) -> io::Result<()> {
         match *self {
             OutputMode::Debug => {
                 if verbose {
@@ -190,8 +194,8 @@ impl OutputMode {
                         writeln!(
                             err,
                             "Failed to parse event {}@{}: {:?}",
-                            event.event_stream_id,
-                            event.event_number,
+                            event.stream_id,
+                            u64::from(event.number),
                             fail)?
                     }
                 }
@@ -215,7 +219,7 @@ impl OutputMode {
         }
     }
 
-    fn format_events<'a, Out: io::Write, ErrOut: io::Write>(&self, verbose: bool, events: Vec<EventRecord<'a>>, out: &mut Out, err: &mut ErrOut) -> io::Result<()> {
+    fn format_events<Out: io::Write, ErrOut: io::Write>(&self, verbose: bool, events: Vec<RecordedEvent>, out: &mut Out, err: &mut ErrOut) -> io::Result<()> {
         for event in events {
             self.format_event(verbose, event, out, err)?;
         }
@@ -249,7 +253,7 @@ impl OutputMode {
                 self.format_fail(verbose, fail.into(), out, err)
             }
             AdaptedMessage::ReadAllEventsCompleted(_, Err(fail)) => {
-                self.format_fail(verbose, fail.into(), out, err)
+                self.format_fail(verbose, fail.error.into(), out, err)
             }
             x => {
                 if verbose {
@@ -1,21 +1,19 @@
 #!/bin/bash
 #
-# Simple test wrapper which is not optimal as it currently does not download and start own EventStore inmemory instance.
-# Also this is quite light-weight testing :)
+# Simple smoke test of the testclient. Without ES_PORT the tests are run against the in-memory
+# server of `memory-server/` started on an ephemeral port, otherwise against the EventStore at
+# ES_ADDR:ES_PORT. A real EventStore needs to be restarted between the runs.
 #
-# Run this with `bash test_with_inmemory_es.bash` while running an inmemory EventStore on localhost. You'll need to restart the server between the runs.
+# Run this with `bash test_with_inmemory_es.bash [stream_name]`.
 set -eu
 set -o pipefail
 
 readonly DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" && pwd )"
-readonly ES_URL="http://download.geteventstore.com/binaries/EventStore-OSS-Ubuntu-14.04-v3.9.3.tar.gz"
-readonly TMP_DIR="$DIR/target/es"
-readonly ES_PATH="EventStore-OSS-Ubuntu-14.04-v3.9.3"
 ES_PID=""
 readonly self="$(basename "$0")"
 
 readonly addr="${ES_ADDR:-127.0.0.1}"
-readonly port="${ES_PORT:-1113}"
+port="${ES_PORT:-}"
 
 # the events to write
 readonly messages_in=(
@@ -50,13 +48,9 @@ main () {
 		}
 	fi
 
-	# ES is not yet downloaded and executed as:
-	#  1. no https link
-	#  2. I do not know how to use seccomp to jail it for only localhost networking
-
-	#download_es || { echo "Downloading EventStore failed"; return 1; }
-	#"$TMP_DIR/$ES_PATH/run_node.sh" --mem-db & &> eventstore.log
-	#ES_PID=$!
+	if [ -z "$port" ]; then
+		start_server || { echo "Starting memory-server failed" >&2; return 1; }
+	fi
 
 	echo "$self: using stream name $stream_name" >&2
 
@@ -157,27 +151,33 @@ main () {
 
 kill_server () {
 	if [ "$ES_PID" ]; then
-		echo "killing eventstore: $ES_PID" >&2
+		echo "killing memory-server: $ES_PID" >&2
 		kill "$ES_PID"
-		echo "awaiting EventStore to stop..." >&2
-		wait "$ES_PID"
-		echo "done awaiting, exit code: $?" >&2
+		wait "$ES_PID" || true
 		ES_PID=""
 	fi
 }
 
-download_es () {
-	if [ -x "$TMP_DIR/$ES_PATH/run_node.sh" ]; then
-		return 0
-	fi
-	if [ -d "$TMP_DIR/$ES_PATH" ]; then
-		echo "removing previously partially unpacked EventStore under $TMP_DIR" >&2
-		rm -rf "$TMP_DIR"
-	fi
-	mkdir -p "$TMP_DIR"
-	echo "downloading and unpacking EventStore under $TMP_DIR ..." >&2
-	cd "$DIR/target/es"
-	curl --silent http://download.geteventstore.com/binaries/EventStore-OSS-Ubuntu-14.04-v3.9.3.tar.gz | tar zxf -
+start_server () {
+	local addr_file="$DIR/target/memory-server.addr"
+	mkdir -p "$DIR/target"
+	rm -f "$addr_file"
+
+	(cd "$DIR/.." && cargo build --quiet -p memory-server)
+	"$DIR/../target/debug/memory-server" --host "$addr" --port 0 > "$addr_file" &
+	ES_PID=$!
+
+	# the server prints the bound address once it is listening
+	for _ in $(seq 50); do
+		if [ -s "$addr_file" ]; then
+			port="$(sed -e 's/.*://' "$addr_file")"
+			echo "$self: using memory-server at $addr:$port" >&2
+			return 0
+		fi
+		sleep 0.1
+	done
+
+	return 1
 }
 
 trap kill_server EXIT